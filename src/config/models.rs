@@ -29,6 +29,54 @@ pub struct AppConfig {
     /// Performance and resource management settings.
     #[serde(default)]
     pub performance: PerformanceConfig,
+
+    /// Settings for fetching URL-referenced images and documents.
+    #[serde(default)]
+    pub media: MediaConfig,
+
+    /// Local Files API storage settings.
+    #[serde(default)]
+    pub files: FilesConfig,
+
+    /// System prompt injection policy.
+    #[serde(default)]
+    pub prompt: PromptConfig,
+
+    /// Per-model capability overrides, checked before the built-in table.
+    #[serde(default)]
+    pub models: Vec<ModelConfig>,
+
+    /// Claude → Gemini model routing table.
+    #[serde(default)]
+    pub routing: RoutingConfig,
+
+    /// Multi-account credential pool.
+    #[serde(default)]
+    pub pool: PoolConfig,
+
+    /// Additional upstream backends that routes can select by name.
+    #[serde(default)]
+    pub backends: Vec<BackendConfig>,
+
+    /// Per-model concurrency and request rate limits.
+    #[serde(default)]
+    pub limits: LimitsConfig,
+
+    /// Fail-fast behaviour during upstream outages.
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+
+    /// Code Assist quota polling.
+    #[serde(default)]
+    pub quota: QuotaConfig,
+
+    /// Upstream probe behind `/health` and `/readyz`.
+    #[serde(default)]
+    pub health: HealthConfig,
+
+    /// Stall detection for upstream streams.
+    #[serde(default)]
+    pub stream: StreamConfig,
 }
 
 /// Settings for the built-in HTTP server.
@@ -87,10 +135,41 @@ pub struct GeminiConfig {
     #[serde(default = "default_timeout")]
     pub timeout_seconds: u64,
 
-    /// Maximum number of times to retry failed API requests.
+    /// Maximum number of times to retry a generation request (or a stream
+    /// handshake) after a 429, a 5xx or a failed connection, with exponential
+    /// backoff.
+    ///
+    /// Retries multiply with the other layers: dispatch switches once to a
+    /// fallback model, and a stream that fails before content is reissued up
+    /// to `stream.retries_before_content` times. A streaming request may thus
+    /// make up to `(max_retries + 1) × 2 × (retries_before_content + 1)`
+    /// upstream calls (36 with the defaults).
     /// Default: `3`
     #[serde(default = "default_max_retries")]
     pub max_retries: u32,
+
+    /// JSON file persisting model availability across restarts. Empty disables
+    /// persistence.
+    /// Default: `~/.gem2claude/state.json`
+    #[serde(default = "default_state_file")]
+    pub state_file: String,
+
+    /// Google Cloud project to use for Code Assist, e.g. for Workspace
+    /// accounts. Sent to `loadCodeAssist`; `[[pool.accounts]]` may override it.
+    /// Default: `GOOGLE_CLOUD_PROJECT`, else the project Code Assist assigns
+    #[serde(default)]
+    pub project_id: Option<String>,
+
+    /// JSON file caching each account's resolved project. Empty disables the
+    /// cache.
+    /// Default: `~/.gem2claude/projects.json`
+    #[serde(default = "default_project_cache_file")]
+    pub project_cache_file: String,
+
+    /// How long a cached project is reused before resolving it again.
+    /// Default: `86400` (1 day)
+    #[serde(default = "default_project_cache_ttl")]
+    pub project_cache_ttl_seconds: u64,
 }
 
 /// Settings for application logging and output format.
@@ -126,6 +205,606 @@ pub struct PerformanceConfig {
     pub enable_compression: bool,
 }
 
+/// Settings for fetching URL-referenced images and documents.
+///
+/// URL sources are downloaded by the proxy and forwarded to Gemini as inline
+/// data. Host filtering and the private network check guard against SSRF.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaConfig {
+    /// Whether `{"type": "url"}` image and document sources are fetched.
+    /// Default: `true`
+    #[serde(default = "default_true")]
+    pub url_sources_enabled: bool,
+
+    /// Maximum size of a single fetched resource in bytes.
+    /// Default: `20971520` (20MB, Gemini's inline data limit)
+    #[serde(default = "default_max_fetch_bytes")]
+    pub max_fetch_bytes: usize,
+
+    /// Timeout for a single fetch in seconds.
+    /// Default: `30`
+    #[serde(default = "default_fetch_timeout")]
+    pub fetch_timeout_seconds: u64,
+
+    /// Hosts that may be fetched from (`example.com` or `*.example.com`).
+    /// Default: empty (any public host)
+    #[serde(default)]
+    pub allowed_hosts: Vec<String>,
+
+    /// Hosts that are never fetched from, checked before `allowed_hosts`.
+    /// Default: empty
+    #[serde(default)]
+    pub denied_hosts: Vec<String>,
+
+    /// Whether loopback, private and link-local addresses may be fetched.
+    /// Default: `false`
+    #[serde(default)]
+    pub allow_private_networks: bool,
+
+    /// Number of fetched resources kept in the in-memory cache (`0` disables it).
+    /// Default: `32`
+    #[serde(default = "default_media_cache_entries")]
+    pub cache_entries: usize,
+
+    /// Seconds a cached resource is reused before being fetched again.
+    /// Default: `600` (10 minutes)
+    #[serde(default = "default_media_cache_ttl")]
+    pub cache_ttl_seconds: u64,
+}
+
+/// How requests are spread across pooled accounts.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PoolStrategy {
+    /// Prefer the account whose last rate limit is oldest (or that was never limited).
+    #[default]
+    LeastRecentlyRateLimited,
+    /// Rotate through accounts in order.
+    RoundRobin,
+}
+
+/// Settings for spreading requests over several Google accounts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PoolConfig {
+    /// Accounts in the pool. When empty, the single `oauth.credentials_path`
+    /// account is used.
+    /// Default: empty
+    #[serde(default)]
+    pub accounts: Vec<AccountConfig>,
+
+    /// Account selection strategy (`least_recently_rate_limited` or `round_robin`).
+    /// Default: `least_recently_rate_limited`
+    #[serde(default)]
+    pub strategy: PoolStrategy,
+
+    /// Keep each conversation on the account that first served it, preserving
+    /// server-side caching.
+    /// Default: `true`
+    #[serde(default = "default_true")]
+    pub sticky_sessions: bool,
+
+    /// Number of conversations remembered for sticky assignment.
+    /// Default: `1024`
+    #[serde(default = "default_sticky_capacity")]
+    pub sticky_capacity: usize,
+}
+
+/// A single pooled account.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct AccountConfig {
+    /// Account name used in logs, metrics and `/health`.
+    pub name: String,
+
+    /// Path to the account's OAuth credentials file.
+    pub credentials_path: String,
+
+    /// Google Cloud project for this account.
+    /// Default: `gemini.project_id`
+    #[serde(default)]
+    pub project_id: Option<String>,
+}
+
+/// Upstream API a backend talks to.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendKind {
+    /// Code Assist (`cloudcode-pa.googleapis.com/v1internal`) with Gemini CLI
+    /// OAuth credentials. Served by the account pool.
+    #[default]
+    CodeAssist,
+    /// Public Generative Language API with an AI Studio API key.
+    ApiKey,
+    /// Vertex AI with Application Default Credentials or a service account.
+    Vertex,
+}
+
+impl BackendKind {
+    /// Label used in logs and `/health`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BackendKind::CodeAssist => "code_assist",
+            BackendKind::ApiKey => "api_key",
+            BackendKind::Vertex => "vertex",
+        }
+    }
+}
+
+/// A named upstream backend (`[[backends]]`).
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct BackendConfig {
+    /// Name that routes and rules select the backend by.
+    pub name: String,
+
+    /// Upstream API (`api_key` or `vertex`; `code_assist` is the account pool).
+    pub kind: BackendKind,
+
+    /// API key for `api_key` backends. Prefer `api_key_env` to keep the key out
+    /// of the config file.
+    /// Default: unset
+    #[serde(default)]
+    pub api_key: Option<String>,
+
+    /// Environment variable holding the API key when `api_key` is unset.
+    /// Default: `GEMINI_API_KEY`
+    #[serde(default)]
+    pub api_key_env: Option<String>,
+
+    /// API base URL.
+    /// Default: the public endpoint for the kind
+    #[serde(default)]
+    pub base_url: Option<String>,
+
+    /// Google Cloud project for `vertex` backends.
+    /// Default: the credentials' project, then `GOOGLE_CLOUD_PROJECT`
+    #[serde(default)]
+    pub project: Option<String>,
+
+    /// Vertex AI location, e.g. `us-central1` or `global`.
+    /// Default: `global`
+    #[serde(default)]
+    pub location: Option<String>,
+
+    /// Service account key or `gcloud auth application-default login` file for
+    /// `vertex` backends.
+    /// Default: `GOOGLE_APPLICATION_CREDENTIALS`, then gcloud's ADC file
+    #[serde(default)]
+    pub credentials_path: Option<String>,
+}
+
+/// Client-side limits applied per account and Gemini model before calling
+/// upstream. Requests over a limit wait in a FIFO queue.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LimitsConfig {
+    /// Requests allowed in flight at once per account and model (`0` disables).
+    /// Streams hold their slot until they finish.
+    /// Default: `8`
+    #[serde(default = "default_max_concurrent_requests")]
+    pub max_concurrent_requests: usize,
+
+    /// Requests started per minute per account and model (`0` disables).
+    /// Default: `0`
+    #[serde(default)]
+    pub requests_per_minute: u32,
+
+    /// How long a request may wait in the queue before failing with
+    /// `overloaded_error`.
+    /// Default: `120`
+    #[serde(default = "default_queue_timeout")]
+    pub queue_timeout_seconds: u64,
+
+    /// Overrides for specific models.
+    /// Default: empty
+    #[serde(default)]
+    pub models: Vec<ModelLimit>,
+}
+
+/// Limits for one Gemini model, replacing the `[limits]` defaults.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ModelLimit {
+    /// Gemini model name, e.g. `gemini-2.5-pro`.
+    pub model: String,
+
+    /// Requests in flight at once (`0` disables).
+    /// Default: `limits.max_concurrent_requests`
+    #[serde(default)]
+    pub max_concurrent_requests: Option<usize>,
+
+    /// Requests started per minute (`0` disables).
+    /// Default: `limits.requests_per_minute`
+    #[serde(default)]
+    pub requests_per_minute: Option<u32>,
+}
+
+/// Circuit breaker settings, applied per upstream endpoint and model.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// Whether circuits can open.
+    /// Default: `true`
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Consecutive failures (5xx, 529, timeouts, connection errors) that open
+    /// a circuit.
+    /// Default: `5`
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+
+    /// Seconds a circuit stays open before a probe request is let through.
+    /// Default: `30`
+    #[serde(default = "default_open_seconds")]
+    pub open_seconds: u64,
+}
+
+/// Settings for polling remaining Code Assist quota.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotaConfig {
+    /// Seconds between quota queries for each account (`0` disables polling).
+    /// Default: `300`
+    #[serde(default = "default_quota_refresh")]
+    pub refresh_seconds: u64,
+
+    /// Remaining share (0-1) at or below which a model is avoided on that
+    /// account while other candidates remain.
+    /// Default: `0.05`
+    #[serde(default = "default_low_remaining_fraction")]
+    pub low_remaining_fraction: f64,
+}
+
+/// Settings for the cached upstream probe.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthConfig {
+    /// Seconds between upstream probes (`0` disables probing).
+    /// Default: `60`
+    #[serde(default = "default_probe_interval")]
+    pub probe_interval_seconds: u64,
+
+    /// Model whose tokens are counted by the probe. Counting tokens does not
+    /// spend quota.
+    /// Default: `gemini-2.5-flash-lite`
+    #[serde(default = "default_probe_model")]
+    pub probe_model: String,
+}
+
+/// Settings for the upstream stream watchdog.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamConfig {
+    /// Longest wait for the next upstream chunk before the stream counts as
+    /// stalled (`0` disables). Also bounds the wait for the first chunk.
+    /// Default: `120`
+    #[serde(default = "default_stream_idle_timeout")]
+    pub idle_timeout_seconds: u64,
+
+    /// Longest time the model may think before producing visible output
+    /// (`0` disables).
+    /// Default: `240`
+    #[serde(default = "default_max_thinking")]
+    pub max_thinking_seconds: u64,
+
+    /// Times a stream that fails with a retryable error (429, 503, connection
+    /// reset, stall) before any content reached the client is reissued,
+    /// invisibly to the client (`0` disables).
+    /// Default: `2`
+    #[serde(default = "default_retries_before_content")]
+    pub retries_before_content: u32,
+
+    /// Whether such a retry moves on to the next model of the fallback chain
+    /// instead of retrying the model that failed.
+    /// Default: `false`
+    #[serde(default)]
+    pub retry_on_fallback: bool,
+}
+
+/// Settings for the local Files API (`/v1/files`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilesConfig {
+    /// Whether the `/v1/files` endpoints and `file_id` sources are available.
+    /// Default: `true`
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Directory where uploaded files and their metadata are stored.
+    /// Default: `~/.gem2claude/files`
+    #[serde(default = "default_files_storage_dir")]
+    pub storage_dir: String,
+
+    /// Maximum size of a single upload in bytes.
+    /// Default: `20971520` (20MB, Gemini's inline data limit)
+    #[serde(default = "default_max_fetch_bytes")]
+    pub max_file_bytes: usize,
+}
+
+/// Controls the text the proxy adds to the system prompt sent to Gemini.
+///
+/// Nothing is injected unless configured. Injected text may use the variables
+/// `{{date}}`, `{{model}}` (requested model), `{{gemini_model}}` and `{{client}}`.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct PromptConfig {
+    /// Named text snippets, referenced by `prefix` and `suffix`.
+    /// Default: empty
+    #[serde(default)]
+    pub snippets: std::collections::HashMap<String, String>,
+
+    /// Snippet names injected before the client's system prompt, in order.
+    /// Default: empty
+    #[serde(default)]
+    pub prefix: Vec<String>,
+
+    /// Snippet names injected after the client's system prompt, in order.
+    /// Default: empty
+    #[serde(default)]
+    pub suffix: Vec<String>,
+
+    /// Templates applied to matching models and/or clients, in order.
+    /// Default: empty
+    #[serde(default)]
+    pub templates: Vec<PromptTemplate>,
+}
+
+/// A system prompt template scoped to a model and/or client.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct PromptTemplate {
+    /// Requested model name this template applies to (any model if unset).
+    #[serde(default)]
+    pub model: Option<String>,
+
+    /// Client name this template applies to, taken from the `User-Agent`
+    /// product token (e.g. `claude-cli`); any client if unset.
+    #[serde(default)]
+    pub client: Option<String>,
+
+    /// Text injected before the client's system prompt.
+    #[serde(default)]
+    pub prefix: Option<String>,
+
+    /// Text injected after the client's system prompt.
+    #[serde(default)]
+    pub suffix: Option<String>,
+}
+
+/// Runtime model routing, consulted before the built-in model map.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoutingConfig {
+    /// Route unknown model names to `gemini.default_model` instead of rejecting them.
+    /// Default: `true`
+    #[serde(default = "default_true")]
+    pub fallback_to_default_model: bool,
+
+    /// Request rules checked in order before `routes`; the first match wins.
+    /// Default: empty
+    #[serde(default)]
+    pub rules: Vec<RoutingRule>,
+
+    /// Routes checked in order; the first match wins.
+    /// Default: empty
+    #[serde(default)]
+    pub routes: Vec<RouteConfig>,
+
+    /// Ordered Gemini model chains used when a model is unavailable or
+    /// returns 429/503.
+    /// Default: empty
+    #[serde(default)]
+    pub fallbacks: Vec<FallbackChain>,
+}
+
+/// An ordered list of Gemini models, most preferred first.
+///
+/// A request routed to a model in the chain may be served by any model after it.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct FallbackChain {
+    /// Gemini model names, e.g. `["gemini-3-pro-preview", "gemini-2.5-pro"]`.
+    pub models: Vec<String>,
+}
+
+/// Routes requests by their characteristics rather than their model name.
+///
+/// Every set predicate must hold for the rule to match; unset predicates are
+/// ignored.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct RoutingRule {
+    /// Rule name used in logs and metrics.
+    pub name: String,
+
+    /// Gemini model that matching requests are sent to.
+    pub gemini_model: String,
+
+    /// Requested model name or glob pattern, e.g. `claude-*`.
+    #[serde(default)]
+    pub model: Option<String>,
+
+    /// Whether the request defines tools.
+    #[serde(default)]
+    pub has_tools: Option<bool>,
+
+    /// Whether the request enables extended thinking.
+    #[serde(default)]
+    pub thinking: Option<bool>,
+
+    /// Lower bound on the estimated input tokens.
+    #[serde(default)]
+    pub input_tokens_at_least: Option<u32>,
+
+    /// Upper bound on the estimated input tokens.
+    #[serde(default)]
+    pub input_tokens_at_most: Option<u32>,
+
+    /// Lower bound on the request's `max_tokens`.
+    #[serde(default)]
+    pub max_tokens_at_least: Option<u32>,
+
+    /// Upper bound on the request's `max_tokens`.
+    #[serde(default)]
+    pub max_tokens_at_most: Option<u32>,
+
+    /// Matches when the system prompt contains any of these substrings
+    /// (case-insensitive).
+    #[serde(default)]
+    pub system_contains: Vec<String>,
+
+    /// Client name (the `User-Agent` product token, e.g. `claude-cli`).
+    #[serde(default)]
+    pub client: Option<String>,
+
+    /// Backend serving matching requests (a `[[backends]]` name).
+    /// Default: the Code Assist account pool
+    #[serde(default)]
+    pub backend: Option<String>,
+}
+
+/// A single routing rule with optional request overrides.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct RouteConfig {
+    /// Exact model name or glob pattern (`*` and `?`), e.g. `claude-*-haiku-*`.
+    #[serde(default)]
+    pub model: Option<String>,
+
+    /// Regular expression matched against the whole model name (instead of `model`).
+    #[serde(default)]
+    pub regex: Option<String>,
+
+    /// Gemini model that matching requests are sent to.
+    pub gemini_model: String,
+
+    /// Upper bound applied to the request's `max_tokens`.
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+
+    /// Temperature forced on matching requests.
+    #[serde(default)]
+    pub temperature: Option<f32>,
+
+    /// `top_p` forced on matching requests.
+    #[serde(default)]
+    pub top_p: Option<f32>,
+
+    /// `top_k` forced on matching requests.
+    #[serde(default)]
+    pub top_k: Option<u32>,
+
+    /// Thinking budget forced on matching requests that enable thinking.
+    #[serde(default)]
+    pub thinking_budget: Option<u32>,
+
+    /// Backend serving matching requests (a `[[backends]]` name).
+    /// Default: the Code Assist account pool
+    #[serde(default)]
+    pub backend: Option<String>,
+}
+
+/// Capability overrides for Gemini models matching `name`.
+///
+/// Unset fields fall through to later matching entries and finally the
+/// built-in table.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ModelConfig {
+    /// Gemini model name, or a prefix ending in `*` (e.g. `gemini-2.5-flash*`).
+    pub name: String,
+
+    /// Largest `max_tokens` forwarded; larger requests are clamped.
+    #[serde(default)]
+    pub max_output_tokens: Option<u32>,
+
+    /// Input context window in tokens (informational).
+    #[serde(default)]
+    pub context_window: Option<u32>,
+
+    /// Whether `temperature` is forwarded (dropped otherwise).
+    #[serde(default)]
+    pub supports_temperature: Option<bool>,
+
+    /// Whether `top_p` is forwarded (dropped otherwise).
+    #[serde(default)]
+    pub supports_top_p: Option<bool>,
+
+    /// Whether `top_k` is forwarded (dropped otherwise).
+    #[serde(default)]
+    pub supports_top_k: Option<bool>,
+
+    /// Whether tool definitions are forwarded (dropped otherwise).
+    #[serde(default)]
+    pub supports_tools: Option<bool>,
+
+    /// Whether images and documents are forwarded (replaced by a note otherwise).
+    #[serde(default)]
+    pub supports_vision: Option<bool>,
+
+    /// Whether extended thinking is enabled for this model.
+    #[serde(default)]
+    pub supports_thinking: Option<bool>,
+
+    /// Maximum number of stop sequences; extra ones are dropped.
+    #[serde(default)]
+    pub max_stop_sequences: Option<usize>,
+
+    /// How Anthropic thinking budgets are translated for this model.
+    #[serde(default)]
+    pub thinking: Option<ModelThinkingConfig>,
+}
+
+/// Thinking budget translation for one model.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelThinkingConfig {
+    /// Whether the model takes a `thinkingLevel` or a `thinkingBudget`.
+    pub mode: ThinkingMode,
+
+    /// Smallest budget the model accepts; requests are raised to it.
+    /// Default: `0`
+    #[serde(default)]
+    pub min_budget: u32,
+
+    /// Largest budget the model accepts; requests are lowered to it.
+    /// Default: `32768`
+    #[serde(default = "default_max_thinking_budget")]
+    pub max_budget: u32,
+
+    /// Whether budgets pass through (clamped) or are mapped via `buckets`.
+    /// Level mode always uses buckets.
+    /// Default: `passthrough`
+    #[serde(default)]
+    pub mapping: ThinkingMapping,
+
+    /// Buckets matched in ascending `up_to` order; budgets above the last
+    /// bucket use the last one.
+    /// Default: empty
+    #[serde(default)]
+    pub buckets: Vec<ThinkingBucket>,
+}
+
+/// Thinking control exposed by a Gemini model.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ThinkingMode {
+    /// `thinkingLevel` (`LOW`/`MEDIUM`/`HIGH`), used by Gemini 3.x.
+    Level,
+    /// `thinkingBudget` in tokens, used by Gemini 2.5.
+    Budget,
+}
+
+/// How a requested budget is turned into the model's setting.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ThinkingMapping {
+    /// Forward the requested budget, clamped to the model's range.
+    #[default]
+    Passthrough,
+    /// Map the requested budget onto the configured buckets.
+    Buckets,
+}
+
+/// One step of a bucket mapping.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThinkingBucket {
+    /// Largest requested budget (inclusive) that falls in this bucket.
+    pub up_to: u32,
+
+    /// Thinking level sent in level mode.
+    #[serde(default)]
+    pub level: Option<String>,
+
+    /// Thinking budget sent in budget mode.
+    #[serde(default)]
+    pub budget: Option<u32>,
+}
+
 // Default trait implementations linking to custom logic
 
 impl Default for ServerConfig {
@@ -155,6 +834,10 @@ impl Default for GeminiConfig {
             default_model: default_model(),
             timeout_seconds: default_timeout(),
             max_retries: default_max_retries(),
+            state_file: default_state_file(),
+            project_id: None,
+            project_cache_file: default_project_cache_file(),
+            project_cache_ttl_seconds: default_project_cache_ttl(),
         }
     }
 }
@@ -178,6 +861,103 @@ impl Default for PerformanceConfig {
     }
 }
 
+impl Default for MediaConfig {
+    fn default() -> Self {
+        Self {
+            url_sources_enabled: true,
+            max_fetch_bytes: default_max_fetch_bytes(),
+            fetch_timeout_seconds: default_fetch_timeout(),
+            allowed_hosts: Vec::new(),
+            denied_hosts: Vec::new(),
+            allow_private_networks: false,
+            cache_entries: default_media_cache_entries(),
+            cache_ttl_seconds: default_media_cache_ttl(),
+        }
+    }
+}
+
+impl Default for RoutingConfig {
+    fn default() -> Self {
+        Self {
+            fallback_to_default_model: true,
+            rules: Vec::new(),
+            routes: Vec::new(),
+            fallbacks: Vec::new(),
+        }
+    }
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            accounts: Vec::new(),
+            strategy: PoolStrategy::default(),
+            sticky_sessions: true,
+            sticky_capacity: default_sticky_capacity(),
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_requests: default_max_concurrent_requests(),
+            requests_per_minute: 0,
+            queue_timeout_seconds: default_queue_timeout(),
+            models: Vec::new(),
+        }
+    }
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            failure_threshold: default_failure_threshold(),
+            open_seconds: default_open_seconds(),
+        }
+    }
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        Self {
+            refresh_seconds: default_quota_refresh(),
+            low_remaining_fraction: default_low_remaining_fraction(),
+        }
+    }
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            probe_interval_seconds: default_probe_interval(),
+            probe_model: default_probe_model(),
+        }
+    }
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout_seconds: default_stream_idle_timeout(),
+            max_thinking_seconds: default_max_thinking(),
+            retries_before_content: default_retries_before_content(),
+            retry_on_fallback: false,
+        }
+    }
+}
+
+impl Default for FilesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            storage_dir: default_files_storage_dir(),
+            max_file_bytes: default_max_fetch_bytes(),
+        }
+    }
+}
+
 // Helper functions for serde defaults and shared constants
 fn default_host() -> String {
     "127.0.0.1".to_string()
@@ -224,6 +1004,28 @@ fn default_max_retries() -> u32 {
     3
 }
 
+fn default_state_file() -> String {
+    dirs::home_dir()
+        .unwrap_or_else(|| std::path::PathBuf::from("."))
+        .join(".gem2claude")
+        .join("state.json")
+        .to_string_lossy()
+        .to_string()
+}
+
+fn default_project_cache_file() -> String {
+    dirs::home_dir()
+        .unwrap_or_else(|| std::path::PathBuf::from("."))
+        .join(".gem2claude")
+        .join("projects.json")
+        .to_string_lossy()
+        .to_string()
+}
+
+fn default_project_cache_ttl() -> u64 {
+    86400
+}
+
 fn default_log_level() -> String {
     "info".to_string()
 }
@@ -235,3 +1037,80 @@ fn default_log_format() -> String {
 fn default_pool_size() -> usize {
     100
 }
+
+fn default_max_fetch_bytes() -> usize {
+    20 * 1024 * 1024
+}
+
+fn default_fetch_timeout() -> u64 {
+    30
+}
+
+fn default_media_cache_entries() -> usize {
+    32
+}
+
+fn default_media_cache_ttl() -> u64 {
+    600
+}
+
+fn default_files_storage_dir() -> String {
+    dirs::home_dir()
+        .unwrap_or_else(|| std::path::PathBuf::from("."))
+        .join(".gem2claude")
+        .join("files")
+        .to_string_lossy()
+        .to_string()
+}
+
+fn default_sticky_capacity() -> usize {
+    1024
+}
+
+fn default_max_concurrent_requests() -> usize {
+    8
+}
+
+fn default_queue_timeout() -> u64 {
+    120
+}
+
+fn default_failure_threshold() -> u32 {
+    5
+}
+
+fn default_open_seconds() -> u64 {
+    30
+}
+
+fn default_quota_refresh() -> u64 {
+    300
+}
+
+fn default_low_remaining_fraction() -> f64 {
+    0.05
+}
+
+fn default_probe_interval() -> u64 {
+    60
+}
+
+fn default_probe_model() -> String {
+    "gemini-2.5-flash-lite".to_string()
+}
+
+fn default_stream_idle_timeout() -> u64 {
+    120
+}
+
+fn default_max_thinking() -> u64 {
+    240
+}
+
+fn default_retries_before_content() -> u32 {
+    2
+}
+
+fn default_max_thinking_budget() -> u32 {
+    32768
+}
@@ -31,6 +31,10 @@ pub enum ProxyError {
     #[error("Gemini API error: {0}")]
     GeminiApi(String),
 
+    /// Upstream HTTP error response without a more specific variant
+    #[error("Gemini API error: HTTP {0}: {1}")]
+    UpstreamStatus(u16, String),
+
     /// Failed to translate request or response
     #[error("Translation error: {0}")]
     Translation(String),
@@ -39,6 +43,10 @@ pub enum ProxyError {
     #[error("Invalid credentials: {0}")]
     InvalidCredentials(String),
 
+    /// Requested resource does not exist (404)
+    #[error("Not found: {0}")]
+    NotFound(String),
+
     /// OAuth token has expired and could not be refreshed
     #[error("Token expired")]
     TokenExpired,
@@ -84,66 +92,51 @@ pub enum ProxyError {
     Overloaded(String),
 }
 
-// Convert ProxyError to HTTP responses for Axum (matches Claude API error format)
-impl IntoResponse for ProxyError {
-    fn into_response(self) -> Response {
-        let (status, error_type, message) = match self {
+impl ProxyError {
+    /// HTTP status and Claude API error type this error maps to.
+    pub fn status_and_type(&self) -> (StatusCode, &'static str) {
+        match self {
             // 401 - authentication_error
             ProxyError::OAuth(_)
             | ProxyError::InvalidCredentials(_)
             | ProxyError::TokenExpired
-            | ProxyError::OAuthRefresh(_) => (
-                StatusCode::UNAUTHORIZED,
-                "authentication_error",
-                self.to_string(),
-            ),
+            | ProxyError::OAuthRefresh(_) => (StatusCode::UNAUTHORIZED, "authentication_error"),
             // 400 - invalid_request_error
-            ProxyError::InvalidRequest(_) | ProxyError::Translation(_) => (
-                StatusCode::BAD_REQUEST,
-                "invalid_request_error",
-                self.to_string(),
-            ),
+            ProxyError::InvalidRequest(_) | ProxyError::Translation(_) => {
+                (StatusCode::BAD_REQUEST, "invalid_request_error")
+            }
+            // 404 - not_found_error
+            ProxyError::NotFound(_) => (StatusCode::NOT_FOUND, "not_found_error"),
             // 429 - rate_limit_error
-            ProxyError::TooManyRequests(_) => (
-                StatusCode::TOO_MANY_REQUESTS,
-                "rate_limit_error",
-                self.to_string(),
-            ),
+            ProxyError::TooManyRequests(_) => (StatusCode::TOO_MANY_REQUESTS, "rate_limit_error"),
             // 529 - overloaded_error (Gemini API overloaded)
-            ProxyError::Overloaded(_) => (
-                StatusCode::from_u16(529).unwrap(),
-                "overloaded_error",
-                self.to_string(),
-            ),
+            ProxyError::Overloaded(_) => (StatusCode::from_u16(529).unwrap(), "overloaded_error"),
             // 503 - api_error (Service unavailable)
-            ProxyError::ServiceUnavailable(_) => (
-                StatusCode::SERVICE_UNAVAILABLE,
-                "api_error",
-                self.to_string(),
-            ),
+            ProxyError::ServiceUnavailable(_) => (StatusCode::SERVICE_UNAVAILABLE, "api_error"),
             // 500 - api_error (catch-all for internal errors)
-            ProxyError::Config(_) | ProxyError::ConfigParsing(_) | ProxyError::Internal(_) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "api_error",
-                self.to_string(),
-            ),
-            // 502 - api_error (upstream API errors)
-            ProxyError::ProjectResolution(_) | ProxyError::GeminiApi(_) => {
-                (StatusCode::BAD_GATEWAY, "api_error", self.to_string())
+            ProxyError::Config(_) | ProxyError::ConfigParsing(_) | ProxyError::Internal(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, "api_error")
             }
+            // 502 - api_error (upstream API errors)
+            ProxyError::ProjectResolution(_)
+            | ProxyError::GeminiApi(_)
+            | ProxyError::UpstreamStatus(..) => (StatusCode::BAD_GATEWAY, "api_error"),
             // Default - api_error
-            _ => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "api_error",
-                self.to_string(),
-            ),
-        };
+            _ => (StatusCode::INTERNAL_SERVER_ERROR, "api_error"),
+        }
+    }
+}
+
+// Convert ProxyError to HTTP responses for Axum (matches Claude API error format)
+impl IntoResponse for ProxyError {
+    fn into_response(self) -> Response {
+        let (status, error_type) = self.status_and_type();
 
         let body = json!({
             "type": "error",
             "error": {
                 "type": error_type,
-                "message": message,
+                "message": self.to_string(),
             }
         });
 
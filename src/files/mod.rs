@@ -0,0 +1,15 @@
+//! Local implementation of Anthropic's Files API.
+//!
+//! Clients upload large attachments (PDFs, screenshots) once via `/v1/files`
+//! and then reference them from image and document blocks with
+//! `{"type": "file", "file_id": "..."}` sources. Uploads are stored in a
+//! content-addressed directory, so identical attachments share one blob on
+//! disk, and are inlined into the Gemini request at translation time.
+
+// Author: kelexine (https://github.com/kelexine)
+
+pub mod models;
+pub mod store;
+
+pub use models::{FileDeleted, FileList, FileObject, FileRecord};
+pub use store::FileStore;
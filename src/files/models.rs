@@ -0,0 +1,87 @@
+//! Files API data models.
+//!
+//! Defines the wire types returned by the Anthropic-compatible `/v1/files`
+//! endpoints and the record persisted for each uploaded file.
+
+// Author: kelexine (https://github.com/kelexine)
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// File object returned by the Files API.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileObject {
+    /// Unique file identifier (`file_...`).
+    pub id: String,
+    /// Object type (always "file").
+    #[serde(rename = "type")]
+    pub object_type: String,
+    /// Original filename supplied at upload time.
+    pub filename: String,
+    /// MIME type of the file contents.
+    pub mime_type: String,
+    /// Size of the file in bytes.
+    pub size_bytes: u64,
+    /// RFC 3339 timestamp of the upload.
+    pub created_at: String,
+    /// Whether the file can be downloaded (uploaded files cannot).
+    pub downloadable: bool,
+}
+
+/// Paginated response for `GET /v1/files`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileList {
+    /// Files on this page, newest first.
+    pub data: Vec<FileObject>,
+    /// ID of the first file on this page.
+    pub first_id: Option<String>,
+    /// ID of the last file on this page.
+    pub last_id: Option<String>,
+    /// Whether more files exist beyond this page.
+    pub has_more: bool,
+}
+
+/// Response for `DELETE /v1/files/{file_id}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileDeleted {
+    /// ID of the deleted file.
+    pub id: String,
+    /// Object type (always "file_deleted").
+    #[serde(rename = "type")]
+    pub object_type: String,
+}
+
+/// Metadata persisted for every uploaded file.
+///
+/// Several records may point at the same content blob when identical bytes
+/// are uploaded more than once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileRecord {
+    /// Unique file identifier (`file_...`).
+    pub id: String,
+    /// Original filename supplied at upload time.
+    pub filename: String,
+    /// MIME type of the file contents.
+    pub mime_type: String,
+    /// Size of the file in bytes.
+    pub size_bytes: u64,
+    /// Upload time.
+    pub created_at: DateTime<Utc>,
+    /// Hex-encoded SHA-256 of the contents, naming the backing blob.
+    pub sha256: String,
+}
+
+impl FileRecord {
+    /// Converts the stored record into the public API representation.
+    pub fn to_object(&self) -> FileObject {
+        FileObject {
+            id: self.id.clone(),
+            object_type: "file".to_string(),
+            filename: self.filename.clone(),
+            mime_type: self.mime_type.clone(),
+            size_bytes: self.size_bytes,
+            created_at: self.created_at.to_rfc3339(),
+            downloadable: false,
+        }
+    }
+}
@@ -0,0 +1,475 @@
+//! Content-addressed on-disk storage for uploaded files.
+//!
+//! Layout under the configured storage directory:
+//!
+//! ```text
+//! blobs/<sha256>       raw file contents, shared by identical uploads
+//! meta/<file_id>.json  one `FileRecord` per upload
+//! ```
+//!
+//! Metadata is loaded into memory at startup so lookups never touch the disk;
+//! only blob reads (at translation time) and writes (on upload) do.
+
+// Author: kelexine (https://github.com/kelexine)
+
+use super::models::{FileDeleted, FileList, FileRecord};
+use crate::config::FilesConfig;
+use crate::error::{ProxyError, Result};
+use crate::models::anthropic::{
+    media_sources_mut, DocumentSource, ImageSource, MediaSourceMut, Message,
+};
+use crate::utils::paths::expand_home;
+use base64::Engine;
+use parking_lot::RwLock;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+/// Stores uploaded files and resolves `file_id` references.
+pub struct FileStore {
+    /// Directory holding the `blobs` and `meta` subdirectories.
+    root: PathBuf,
+    /// Largest accepted upload in bytes.
+    max_file_bytes: usize,
+    /// In-memory index of file ID -> metadata.
+    index: RwLock<HashMap<String, FileRecord>>,
+    /// Serializes blob creation and removal so deletes never race uploads of
+    /// identical content.
+    blob_lock: tokio::sync::Mutex<()>,
+}
+
+impl FileStore {
+    /// Opens (creating if needed) the storage directory and loads existing metadata.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProxyError::Io` if the storage directories cannot be created or read.
+    pub fn open(config: &FilesConfig) -> Result<Self> {
+        let root = expand_home(&config.storage_dir);
+        std::fs::create_dir_all(root.join("blobs"))?;
+        std::fs::create_dir_all(root.join("meta"))?;
+
+        let mut index = HashMap::new();
+        for entry in std::fs::read_dir(root.join("meta"))? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+
+            let record = std::fs::read_to_string(&path)
+                .map_err(ProxyError::from)
+                .and_then(|json| serde_json::from_str::<FileRecord>(&json).map_err(Into::into));
+
+            match record {
+                Ok(record) if root.join("blobs").join(&record.sha256).exists() => {
+                    index.insert(record.id.clone(), record);
+                }
+                Ok(record) => warn!("Skipping file {}: content blob is missing", record.id),
+                Err(e) => warn!(
+                    "Skipping unreadable file metadata {}: {}",
+                    path.display(),
+                    e
+                ),
+            }
+        }
+
+        info!(
+            "File store ready at {} ({} files)",
+            root.display(),
+            index.len()
+        );
+
+        Ok(Self {
+            root,
+            max_file_bytes: config.max_file_bytes,
+            index: RwLock::new(index),
+            blob_lock: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// Stores an upload and returns its new metadata record.
+    ///
+    /// The MIME type is sniffed from the contents when possible, falling back to
+    /// the type declared by the client.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProxyError::InvalidRequest` for empty or oversized uploads and
+    /// `ProxyError::Io` if the file cannot be written.
+    pub async fn upload(
+        &self,
+        filename: String,
+        declared_type: Option<String>,
+        bytes: &[u8],
+    ) -> Result<FileRecord> {
+        if bytes.is_empty() {
+            return Err(ProxyError::InvalidRequest(
+                "Uploaded file is empty".to_string(),
+            ));
+        }
+        if bytes.len() > self.max_file_bytes {
+            return Err(ProxyError::InvalidRequest(format!(
+                "File size ({} bytes) exceeds the maximum of {} bytes",
+                bytes.len(),
+                self.max_file_bytes
+            )));
+        }
+
+        let declared_type = declared_type
+            .map(|t| t.to_lowercase())
+            .filter(|t| !t.is_empty() && t != "application/octet-stream");
+        let mime_type = crate::vision::translation::detect_mime_type(bytes)
+            .or(declared_type)
+            .unwrap_or_else(|| "application/octet-stream".to_string());
+
+        let record = FileRecord {
+            id: format!("file_{}", uuid::Uuid::new_v4().simple()),
+            filename,
+            mime_type,
+            size_bytes: bytes.len() as u64,
+            created_at: chrono::Utc::now(),
+            sha256: hex::encode(Sha256::digest(bytes)),
+        };
+
+        let _guard = self.blob_lock.lock().await;
+
+        let blob_path = self.blob_path(&record.sha256);
+        if tokio::fs::try_exists(&blob_path).await? {
+            debug!("Reusing existing blob {} for {}", record.sha256, record.id);
+        } else {
+            write_atomic(&blob_path, bytes).await?;
+        }
+
+        write_atomic(&self.meta_path(&record.id), &serde_json::to_vec(&record)?).await?;
+        self.index.write().insert(record.id.clone(), record.clone());
+
+        info!(
+            "Stored file {} ({}, {} bytes)",
+            record.id, record.mime_type, record.size_bytes
+        );
+        Ok(record)
+    }
+
+    /// Returns the metadata for a file ID.
+    pub fn get(&self, file_id: &str) -> Option<FileRecord> {
+        self.index.read().get(file_id).cloned()
+    }
+
+    /// Lists files newest first, paginated by `before_id` / `after_id`.
+    pub fn list(&self, limit: usize, before_id: Option<&str>, after_id: Option<&str>) -> FileList {
+        let mut records: Vec<FileRecord> = self.index.read().values().cloned().collect();
+        records.sort_by(|a, b| {
+            b.created_at
+                .cmp(&a.created_at)
+                .then_with(|| b.id.cmp(&a.id))
+        });
+
+        let position = |id: &str| records.iter().position(|r| r.id == id);
+        let (page, has_more) = if let Some(after) = after_id {
+            let start = position(after).map(|p| p + 1).unwrap_or(records.len());
+            let rest = &records[start..];
+            (&rest[..rest.len().min(limit)], rest.len() > limit)
+        } else if let Some(before) = before_id {
+            let end = position(before).unwrap_or(0);
+            let start = end.saturating_sub(limit);
+            (&records[start..end], start > 0)
+        } else {
+            (&records[..records.len().min(limit)], records.len() > limit)
+        };
+
+        FileList {
+            data: page.iter().map(FileRecord::to_object).collect(),
+            first_id: page.first().map(|r| r.id.clone()),
+            last_id: page.last().map(|r| r.id.clone()),
+            has_more,
+        }
+    }
+
+    /// Deletes a file, removing its blob once no other upload references it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProxyError::NotFound` if the file ID is unknown.
+    pub async fn delete(&self, file_id: &str) -> Result<FileDeleted> {
+        let _guard = self.blob_lock.lock().await;
+
+        let (record, blob_in_use) = {
+            let mut index = self.index.write();
+            let record = index
+                .remove(file_id)
+                .ok_or_else(|| ProxyError::NotFound(format!("File not found: {}", file_id)))?;
+            let in_use = index.values().any(|r| r.sha256 == record.sha256);
+            (record, in_use)
+        };
+
+        remove_if_exists(&self.meta_path(&record.id)).await?;
+        if !blob_in_use {
+            remove_if_exists(&self.blob_path(&record.sha256)).await?;
+        }
+
+        info!("Deleted file {}", record.id);
+        Ok(FileDeleted {
+            id: record.id,
+            object_type: "file_deleted".to_string(),
+        })
+    }
+
+    /// Reads a file's contents as `(mime_type, base64_data)`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProxyError::InvalidRequest` if the file ID is unknown, since this is
+    /// only called for `file_id` references inside a messages request.
+    pub async fn read_base64(&self, file_id: &str) -> Result<(String, String)> {
+        let record = self
+            .get(file_id)
+            .ok_or_else(|| ProxyError::InvalidRequest(format!("File not found: {}", file_id)))?;
+        let bytes = tokio::fs::read(self.blob_path(&record.sha256)).await?;
+
+        Ok((
+            record.mime_type,
+            base64::engine::general_purpose::STANDARD.encode(bytes),
+        ))
+    }
+
+    /// Replaces every `file_id` image and document source in `messages` with inline base64 data,
+    /// including those nested in tool results.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProxyError::InvalidRequest` if any referenced file does not exist.
+    pub async fn resolve_messages(&self, messages: &mut [Message]) -> Result<()> {
+        for source in media_sources_mut(messages) {
+            match source {
+                MediaSourceMut::Image(source) => {
+                    if let ImageSource::File { file_id } = source {
+                        let (mime_type, data) = self.read_base64(file_id).await?;
+                        *source = ImageSource::Base64 {
+                            media_type: Some(mime_type),
+                            data,
+                        };
+                    }
+                }
+                MediaSourceMut::Document(source) => {
+                    if let DocumentSource::File { file_id } = source {
+                        let (mime_type, data) = self.read_base64(file_id).await?;
+                        *source = DocumentSource::Base64 {
+                            media_type: Some(mime_type),
+                            data,
+                        };
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn blob_path(&self, sha256: &str) -> PathBuf {
+        self.root.join("blobs").join(sha256)
+    }
+
+    fn meta_path(&self, file_id: &str) -> PathBuf {
+        self.root.join("meta").join(format!("{}.json", file_id))
+    }
+}
+
+/// Writes a file via a temporary sibling and rename, so readers never see partial data.
+async fn write_atomic(path: &Path, bytes: &[u8]) -> Result<()> {
+    let tmp = path.with_extension(format!("tmp-{}", uuid::Uuid::new_v4().simple()));
+    tokio::fs::write(&tmp, bytes).await?;
+    tokio::fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+/// Removes a file, treating "already gone" as success.
+async fn remove_if_exists(path: &Path) -> Result<()> {
+    match tokio::fs::remove_file(path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::anthropic::{ContentBlock, MessageContent, ToolResultContent};
+
+    fn open_store(dir: &Path) -> FileStore {
+        FileStore::open(&FilesConfig {
+            storage_dir: dir.to_string_lossy().to_string(),
+            ..FilesConfig::default()
+        })
+        .unwrap()
+    }
+
+    fn blob_count(dir: &Path) -> usize {
+        std::fs::read_dir(dir.join("blobs")).unwrap().count()
+    }
+
+    #[tokio::test]
+    async fn test_identical_uploads_share_a_blob() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = open_store(dir.path());
+
+        let a = store
+            .upload("a.txt".to_string(), Some("text/plain".to_string()), b"spec")
+            .await
+            .unwrap();
+        let b = store
+            .upload("b.txt".to_string(), Some("text/plain".to_string()), b"spec")
+            .await
+            .unwrap();
+
+        assert_ne!(a.id, b.id);
+        assert_eq!(a.sha256, b.sha256);
+        assert_eq!(blob_count(dir.path()), 1);
+
+        // The blob survives until the last reference is deleted.
+        store.delete(&a.id).await.unwrap();
+        assert_eq!(blob_count(dir.path()), 1);
+        store.delete(&b.id).await.unwrap();
+        assert_eq!(blob_count(dir.path()), 0);
+        assert!(store.delete(&b.id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_files_survive_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let id = {
+            let store = open_store(dir.path());
+            store
+                .upload("spec.pdf".to_string(), None, b"%PDF-1.7\n%fake pdf body")
+                .await
+                .unwrap()
+                .id
+        };
+
+        let reopened = open_store(dir.path());
+        let record = reopened.get(&id).unwrap();
+        assert_eq!(record.mime_type, "application/pdf");
+        assert_eq!(reopened.list(20, None, None).data.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_list_pagination() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = open_store(dir.path());
+        for i in 0..3 {
+            store
+                .upload(format!("{}.txt", i), None, format!("file {}", i).as_bytes())
+                .await
+                .unwrap();
+        }
+
+        let first = store.list(2, None, None);
+        assert_eq!(first.data.len(), 2);
+        assert!(first.has_more);
+
+        let second = store.list(2, None, first.last_id.as_deref());
+        assert_eq!(second.data.len(), 1);
+        assert!(!second.has_more);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_file_references() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = open_store(dir.path());
+        let record = store
+            .upload(
+                "notes.txt".to_string(),
+                Some("text/plain".to_string()),
+                b"hello",
+            )
+            .await
+            .unwrap();
+
+        let mut messages = vec![Message {
+            role: "user".to_string(),
+            content: MessageContent::Blocks(vec![ContentBlock::Document {
+                source: DocumentSource::File {
+                    file_id: record.id.clone(),
+                },
+                title: None,
+                context: None,
+                cache_control: None,
+            }]),
+        }];
+
+        store.resolve_messages(&mut messages).await.unwrap();
+
+        let MessageContent::Blocks(blocks) = &messages[0].content else {
+            panic!("Expected block content");
+        };
+        match &blocks[0] {
+            ContentBlock::Document {
+                source: DocumentSource::Base64 { media_type, data },
+                ..
+            } => {
+                assert_eq!(media_type.as_deref(), Some("text/plain"));
+                assert_eq!(data, "aGVsbG8=");
+            }
+            other => panic!("Expected resolved base64 document, got {:?}", other),
+        }
+
+        let mut missing = vec![Message {
+            role: "user".to_string(),
+            content: MessageContent::Blocks(vec![ContentBlock::Image {
+                source: ImageSource::File {
+                    file_id: "file_missing".to_string(),
+                },
+                cache_control: None,
+            }]),
+        }];
+        assert!(store.resolve_messages(&mut missing).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_file_references_in_tool_results() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = open_store(dir.path());
+        let record = store
+            .upload(
+                "chart.png".to_string(),
+                None,
+                b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR",
+            )
+            .await
+            .unwrap();
+
+        let mut messages = vec![Message {
+            role: "user".to_string(),
+            content: MessageContent::Blocks(vec![ContentBlock::ToolResult {
+                tool_use_id: "toolu_1".to_string(),
+                content: ToolResultContent::Blocks(vec![ContentBlock::Image {
+                    source: ImageSource::File {
+                        file_id: record.id.clone(),
+                    },
+                    cache_control: None,
+                }]),
+                is_error: None,
+            }]),
+        }];
+
+        store.resolve_messages(&mut messages).await.unwrap();
+
+        let MessageContent::Blocks(blocks) = &messages[0].content else {
+            panic!("Expected block content");
+        };
+        let ContentBlock::ToolResult {
+            content: ToolResultContent::Blocks(nested),
+            ..
+        } = &blocks[0]
+        else {
+            panic!("Expected tool result, got {:?}", blocks[0]);
+        };
+        assert!(matches!(
+            &nested[0],
+            ContentBlock::Image {
+                source: ImageSource::Base64 { media_type: Some(media_type), .. },
+                ..
+            } if media_type == "image/png"
+        ));
+    }
+}
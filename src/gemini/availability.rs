@@ -4,23 +4,58 @@
 //! Gemini models. It categorizes model health based on recent API responses,
 //! allowing the proxy to provide early failure feedback to clients and potentially
 //! handle intelligent retries or fallbacks.
+//!
+//! Unhealthy states expire: a `StickyRetry` lasts for the upstream `RetryInfo`
+//! delay, and a `Terminal` quota mark lasts until the quota resets (from the
+//! error details, or the next midnight Pacific time). An expired `Terminal`
+//! model moves to `HalfOpen` and lets a single probe request through.
+//!
+//! With a state file configured, unexpired states are written to disk on every
+//! change and restored on startup, so a restart doesn't forget an exhausted quota.
+//! Inside the runtime the write happens on the blocking pool, off the request path.
 
 // Author: kelexine (https://github.com/kelexine)
 
+use crate::utils::clock::{Clock, SystemClock};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc, Weekday};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
-use tracing::{debug, warn};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use tracing::{debug, info, warn};
+
+/// How long a rate-limited model stays in `StickyRetry` without a `RetryInfo` hint.
+const DEFAULT_STICKY_RETRY_SECS: i64 = 30;
+
+/// How long a half-open probe may run before another request may probe.
+const PROBE_TIMEOUT_SECS: i64 = 120;
 
 /// Represents the current health and availability status of a specific model.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
 pub enum AvailabilityStatus {
     /// Model is behaving as expected and responding to requests.
     Healthy,
     /// Model returned a transient error (e.g., 429) and is marked for a single retry.
-    /// `consumed` tracks if the retry has already been attempted in the current logical turn.
-    StickyRetry { reason: String, consumed: bool },
-    /// Model is considered unavailable (e.g., 403 No Subscription or persistent 429).
-    Terminal { reason: String },
+    /// `consumed` tracks if the retry has already been attempted before `until`.
+    StickyRetry {
+        reason: String,
+        consumed: bool,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    },
+    /// Model is considered unavailable until `until` (e.g., daily quota exhausted).
+    Terminal {
+        reason: String,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    },
+    /// A `Terminal` state expired and a probe request has been let through.
+    HalfOpen {
+        reason: String,
+        since: DateTime<Utc>,
+    },
 }
 
 impl AvailabilityStatus {
@@ -30,10 +65,29 @@ impl AvailabilityStatus {
             AvailabilityStatus::Healthy => "healthy",
             AvailabilityStatus::StickyRetry { .. } => "sticky_retry",
             AvailabilityStatus::Terminal { .. } => "terminal",
+            AvailabilityStatus::HalfOpen { .. } => "half_open",
+        }
+    }
+
+    /// Whether the state still matters at `now`. Expired states (and an
+    /// interrupted probe) are equivalent to healthy after a restart.
+    fn is_live(&self, now: DateTime<Utc>) -> bool {
+        match self {
+            AvailabilityStatus::StickyRetry { until, .. }
+            | AvailabilityStatus::Terminal { until, .. } => *until > now,
+            AvailabilityStatus::Healthy | AvailabilityStatus::HalfOpen { .. } => false,
         }
     }
 }
 
+/// On-disk snapshot of the availability service.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PersistedState {
+    /// Unhealthy models and their states.
+    #[serde(default)]
+    models: HashMap<String, AvailabilityStatus>,
+}
+
 /// Service that maintains a global view of model health across all requests.
 ///
 /// It uses an `Arc<RwLock<HashMap>>` to provide thread-safe access to health states,
@@ -42,15 +96,72 @@ impl AvailabilityStatus {
 pub struct ModelAvailabilityService {
     /// Thread-safe map of Model Identifier -> Current Availability Status.
     health: Arc<RwLock<HashMap<String, AvailabilityStatus>>>,
+    /// Time source for state expiry.
+    clock: Arc<dyn Clock>,
+    /// Pool account whose models this service tracks (metrics label).
+    account: String,
+    /// File that state changes are persisted to, if any.
+    state_file: Option<PathBuf>,
+    /// Sequence number of the latest snapshot taken for the state file.
+    persist_seq: AtomicU64,
+    /// Sequence number of the last snapshot written. Also serializes writes,
+    /// so an older snapshot never lands last.
+    persisted_seq: Arc<Mutex<u64>>,
 }
 
 impl ModelAvailabilityService {
     /// Creates a new, empty model availability service.
     pub fn new() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+
+    /// Creates a new, empty model availability service reading time from `clock`.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
         Self {
             health: Arc::new(RwLock::new(HashMap::new())),
+            clock,
+            account: crate::gemini::pool::DEFAULT_ACCOUNT.to_string(),
+            state_file: None,
+            persist_seq: AtomicU64::new(0),
+            persisted_seq: Arc::new(Mutex::new(0)),
         }
     }
+
+    /// Labels this service's metrics with a pool account name.
+    pub fn with_account(mut self, account: &str) -> Self {
+        self.account = account.to_string();
+        self
+    }
+
+    /// Restores state from `path` (discarding expired entries) and persists
+    /// every later change there.
+    pub fn with_state_file(mut self, path: PathBuf) -> Self {
+        match std::fs::read_to_string(&path) {
+            Ok(text) => match serde_json::from_str::<PersistedState>(&text) {
+                Ok(state) => {
+                    let now = self.clock.now();
+                    let mut health = self.health.write().unwrap();
+                    for (model, status) in state.models {
+                        if status.is_live(now) {
+                            self.record_metrics(&model, status.as_str());
+                            health.insert(model, status);
+                        }
+                    }
+                    info!(
+                        "Restored availability state for {} model(s) from {}",
+                        health.len(),
+                        path.display()
+                    );
+                }
+                Err(e) => warn!("Ignoring unreadable state file {}: {}", path.display(), e),
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to read state file {}: {}", path.display(), e),
+        }
+
+        self.state_file = Some(path);
+        self
+    }
 }
 
 impl Default for ModelAvailabilityService {
@@ -67,29 +178,46 @@ impl ModelAvailabilityService {
         let mut health = self.health.write().unwrap();
         // Only mark as healthy if we already have a record (optimization)
         // or if it was previously unhealthy.
-        if health.contains_key(model) {
+        if let Some(status) = health.get_mut(model) {
+            if *status == AvailabilityStatus::Healthy {
+                return;
+            }
             debug!("Marking model {} as HEALTHY", model);
-            health.insert(model.to_string(), AvailabilityStatus::Healthy);
+            *status = AvailabilityStatus::Healthy;
             self.record_metrics(model, "healthy");
+            drop(health);
+            self.persist();
         }
     }
 
     /// Marks a model as having a terminal error (e.g., Quota Exhausted / No Subscription).
     ///
-    /// Terminal models will be blocked from receiving further traffic until the service
-    /// is restarted or they are manually marked healthy.
-    pub fn mark_terminal(&self, model: &str, reason: String) {
-        warn!("Marking model {} as TERMINAL: {}", model, reason);
+    /// Terminal models are blocked from receiving traffic until `until`, after
+    /// which a single probe request is let through.
+    pub fn mark_terminal(&self, model: &str, reason: String, until: DateTime<Utc>) {
+        warn!(
+            "Marking model {} as TERMINAL until {}: {}",
+            model, until, reason
+        );
         let mut health = self.health.write().unwrap();
-        health.insert(model.to_string(), AvailabilityStatus::Terminal { reason });
+        health.insert(
+            model.to_string(),
+            AvailabilityStatus::Terminal {
+                reason,
+                since: self.clock.now(),
+                until,
+            },
+        );
         self.record_metrics(model, "terminal");
+        drop(health);
+        self.persist();
     }
 
     /// Marks a model for a "sticky" retry due to a transient failure.
     ///
-    /// This state allows for one immediate retry attempt. Terminal models cannot
-    /// be downgraded back to a retry state.
-    pub fn mark_retry_once(&self, model: &str, reason: String) {
+    /// This state allows for one retry attempt before `until`. Terminal models
+    /// cannot be downgraded back to a retry state.
+    pub fn mark_retry_once(&self, model: &str, reason: String, until: DateTime<Utc>) {
         let mut health = self.health.write().unwrap();
 
         // Terminal errors are final; do not downgrade to sticky retry.
@@ -97,32 +225,477 @@ impl ModelAvailabilityService {
             return;
         }
 
-        debug!("Marking model {} as STICKY_RETRY: {}", model, reason);
+        debug!(
+            "Marking model {} as STICKY_RETRY until {}: {}",
+            model, until, reason
+        );
         health.insert(
             model.to_string(),
             AvailabilityStatus::StickyRetry {
                 reason,
                 consumed: false,
+                since: self.clock.now(),
+                until,
             },
         );
         self.record_metrics(model, "sticky_retry");
+        drop(health);
+        self.persist();
+    }
+
+    /// Updates a model's health from a failed upstream response.
+    ///
+    /// A 429 mentioning the daily quota marks the model terminal until the quota
+    /// resets; any other 429 marks it for a sticky retry until the `RetryInfo`
+    /// delay passes.
+    pub fn record_failure(&self, model: &str, status: u16, body: &str) {
+        if status != 429 {
+            return;
+        }
+
+        let now = self.clock.now();
+        if body.contains("Daily") {
+            let until = quota_reset_time(body, now).unwrap_or_else(|| next_pacific_midnight(now));
+            self.mark_terminal(model, body.to_string(), until);
+        } else {
+            let delay = crate::utils::retry::parse_retry_delay(body)
+                .and_then(|delay| Duration::from_std(delay).ok())
+                .unwrap_or_else(|| Duration::seconds(DEFAULT_STICKY_RETRY_SECS));
+            self.mark_retry_once(model, body.to_string(), now + delay);
+        }
+    }
+
+    /// Returns the model's current status, treating an expired sticky retry as
+    /// healthy. An expired `Terminal` state stays until a request probes it.
+    pub fn status(&self, model: &str) -> AvailabilityStatus {
+        let now = self.clock.now();
+        match self.health.read().unwrap().get(model) {
+            Some(AvailabilityStatus::StickyRetry { until, .. }) if *until <= now => {
+                AvailabilityStatus::Healthy
+            }
+            Some(status) => status.clone(),
+            None => AvailabilityStatus::Healthy,
+        }
+    }
+
+    /// Returns every model not currently healthy, sorted by name.
+    pub fn unhealthy_models(&self) -> Vec<(String, AvailabilityStatus)> {
+        let names: Vec<String> = self.health.read().unwrap().keys().cloned().collect();
+        let mut models: Vec<_> = names
+            .into_iter()
+            .map(|model| {
+                let status = self.status(&model);
+                (model, status)
+            })
+            .filter(|(_, status)| *status != AvailabilityStatus::Healthy)
+            .collect();
+        models.sort_by(|a, b| a.0.cmp(&b.0));
+        models
     }
 
     /// Checks if a model is currently eligible to receive traffic.
     ///
-    /// Returns `false` ONLY for models in the `Terminal` state.
-    /// `Healthy` and `StickyRetry` models are considered available.
+    /// Unlike [`Self::try_acquire`], this does not consume a sticky retry or
+    /// start a half-open probe.
     pub fn is_available(&self, model: &str) -> bool {
+        let now = self.clock.now();
         let health = self.health.read().unwrap();
-        !matches!(health.get(model), Some(AvailabilityStatus::Terminal { .. }))
+        match health.get(model) {
+            None | Some(AvailabilityStatus::Healthy) => true,
+            Some(AvailabilityStatus::StickyRetry {
+                consumed, until, ..
+            }) => !consumed || *until <= now,
+            Some(AvailabilityStatus::Terminal { until, .. }) => *until <= now,
+            Some(AvailabilityStatus::HalfOpen { since, .. }) => {
+                *since + Duration::seconds(PROBE_TIMEOUT_SECS) <= now
+            }
+        }
+    }
+
+    /// Claims a request slot on a model, returning `false` if it should be skipped.
+    ///
+    /// Consumes the single retry of a `StickyRetry` model, and turns an expired
+    /// `Terminal` model into a `HalfOpen` probe.
+    pub fn try_acquire(&self, model: &str) -> bool {
+        let now = self.clock.now();
+        let mut health = self.health.write().unwrap();
+        let Some(status) = health.get_mut(model) else {
+            return true;
+        };
+
+        let before = status.clone();
+        let acquired = match status {
+            AvailabilityStatus::Healthy => true,
+            AvailabilityStatus::StickyRetry { until, .. } if *until <= now => {
+                debug!("Sticky retry for model {} expired", model);
+                *status = AvailabilityStatus::Healthy;
+                self.record_metrics(model, "healthy");
+                true
+            }
+            AvailabilityStatus::StickyRetry { consumed, .. } => !std::mem::replace(consumed, true),
+            AvailabilityStatus::Terminal { reason, until, .. } if *until <= now => {
+                info!("Model {} quota window passed; probing (half-open)", model);
+                *status = AvailabilityStatus::HalfOpen {
+                    reason: std::mem::take(reason),
+                    since: now,
+                };
+                self.record_metrics(model, "half_open");
+                true
+            }
+            AvailabilityStatus::Terminal { .. } => false,
+            AvailabilityStatus::HalfOpen { since, .. } => {
+                if *since + Duration::seconds(PROBE_TIMEOUT_SECS) <= now {
+                    info!(
+                        "Half-open probe for model {} timed out; probing again",
+                        model
+                    );
+                    *since = now;
+                    true
+                } else {
+                    false
+                }
+            }
+        };
+
+        let changed = *status != before;
+        drop(health);
+        if changed {
+            self.persist();
+        }
+        acquired
+    }
+
+    /// Writes the live states to the state file, if one is configured.
+    ///
+    /// The snapshot is taken immediately; within a Tokio runtime the file is
+    /// written on the blocking pool so request handlers never wait on disk.
+    fn persist(&self) {
+        let Some(path) = &self.state_file else {
+            return;
+        };
+
+        let now = self.clock.now();
+        let seq = self.persist_seq.fetch_add(1, Ordering::SeqCst) + 1;
+        let state = PersistedState {
+            models: self
+                .health
+                .read()
+                .unwrap()
+                .iter()
+                .filter(|(_, status)| status.is_live(now))
+                .map(|(model, status)| (model.clone(), status.clone()))
+                .collect(),
+        };
+
+        let path = path.clone();
+        let persisted_seq = self.persisted_seq.clone();
+        let write = move || {
+            let mut persisted_seq = persisted_seq.lock().unwrap();
+            // A newer snapshot was already written
+            if *persisted_seq > seq {
+                return;
+            }
+            *persisted_seq = seq;
+            if let Err(e) = write_state_file(&path, &state) {
+                warn!(
+                    "Failed to persist availability state to {}: {}",
+                    path.display(),
+                    e
+                );
+            }
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => drop(handle.spawn_blocking(write)),
+            Err(_) => write(),
+        }
     }
 
     /// Private helper to update Prometheus metrics for model health changes.
     fn record_metrics(&self, model: &str, status: &str) {
         crate::metrics::record_model_health(
+            &self.account,
             model,
             status,
-            &["healthy", "sticky_retry", "terminal"],
+            &["healthy", "sticky_retry", "terminal", "half_open"],
+        );
+    }
+}
+
+/// Writes the state file via a temporary sibling and rename.
+fn write_state_file(path: &Path, state: &PersistedState) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(state)?)?;
+    std::fs::rename(&tmp, path)
+}
+
+/// Extracts the quota reset instant from a Google RPC error body.
+///
+/// Reads `quotaResetTimeStamp` (RFC 3339) or `quotaResetDelay` (e.g. `"3h2m1.5s"`)
+/// from the `ErrorInfo` metadata.
+fn quota_reset_time(body: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let value: serde_json::Value = serde_json::from_str(body).ok()?;
+
+    if let Some(timestamp) = find_string(&value, "quotaResetTimeStamp") {
+        if let Ok(at) = DateTime::parse_from_rfc3339(timestamp) {
+            return Some(at.with_timezone(&Utc));
+        }
+    }
+    find_string(&value, "quotaResetDelay")
+        .and_then(parse_go_duration)
+        .map(|delay| now + delay)
+}
+
+/// Depth-first search for a string field named `key`.
+fn find_string<'a>(value: &'a serde_json::Value, key: &str) -> Option<&'a str> {
+    match value {
+        serde_json::Value::Object(map) => map
+            .get(key)
+            .and_then(|v| v.as_str())
+            .or_else(|| map.values().find_map(|v| find_string(v, key))),
+        serde_json::Value::Array(items) => items.iter().find_map(|v| find_string(v, key)),
+        _ => None,
+    }
+}
+
+/// Parses a Go-style duration such as `"1h2m3.5s"` or `"250ms"`.
+fn parse_go_duration(text: &str) -> Option<Duration> {
+    let mut total_ms = 0.0;
+    let mut rest = text.trim();
+    if rest.is_empty() {
+        return None;
+    }
+
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let number: f64 = rest[..split].parse().ok()?;
+        rest = &rest[split..];
+
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let unit_ms = match &rest[..unit_len] {
+            "h" => 3_600_000.0,
+            "m" => 60_000.0,
+            "s" => 1_000.0,
+            "ms" => 1.0,
+            _ => return None,
+        };
+        rest = &rest[unit_len..];
+        total_ms += number * unit_ms;
+    }
+
+    Some(Duration::milliseconds(total_ms as i64))
+}
+
+/// Returns the next midnight in US Pacific time, when Gemini daily quotas reset.
+pub fn next_pacific_midnight(now: DateTime<Utc>) -> DateTime<Utc> {
+    let local = now + Duration::hours(pacific_offset_hours(now));
+    let midnight = local
+        .date_naive()
+        .succ_opt()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .expect("valid calendar date")
+        .and_utc();
+
+    // DST switches at 02:00, never at midnight, so the offset in effect at the
+    // standard-time reading of midnight is the right one.
+    let offset = pacific_offset_hours(midnight + Duration::hours(8));
+    midnight - Duration::hours(offset)
+}
+
+/// UTC offset of US Pacific time at `at` (-7 during daylight saving, else -8).
+fn pacific_offset_hours(at: DateTime<Utc>) -> i64 {
+    let sunday = |month, n| {
+        NaiveDate::from_weekday_of_month_opt(at.year(), month, Weekday::Sun, n)
+            .expect("month has enough Sundays")
+    };
+    // 02:00 PST on the second Sunday of March to 02:00 PDT on the first Sunday of November
+    let dst_start = sunday(3, 2).and_hms_opt(10, 0, 0).unwrap().and_utc();
+    let dst_end = sunday(11, 1).and_hms_opt(9, 0, 0).unwrap().and_utc();
+
+    if at >= dst_start && at < dst_end {
+        -7
+    } else {
+        -8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::clock::MockClock;
+
+    fn at(rfc3339: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(rfc3339)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn service(now: &str) -> (Arc<MockClock>, ModelAvailabilityService) {
+        let clock = Arc::new(MockClock::new(at(now)));
+        let service = ModelAvailabilityService::with_clock(clock.clone());
+        (clock, service)
+    }
+
+    #[test]
+    fn test_record_failure_classifies_429() {
+        let (_, service) = service("2026-01-15T20:00:00Z");
+
+        service.record_failure("gemini-2.5-pro", 429, "Resource exhausted");
+        assert!(service.is_available("gemini-2.5-pro"));
+
+        service.record_failure("gemini-3-pro-preview", 429, "Daily quota exceeded");
+        assert!(!service.is_available("gemini-3-pro-preview"));
+
+        // Server errors don't change health
+        service.record_failure("gemini-2.5-flash", 503, "Unavailable");
+        assert!(service.is_available("gemini-2.5-flash"));
+    }
+
+    #[test]
+    fn test_sticky_retry_is_consumed_and_expires() {
+        let (clock, service) = service("2026-01-15T20:00:00Z");
+        let body = r#"{"error": {"code": 429, "details": [
+            {"@type": "type.googleapis.com/google.rpc.RetryInfo", "retryDelay": "10s"}
+        ]}}"#;
+        service.record_failure("gemini-2.5-pro", 429, body);
+
+        assert!(service.try_acquire("gemini-2.5-pro"));
+        assert!(!service.try_acquire("gemini-2.5-pro"));
+        assert!(!service.is_available("gemini-2.5-pro"));
+
+        clock.advance(Duration::seconds(11));
+        assert!(service.is_available("gemini-2.5-pro"));
+        assert!(service.try_acquire("gemini-2.5-pro"));
+        assert_eq!(
+            service.status("gemini-2.5-pro"),
+            AvailabilityStatus::Healthy
+        );
+    }
+
+    #[test]
+    fn test_terminal_recovers_at_pacific_midnight_with_half_open_probe() {
+        // 12:00 PST
+        let (clock, service) = service("2026-01-15T20:00:00Z");
+        service.record_failure("gemini-3-pro-preview", 429, "Daily quota exceeded");
+
+        match service.status("gemini-3-pro-preview") {
+            AvailabilityStatus::Terminal { until, .. } => {
+                assert_eq!(until, at("2026-01-16T08:00:00Z"))
+            }
+            other => panic!("Expected terminal, got {:?}", other),
+        }
+        assert!(!service.try_acquire("gemini-3-pro-preview"));
+
+        clock.advance(Duration::hours(12));
+        assert!(service.try_acquire("gemini-3-pro-preview"));
+        // Only one probe at a time
+        assert!(!service.try_acquire("gemini-3-pro-preview"));
+
+        clock.advance(Duration::seconds(PROBE_TIMEOUT_SECS));
+        assert!(service.try_acquire("gemini-3-pro-preview"));
+
+        service.mark_healthy("gemini-3-pro-preview");
+        assert!(service.try_acquire("gemini-3-pro-preview"));
+        assert!(service.try_acquire("gemini-3-pro-preview"));
+    }
+
+    #[test]
+    fn test_quota_reset_from_error_details() {
+        let now = at("2026-01-15T20:00:00Z");
+        let with_timestamp = r#"[{"error": {"code": 429, "details": [{
+            "@type": "type.googleapis.com/google.rpc.ErrorInfo",
+            "metadata": {"quotaResetTimeStamp": "2026-01-16T03:00:00Z"}
+        }]}}]"#;
+        assert_eq!(
+            quota_reset_time(with_timestamp, now),
+            Some(at("2026-01-16T03:00:00Z"))
         );
+
+        let with_delay =
+            r#"{"error": {"details": [{"metadata": {"quotaResetDelay": "1h30m0.5s"}}]}}"#;
+        assert_eq!(
+            quota_reset_time(with_delay, now),
+            Some(now + Duration::milliseconds(5_400_500))
+        );
+
+        assert_eq!(quota_reset_time("Daily quota exceeded", now), None);
+    }
+
+    #[test]
+    fn test_next_pacific_midnight_follows_dst() {
+        // Winter (PST, UTC-8)
+        assert_eq!(
+            next_pacific_midnight(at("2026-01-15T20:00:00Z")),
+            at("2026-01-16T08:00:00Z")
+        );
+        // Summer (PDT, UTC-7): 23:30 on June 30 local
+        assert_eq!(
+            next_pacific_midnight(at("2026-07-01T06:30:00Z")),
+            at("2026-07-01T07:00:00Z")
+        );
+        // Midnight after the spring-forward day is already PDT
+        assert_eq!(
+            next_pacific_midnight(at("2026-03-08T12:00:00Z")),
+            at("2026-03-09T07:00:00Z")
+        );
+    }
+
+    #[test]
+    fn test_state_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let clock = Arc::new(MockClock::new(at("2026-01-15T20:00:00Z")));
+
+        let service =
+            ModelAvailabilityService::with_clock(clock.clone()).with_state_file(path.clone());
+        service.record_failure("gemini-3-pro-preview", 429, "Daily quota exceeded");
+        service.record_failure("gemini-2.5-pro", 429, "Resource exhausted");
+        assert!(service.try_acquire("gemini-2.5-pro"));
+        drop(service);
+
+        let restored =
+            ModelAvailabilityService::with_clock(clock.clone()).with_state_file(path.clone());
+        assert!(!restored.is_available("gemini-3-pro-preview"));
+        // The consumed sticky retry is remembered too
+        assert!(!restored.try_acquire("gemini-2.5-pro"));
+
+        // Entries that expired while the proxy was down are discarded
+        clock.advance(Duration::days(1));
+        let later = ModelAvailabilityService::with_clock(clock).with_state_file(path);
+        assert_eq!(
+            later.status("gemini-3-pro-preview"),
+            AvailabilityStatus::Healthy
+        );
+        assert!(later.try_acquire("gemini-2.5-pro"));
+    }
+
+    #[tokio::test]
+    async fn test_persists_off_the_request_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let clock = Arc::new(MockClock::new(at("2026-01-15T20:00:00Z")));
+
+        let service =
+            ModelAvailabilityService::with_clock(clock.clone()).with_state_file(path.clone());
+        service.record_failure("gemini-3-pro-preview", 429, "Daily quota exceeded");
+        service.record_failure("gemini-2.5-pro", 429, "Daily quota exceeded");
+        service.mark_healthy("gemini-2.5-pro");
+
+        // Only the latest snapshot may be left on disk
+        let expected = r#""gemini-3-pro-preview""#;
+        for _ in 0..100 {
+            let text = std::fs::read_to_string(&path).unwrap_or_default();
+            if text.contains(expected) && !text.contains("gemini-2.5-pro") {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("State file was not written");
     }
 }
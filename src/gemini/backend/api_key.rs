@@ -0,0 +1,317 @@
+//! Public Generative Language API backend (AI Studio API key).
+
+// Author: kelexine (https://github.com/kelexine)
+
+use super::{ResponseEnvelope, UpstreamBackend};
+use crate::config::{BackendConfig, BackendKind, GeminiConfig};
+use crate::error::{ProxyError, Result};
+use crate::gemini::streaming::GeminiStream;
+use crate::gemini::ModelAvailabilityService;
+use crate::models::gemini::{GenerateContentRequest, GenerateContentResponse};
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder};
+use std::time::Duration;
+use tracing::debug;
+
+/// Default public API endpoint.
+const DEFAULT_BASE_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+
+/// Environment variable read when neither `api_key` nor `api_key_env` is set.
+const DEFAULT_API_KEY_ENV: &str = "GEMINI_API_KEY";
+
+/// Calls `generativelanguage.googleapis.com` with an API key.
+pub struct ApiKeyBackend {
+    http_client: Client,
+    base_url: String,
+    api_key: String,
+    availability: ModelAvailabilityService,
+    /// Retries allowed for failed generation requests (`gemini.max_retries`).
+    max_retries: u32,
+}
+
+impl ApiKeyBackend {
+    /// Creates the backend, reading the key from `api_key` or the environment.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProxyError::Config` if no API key is available.
+    pub fn new(config: &BackendConfig, gemini: &GeminiConfig) -> Result<Self> {
+        let env = config.api_key_env.as_deref().unwrap_or(DEFAULT_API_KEY_ENV);
+        let api_key = config
+            .api_key
+            .clone()
+            .or_else(|| std::env::var(env).ok())
+            .filter(|key| !key.is_empty())
+            .ok_or_else(|| {
+                ProxyError::Config(format!(
+                    "Backend {}: set `api_key` or the {} environment variable",
+                    config.name, env
+                ))
+            })?;
+
+        Ok(Self {
+            http_client: super::http_client(gemini)?,
+            base_url: config
+                .base_url
+                .clone()
+                .unwrap_or_else(|| DEFAULT_BASE_URL.to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            api_key,
+            availability: super::availability_for(gemini, &config.name),
+            max_retries: gemini.max_retries,
+        })
+    }
+
+    /// Starts a POST to `models/{model}:{method}` with the key attached.
+    fn post(&self, model: &str, method: &str) -> RequestBuilder {
+        self.http_client
+            .post(format!("{}/models/{}:{}", self.base_url, model, method))
+            .header("x-goog-api-key", &self.api_key)
+    }
+}
+
+#[async_trait]
+impl UpstreamBackend for ApiKeyBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::ApiKey
+    }
+
+    fn endpoint(&self) -> &str {
+        &self.base_url
+    }
+
+    fn availability(&self) -> &ModelAvailabilityService {
+        &self.availability
+    }
+
+    async fn generate_content(
+        &self,
+        request: GenerateContentRequest,
+        model: &str,
+    ) -> Result<GenerateContentResponse> {
+        debug!("Calling public generateContent API for model: {}", model);
+        let request = self
+            .post(model, "generateContent")
+            .json(&super::bare_request(request));
+        super::send_generate(
+            request,
+            &self.availability,
+            model,
+            self.max_retries,
+            ResponseEnvelope::Bare,
+        )
+        .await
+    }
+
+    async fn stream_generate_content(
+        &self,
+        request: GenerateContentRequest,
+        model: &str,
+    ) -> Result<GeminiStream> {
+        debug!(
+            "Calling public streamGenerateContent API for model: {}",
+            model
+        );
+        let request = self
+            .post(model, "streamGenerateContent")
+            .query(&[("alt", "sse")])
+            .json(&super::bare_request(request));
+        crate::gemini::streaming::stream_generate_content(
+            request,
+            &self.availability,
+            model,
+            self.max_retries,
+            ResponseEnvelope::Bare,
+        )
+        .await
+    }
+
+    async fn count_tokens(&self, request: &GenerateContentRequest, model: &str) -> Result<u32> {
+        // The full request (system instruction, tools) must be nested with its model
+        let mut generate_request = serde_json::to_value(super::bare_request(request.clone()))?;
+        generate_request["model"] = format!("models/{}", model).into();
+        let request = self
+            .post(model, "countTokens")
+            .json(&serde_json::json!({ "generateContentRequest": generate_request }));
+        super::send_count_tokens(request).await
+    }
+
+    async fn check_connectivity(&self, _model: &str) -> Result<Duration> {
+        // Listing models validates the key without spending quota
+        let request = self
+            .http_client
+            .get(format!("{}/models", self.base_url))
+            .query(&[("pageSize", "1")])
+            .header("x-goog-api-key", &self.api_key);
+        super::send_probe(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_support::{bare_response, first_text, hello_request};
+    use super::*;
+    use futures::StreamExt;
+
+    fn backend(server: &mockito::Server) -> ApiKeyBackend {
+        backend_with_retries(server, 0)
+    }
+
+    fn backend_with_retries(server: &mockito::Server, max_retries: u32) -> ApiKeyBackend {
+        ApiKeyBackend::new(
+            &BackendConfig {
+                name: "studio".to_string(),
+                kind: BackendKind::ApiKey,
+                api_key: Some("test-key".to_string()),
+                base_url: Some(server.url()),
+                ..BackendConfig::default()
+            },
+            &GeminiConfig {
+                max_retries,
+                state_file: String::new(),
+                ..GeminiConfig::default()
+            },
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_generate_content() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/models/gemini-2.5-flash:generateContent")
+            .match_header("x-goog-api-key", "test-key")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "contents": [{"role": "user", "parts": [{"text": "hi"}]}],
+            })))
+            .with_body(bare_response("hello"))
+            .create_async()
+            .await;
+
+        let response = backend(&server)
+            .generate_content(hello_request(), "gemini-2.5-flash")
+            .await
+            .unwrap();
+
+        assert_eq!(first_text(&response), "hello");
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_stream_generate_content() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/models/gemini-2.5-flash:streamGenerateContent")
+            .match_query(mockito::Matcher::UrlEncoded("alt".into(), "sse".into()))
+            .with_header("content-type", "text/event-stream")
+            .with_body(format!(
+                "data: {}\n\ndata: {}\n\n",
+                bare_response("Hel"),
+                bare_response("lo")
+            ))
+            .create_async()
+            .await;
+
+        let stream = backend(&server)
+            .stream_generate_content(hello_request(), "gemini-2.5-flash")
+            .await
+            .unwrap();
+        let chunks: Vec<_> = stream.collect().await;
+
+        let text: String = chunks
+            .iter()
+            .map(|c| first_text(c.as_ref().unwrap()))
+            .collect();
+        assert_eq!(text, "Hello");
+    }
+
+    #[tokio::test]
+    async fn test_count_tokens_and_connectivity() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/models/gemini-2.5-flash:countTokens")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "generateContentRequest": {"model": "models/gemini-2.5-flash"},
+            })))
+            .with_body(r#"{"totalTokens": 42}"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/models")
+            .match_query(mockito::Matcher::Any)
+            .with_body(r#"{"models": []}"#)
+            .create_async()
+            .await;
+
+        let backend = backend(&server);
+        assert_eq!(
+            backend
+                .count_tokens(&hello_request(), "gemini-2.5-flash")
+                .await
+                .unwrap(),
+            42
+        );
+        assert!(backend
+            .check_connectivity("gemini-2.5-flash-lite")
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_marks_model() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/models/gemini-2.5-pro:generateContent")
+            .with_status(429)
+            .with_body(r#"{"error": {"code": 429, "status": "RESOURCE_EXHAUSTED"}}"#)
+            .create_async()
+            .await;
+
+        let backend = backend(&server);
+        let result = backend
+            .generate_content(hello_request(), "gemini-2.5-pro")
+            .await;
+
+        assert!(matches!(result, Err(ProxyError::TooManyRequests(_))));
+        assert!(!backend.availability().unhealthy_models().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_server_errors_are_retried() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/models/gemini-2.5-pro:generateContent")
+            .with_status(503)
+            .with_body(r#"{"error": {"code": 503, "status": "UNAVAILABLE"}}"#)
+            .expect(2)
+            .create_async()
+            .await;
+
+        let result = backend_with_retries(&server, 1)
+            .generate_content(hello_request(), "gemini-2.5-pro")
+            .await;
+
+        assert!(matches!(result, Err(ProxyError::ServiceUnavailable(_))));
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_exhausted_quota_is_not_retried() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/models/gemini-2.5-pro:generateContent")
+            .with_status(429)
+            .with_body(r#"{"error": {"code": 429, "message": "Daily limit reached"}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let result = backend_with_retries(&server, 3)
+            .generate_content(hello_request(), "gemini-2.5-pro")
+            .await;
+
+        assert!(matches!(result, Err(ProxyError::TooManyRequests(_))));
+        mock.assert_async().await;
+    }
+}
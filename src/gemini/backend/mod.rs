@@ -0,0 +1,569 @@
+//! Pluggable upstream backends.
+//!
+//! Every upstream API the proxy can call implements [`UpstreamBackend`]:
+//! - Code Assist (`cloudcode-pa.googleapis.com/v1internal`) with Gemini CLI
+//!   OAuth credentials, implemented by `GeminiClient` and served by the
+//!   account pool
+//! - The public Generative Language API with an AI Studio key ([`ApiKeyBackend`])
+//! - Vertex AI with Application Default Credentials ([`VertexBackend`])
+//!
+//! Routes and rules select a `[[backends]]` entry by name; requests without one
+//! go to the Code Assist account pool.
+
+// Author: kelexine (https://github.com/kelexine)
+
+mod api_key;
+mod vertex;
+
+pub use api_key::ApiKeyBackend;
+pub use vertex::VertexBackend;
+
+use super::streaming::GeminiStream;
+use super::{Account, GeminiClient, ModelAvailabilityService};
+use crate::config::{AppConfig, BackendKind, GeminiConfig};
+use crate::error::{ProxyError, Result};
+use crate::models::gemini::{GenerateContentRequest, GenerateContentResponse, ResponseWrapper};
+use crate::utils::retry::CONNECT_ERROR;
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder, Response};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// An upstream API serving Gemini models.
+#[async_trait]
+pub trait UpstreamBackend: Send + Sync {
+    /// Which API this backend talks to.
+    fn kind(&self) -> BackendKind;
+
+    /// Base URL of the upstream endpoint, used to key circuit breakers.
+    fn endpoint(&self) -> &str;
+
+    /// Model health tracked for this backend.
+    fn availability(&self) -> &ModelAvailabilityService;
+
+    /// The Code Assist client behind this backend, if it is one. Context
+    /// caching and account health reporting are Code Assist specific.
+    fn code_assist(&self) -> Option<&GeminiClient> {
+        None
+    }
+
+    /// Executes a unary content generation request.
+    async fn generate_content(
+        &self,
+        request: GenerateContentRequest,
+        model: &str,
+    ) -> Result<GenerateContentResponse>;
+
+    /// Executes a streaming content generation request.
+    async fn stream_generate_content(
+        &self,
+        request: GenerateContentRequest,
+        model: &str,
+    ) -> Result<GeminiStream>;
+
+    /// Counts the input tokens of a request.
+    async fn count_tokens(&self, request: &GenerateContentRequest, model: &str) -> Result<u32>;
+
+    /// Verifies credentials and reachability without spending quota,
+    /// returning the round-trip latency. `model` is the configured
+    /// `health.probe_model`, for APIs whose cheapest check is per model.
+    async fn check_connectivity(&self, model: &str) -> Result<Duration>;
+}
+
+/// How an API wraps generation responses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseEnvelope {
+    /// Code Assist nests the response under `response`.
+    Wrapped,
+    /// The public and Vertex APIs return it at the top level.
+    Bare,
+}
+
+impl ResponseEnvelope {
+    /// Parses one response (or stream chunk) body.
+    pub fn parse(self, body: &str) -> serde_json::Result<GenerateContentResponse> {
+        match self {
+            ResponseEnvelope::Wrapped => serde_json::from_str(body),
+            ResponseEnvelope::Bare => Ok(GenerateContentResponse {
+                response: Some(serde_json::from_str::<ResponseWrapper>(body)?),
+            }),
+        }
+    }
+}
+
+/// Named backends from `[[backends]]`, each wrapped as an [`Account`] so that
+/// dispatch treats them like pooled accounts.
+pub struct BackendRegistry {
+    backends: Vec<Arc<Account>>,
+}
+
+impl BackendRegistry {
+    /// Builds every configured backend.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProxyError::Config` for unnamed, duplicate or `code_assist`
+    /// entries, for the name reserved for the default account and for missing
+    /// API keys or projects, and
+    /// `ProxyError::InvalidCredentials` for unreadable ADC files.
+    pub fn new(config: &AppConfig) -> Result<Self> {
+        let mut backends: Vec<Arc<Account>> = Vec::with_capacity(config.backends.len());
+        for backend in &config.backends {
+            if backend.name.is_empty() {
+                return Err(ProxyError::Config("Backends must have a name".to_string()));
+            }
+            // The default account's state file would be shared otherwise
+            if backend.name == super::pool::DEFAULT_ACCOUNT {
+                return Err(ProxyError::Config(format!(
+                    "Backend name {} is reserved for the default account",
+                    backend.name
+                )));
+            }
+            if backends.iter().any(|b| b.name() == backend.name)
+                || config.pool.accounts.iter().any(|a| a.name == backend.name)
+            {
+                return Err(ProxyError::Config(format!(
+                    "Duplicate backend or account name: {}",
+                    backend.name
+                )));
+            }
+
+            let upstream: Arc<dyn UpstreamBackend> = match backend.kind {
+                BackendKind::CodeAssist => {
+                    return Err(ProxyError::Config(format!(
+                        "Backend {}: Code Assist is served by the account pool; \
+                         configure it under [pool] instead",
+                        backend.name
+                    )))
+                }
+                BackendKind::ApiKey => Arc::new(ApiKeyBackend::new(backend, &config.gemini)?),
+                BackendKind::Vertex => Arc::new(VertexBackend::new(backend, &config.gemini)?),
+            };
+            info!(
+                "Configured {} backend {}",
+                backend.kind.as_str(),
+                backend.name
+            );
+            backends.push(Arc::new(Account::with_backend(&backend.name, upstream)));
+        }
+
+        Ok(Self { backends })
+    }
+
+    /// Looks up a backend by name.
+    pub fn get(&self, name: &str) -> Option<&Arc<Account>> {
+        self.backends.iter().find(|backend| backend.name() == name)
+    }
+
+    /// Returns all backends in configuration order.
+    pub fn backends(&self) -> &[Arc<Account>] {
+        &self.backends
+    }
+}
+
+/// Builds the HTTP client shared by a backend's requests.
+pub(crate) fn http_client(config: &GeminiConfig) -> Result<Client> {
+    // Custom pool and keep-alive minimize handshake overhead while streaming
+    Client::builder()
+        .timeout(Duration::from_secs(config.timeout_seconds))
+        .connect_timeout(Duration::from_secs(10))
+        .pool_max_idle_per_host(10)
+        .pool_idle_timeout(Duration::from_secs(90))
+        .tcp_keepalive(Some(Duration::from_secs(60)))
+        .tcp_nodelay(true)
+        .use_rustls_tls()
+        .build()
+        .map_err(|e| ProxyError::Internal(format!("Failed to create HTTP client: {}", e)))
+}
+
+/// Builds the availability tracker for a named account or backend.
+///
+/// The default account persists to `gemini.state_file`; others use
+/// `state.<name>.json` beside it.
+pub(crate) fn availability_for(config: &GeminiConfig, name: &str) -> ModelAvailabilityService {
+    let availability = ModelAvailabilityService::new().with_account(name);
+    if config.state_file.is_empty() {
+        return availability;
+    }
+    let mut state_file = crate::utils::paths::expand_home(&config.state_file);
+    if name != super::pool::DEFAULT_ACCOUNT {
+        state_file.set_extension(format!("{}.json", name));
+    }
+    availability.with_state_file(state_file)
+}
+
+/// Maps an upstream HTTP error status to a `ProxyError`.
+pub(crate) fn upstream_error(status: u16, body: String) -> ProxyError {
+    match status {
+        429 => ProxyError::TooManyRequests(format!("Gemini API quota exceeded: {}", body)),
+        529 => ProxyError::Overloaded(format!("Gemini API overloaded: {}", body)),
+        503 | 504 => ProxyError::ServiceUnavailable(format!("Upstream unavailable: {}", body)),
+        _ => ProxyError::UpstreamStatus(status, body),
+    }
+}
+
+/// Sends a prepared generation request, retrying 429 and 5xx responses and
+/// failed connections up to `max_retries` times.
+///
+/// Every attempt is recorded in metrics and model health. Retries stop early
+/// once a failure makes the model unavailable (e.g. its daily quota is
+/// exhausted), leaving the fallback chain to pick another model. Other
+/// transport errors (e.g. read timeouts) are not retried, as the upstream may
+/// already be generating.
+pub(crate) async fn send_with_retry(
+    request: RequestBuilder,
+    availability: &ModelAvailabilityService,
+    model: &str,
+    max_retries: u32,
+    streaming: bool,
+) -> Result<Response> {
+    let operation_name = if streaming {
+        "streamGenerateContent"
+    } else {
+        "generateContent"
+    };
+
+    crate::utils::retry::with_retry_limit(
+        operation_name,
+        max_retries,
+        |status, _| {
+            let retry = availability.is_available(model);
+            if retry {
+                let reason = match status {
+                    CONNECT_ERROR => "connect_error",
+                    429 => "rate_limit",
+                    _ => "server_error",
+                };
+                if status == CONNECT_ERROR {
+                    warn!(
+                        "{} for model {} could not connect, retrying",
+                        operation_name, model
+                    );
+                } else {
+                    warn!(
+                        "{} for model {} failed with HTTP {}, retrying",
+                        operation_name, model, status
+                    );
+                }
+                crate::metrics::record_retry_attempt(model, reason);
+            }
+            retry
+        },
+        || async {
+            let attempt = request
+                .try_clone()
+                .ok_or_else(|| (0, "Request body cannot be retried".to_string()))?;
+            let start_time = std::time::Instant::now();
+            let response = attempt
+                .header("Content-Type", "application/json")
+                .send()
+                .await
+                .map_err(|e| {
+                    let status = if e.is_connect() { CONNECT_ERROR } else { 0 };
+                    (status, format!("HTTP error: {}", e))
+                })?;
+
+            let duration = start_time.elapsed().as_secs_f64();
+            let status = response.status();
+            // For streams this is the handshake, i.e. time to first byte
+            crate::metrics::record_gemini_call(model, status.as_u16(), streaming, duration);
+
+            if !status.is_success() {
+                let error_text = response.text().await.unwrap_or_default();
+                error!(
+                    "Gemini API error: HTTP {} - Response body: {}",
+                    status, error_text
+                );
+
+                // Update health scoring for subsequent requests
+                availability.record_failure(model, status.as_u16(), &error_text);
+                return Err((status.as_u16(), error_text));
+            }
+
+            availability.mark_healthy(model);
+            Ok(response)
+        },
+    )
+    .await
+    .map_err(|(status, body)| match status {
+        // Timeouts and connection failures: the endpoint may be down
+        0 | CONNECT_ERROR => {
+            ProxyError::ServiceUnavailable(format!("Upstream unreachable: {}", body))
+        }
+        _ => upstream_error(status, body),
+    })
+}
+
+/// Sends a prepared unary generation request and parses the response.
+pub(crate) async fn send_generate(
+    request: RequestBuilder,
+    availability: &ModelAvailabilityService,
+    model: &str,
+    max_retries: u32,
+    envelope: ResponseEnvelope,
+) -> Result<GenerateContentResponse> {
+    let response = send_with_retry(request, availability, model, max_retries, false).await?;
+
+    let response_text = response
+        .text()
+        .await
+        .map_err(|e| ProxyError::GeminiApi(format!("Failed to read response body: {}", e)))?;
+
+    envelope.parse(&response_text).map_err(|e| {
+        error!("Failed to parse Gemini response: {}", e);
+        ProxyError::GeminiApi(format!("Response parsing error: {}", e))
+    })
+}
+
+/// Sends a prepared `countTokens` request and returns `totalTokens`.
+pub(crate) async fn send_count_tokens(request: RequestBuilder) -> Result<u32> {
+    #[derive(serde::Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct CountTokensResponse {
+        #[serde(default)]
+        total_tokens: u32,
+    }
+
+    let response = request
+        .header("Content-Type", "application/json")
+        .send()
+        .await
+        .map_err(|e| ProxyError::GeminiApi(format!("HTTP error: {}", e)))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let error_text = response.text().await.unwrap_or_default();
+        return Err(upstream_error(status.as_u16(), error_text));
+    }
+
+    let response: CountTokensResponse = response
+        .json()
+        .await
+        .map_err(|e| ProxyError::GeminiApi(format!("Response parsing error: {}", e)))?;
+    Ok(response.total_tokens)
+}
+
+/// Sends a prepared connectivity probe and returns its latency.
+pub(crate) async fn send_probe(request: RequestBuilder) -> Result<Duration> {
+    let start = std::time::Instant::now();
+    let response = request
+        .timeout(Duration::from_secs(5))
+        .send()
+        .await
+        .map_err(|e| ProxyError::ServiceUnavailable(format!("Upstream unreachable: {}", e)))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let error_text = response.text().await.unwrap_or_default();
+        return Err(ProxyError::GeminiApi(format!(
+            "API check failed: {}",
+            error_text
+        )));
+    }
+    Ok(start.elapsed())
+}
+
+/// Request body for the public and Vertex APIs, which take the
+/// `GenerateContentRequest` directly.
+///
+/// Code Assist context caches don't exist on these APIs, so any cache
+/// reference is dropped.
+pub(crate) fn bare_request(mut request: GenerateContentRequest) -> GenerateContentRequest {
+    request.cached_content = None;
+    request
+}
+
+#[cfg(test)]
+pub(crate) mod test_support {
+    //! Shared fixtures for the mock-server backend tests.
+
+    use crate::models::gemini::{Content, GenerateContentRequest, Part};
+
+    /// A one-turn "hi" request.
+    pub fn hello_request() -> GenerateContentRequest {
+        GenerateContentRequest {
+            contents: vec![Content {
+                role: "user".to_string(),
+                parts: vec![Part::Text {
+                    text: "hi".to_string(),
+                    thought: None,
+                    thought_signature: None,
+                }],
+            }],
+            system_instruction: None,
+            generation_config: None,
+            tools: None,
+            tool_config: None,
+            cached_content: None,
+        }
+    }
+
+    /// A bare (public/Vertex API) response body with the given text.
+    pub fn bare_response(text: &str) -> String {
+        serde_json::json!({
+            "candidates": [{
+                "content": {"role": "model", "parts": [{"text": text}]},
+                "finishReason": "STOP",
+            }],
+            "usageMetadata": {"promptTokenCount": 1, "candidatesTokenCount": 1},
+        })
+        .to_string()
+    }
+
+    /// Text of the first candidate's first part.
+    pub fn first_text(response: &crate::models::gemini::GenerateContentResponse) -> String {
+        response.response.as_ref().unwrap().candidates[0]
+            .content
+            .parts[0]
+            .as_text()
+            .unwrap()
+            .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BackendConfig;
+
+    #[test]
+    fn test_response_envelopes() {
+        let bare = test_support::bare_response("hello");
+        let wrapped = format!(r#"{{"response": {}}}"#, bare);
+
+        let from_bare = ResponseEnvelope::Bare.parse(&bare).unwrap();
+        let from_wrapped = ResponseEnvelope::Wrapped.parse(&wrapped).unwrap();
+        assert_eq!(test_support::first_text(&from_bare), "hello");
+        assert_eq!(test_support::first_text(&from_wrapped), "hello");
+    }
+
+    #[test]
+    fn test_registry_rejects_code_assist_and_duplicates() {
+        let backend = |name: &str, kind: BackendKind| BackendConfig {
+            name: name.to_string(),
+            kind,
+            api_key: Some("key".to_string()),
+            ..BackendConfig::default()
+        };
+
+        let config = AppConfig {
+            backends: vec![backend("pool", BackendKind::CodeAssist)],
+            ..AppConfig::default()
+        };
+        assert!(BackendRegistry::new(&config).is_err());
+
+        let config = AppConfig {
+            backends: vec![backend(
+                crate::gemini::pool::DEFAULT_ACCOUNT,
+                BackendKind::ApiKey,
+            )],
+            ..AppConfig::default()
+        };
+        assert!(BackendRegistry::new(&config).is_err());
+
+        let config = AppConfig {
+            backends: vec![
+                backend("studio", BackendKind::ApiKey),
+                backend("studio", BackendKind::ApiKey),
+            ],
+            ..AppConfig::default()
+        };
+        assert!(BackendRegistry::new(&config).is_err());
+
+        let config = AppConfig {
+            backends: vec![backend("studio", BackendKind::ApiKey)],
+            ..AppConfig::default()
+        };
+        let registry = BackendRegistry::new(&config).unwrap();
+        assert_eq!(
+            registry.get("studio").unwrap().backend().kind(),
+            BackendKind::ApiKey
+        );
+    }
+
+    /// Connector layer refusing the next `refusals` connections.
+    #[derive(Clone)]
+    struct RefuseConnections(Arc<std::sync::atomic::AtomicU32>);
+
+    impl<S> tower::Layer<S> for RefuseConnections {
+        type Service = RefusingConnector<S>;
+
+        fn layer(&self, inner: S) -> Self::Service {
+            RefusingConnector {
+                inner,
+                refusals: self.0.clone(),
+            }
+        }
+    }
+
+    #[derive(Clone)]
+    struct RefusingConnector<S> {
+        inner: S,
+        refusals: Arc<std::sync::atomic::AtomicU32>,
+    }
+
+    impl<S, R> tower::Service<R> for RefusingConnector<S>
+    where
+        S: tower::Service<R>,
+        S::Response: Send + 'static,
+        S::Error: From<std::io::Error> + Send + 'static,
+        S::Future: Send + 'static,
+    {
+        type Response = S::Response;
+        type Error = S::Error;
+        type Future =
+            futures::future::BoxFuture<'static, std::result::Result<S::Response, S::Error>>;
+
+        fn poll_ready(
+            &mut self,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::result::Result<(), S::Error>> {
+            self.inner.poll_ready(cx)
+        }
+
+        fn call(&mut self, request: R) -> Self::Future {
+            use std::sync::atomic::Ordering;
+            if self
+                .refusals
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok()
+            {
+                let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+                return Box::pin(futures::future::ready(Err(refused.into())));
+            }
+            Box::pin(self.inner.call(request))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry_retries_refused_connections() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/")
+            .with_status(200)
+            .expect(1)
+            .create_async()
+            .await;
+        // The first connection is refused, the retry reaches the server
+        let refusing_client = || {
+            Client::builder()
+                .connector_layer(RefuseConnections(Arc::new(1.into())))
+                .build()
+                .unwrap()
+        };
+
+        let request = refusing_client().post(server.url());
+        let availability = ModelAvailabilityService::new();
+        let response = send_with_retry(request, &availability, "gemini-2.5-pro", 1, false)
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        mock.assert_async().await;
+
+        // Without retries the refusal is reported as unreachable
+        let request = refusing_client().post(server.url());
+        let result = send_with_retry(request, &availability, "gemini-2.5-pro", 0, false).await;
+        assert!(matches!(result, Err(ProxyError::ServiceUnavailable(_))));
+    }
+}
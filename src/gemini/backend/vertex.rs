@@ -0,0 +1,325 @@
+//! Vertex AI backend (Application Default Credentials or a service account).
+
+// Author: kelexine (https://github.com/kelexine)
+
+use super::{ResponseEnvelope, UpstreamBackend};
+use crate::config::{BackendConfig, BackendKind, GeminiConfig};
+use crate::error::{ProxyError, Result};
+use crate::gemini::streaming::GeminiStream;
+use crate::gemini::ModelAvailabilityService;
+use crate::models::gemini::{GenerateContentRequest, GenerateContentResponse};
+use crate::oauth::AdcTokenSource;
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder};
+use std::time::Duration;
+use tracing::debug;
+
+/// Location used when none is configured.
+const DEFAULT_LOCATION: &str = "global";
+
+/// Calls `aiplatform.googleapis.com` publisher models in a Google Cloud project.
+pub struct VertexBackend {
+    http_client: Client,
+    /// `.../projects/{project}/locations/{location}/publishers/google/models`
+    models_url: String,
+    token_source: AdcTokenSource,
+    availability: ModelAvailabilityService,
+    /// Retries allowed for failed generation requests (`gemini.max_retries`).
+    max_retries: u32,
+}
+
+impl VertexBackend {
+    /// Creates the backend, loading credentials and resolving the project.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProxyError::InvalidCredentials` if no usable credentials are
+    /// found, or `ProxyError::Config` if no project is configured or implied.
+    pub fn new(config: &BackendConfig, gemini: &GeminiConfig) -> Result<Self> {
+        let http_client = super::http_client(gemini)?;
+        let token_source =
+            AdcTokenSource::load(config.credentials_path.as_deref(), http_client.clone())?;
+
+        let project = config
+            .project
+            .clone()
+            .or_else(|| token_source.project_id().map(str::to_string))
+            .or_else(|| std::env::var("GOOGLE_CLOUD_PROJECT").ok())
+            .filter(|project| !project.is_empty())
+            .ok_or_else(|| {
+                ProxyError::Config(format!(
+                    "Backend {}: set `project` or GOOGLE_CLOUD_PROJECT",
+                    config.name
+                ))
+            })?;
+        let location = config.location.as_deref().unwrap_or(DEFAULT_LOCATION);
+        let base_url = match &config.base_url {
+            Some(base_url) => base_url.trim_end_matches('/').to_string(),
+            None if location == "global" => "https://aiplatform.googleapis.com/v1".to_string(),
+            None => format!("https://{}-aiplatform.googleapis.com/v1", location),
+        };
+
+        Ok(Self {
+            http_client,
+            models_url: format!(
+                "{}/projects/{}/locations/{}/publishers/google/models",
+                base_url, project, location
+            ),
+            token_source,
+            availability: super::availability_for(gemini, &config.name),
+            max_retries: gemini.max_retries,
+        })
+    }
+
+    /// Starts an authenticated POST to `models/{model}:{method}`.
+    async fn post(&self, model: &str, method: &str) -> Result<RequestBuilder> {
+        let access_token = self.token_source.get_token().await?;
+        Ok(self
+            .http_client
+            .post(format!("{}/{}:{}", self.models_url, model, method))
+            .bearer_auth(access_token))
+    }
+
+    /// `countTokens` accepts only the prompt fields of a generation request.
+    fn count_tokens_body(request: &GenerateContentRequest) -> serde_json::Value {
+        let mut body = serde_json::json!({ "contents": request.contents });
+        if let Some(system_instruction) = &request.system_instruction {
+            body["systemInstruction"] = serde_json::json!(system_instruction);
+        }
+        if let Some(tools) = &request.tools {
+            body["tools"] = serde_json::json!(tools);
+        }
+        body
+    }
+}
+
+#[async_trait]
+impl UpstreamBackend for VertexBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Vertex
+    }
+
+    fn endpoint(&self) -> &str {
+        &self.models_url
+    }
+
+    fn availability(&self) -> &ModelAvailabilityService {
+        &self.availability
+    }
+
+    async fn generate_content(
+        &self,
+        request: GenerateContentRequest,
+        model: &str,
+    ) -> Result<GenerateContentResponse> {
+        debug!("Calling Vertex AI generateContent for model: {}", model);
+        let request = self
+            .post(model, "generateContent")
+            .await?
+            .json(&super::bare_request(request));
+        super::send_generate(
+            request,
+            &self.availability,
+            model,
+            self.max_retries,
+            ResponseEnvelope::Bare,
+        )
+        .await
+    }
+
+    async fn stream_generate_content(
+        &self,
+        request: GenerateContentRequest,
+        model: &str,
+    ) -> Result<GeminiStream> {
+        debug!(
+            "Calling Vertex AI streamGenerateContent for model: {}",
+            model
+        );
+        let request = self
+            .post(model, "streamGenerateContent")
+            .await?
+            .query(&[("alt", "sse")])
+            .json(&super::bare_request(request));
+        crate::gemini::streaming::stream_generate_content(
+            request,
+            &self.availability,
+            model,
+            self.max_retries,
+            ResponseEnvelope::Bare,
+        )
+        .await
+    }
+
+    async fn count_tokens(&self, request: &GenerateContentRequest, model: &str) -> Result<u32> {
+        let request = self
+            .post(model, "countTokens")
+            .await?
+            .json(&Self::count_tokens_body(request));
+        super::send_count_tokens(request).await
+    }
+
+    async fn check_connectivity(&self, model: &str) -> Result<Duration> {
+        // countTokens checks credentials and project access without spending quota
+        let request = self
+            .post(model, "countTokens")
+            .await?
+            .json(&serde_json::json!({
+                "contents": [{"role": "user", "parts": [{"text": "hi"}]}],
+            }));
+        super::send_probe(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_support::{bare_response, first_text, hello_request};
+    use super::*;
+    use futures::StreamExt;
+
+    const MODELS_PATH: &str = "/projects/my-project/locations/us-central1/publishers/google/models";
+
+    /// Mocks the token endpoint and returns a backend using it.
+    async fn backend(server: &mut mockito::Server) -> (VertexBackend, tempfile::NamedTempFile) {
+        server
+            .mock("POST", "/token")
+            .with_body(r#"{"access_token": "ya29.vertex", "expires_in": 3599}"#)
+            .create_async()
+            .await;
+        let credentials = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(
+            credentials.path(),
+            serde_json::json!({
+                "type": "authorized_user",
+                "client_id": "client",
+                "client_secret": "secret",
+                "refresh_token": "refresh",
+                "token_uri": format!("{}/token", server.url()),
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let backend = VertexBackend::new(
+            &BackendConfig {
+                name: "vertex".to_string(),
+                kind: BackendKind::Vertex,
+                base_url: Some(server.url()),
+                project: Some("my-project".to_string()),
+                location: Some("us-central1".to_string()),
+                credentials_path: Some(credentials.path().to_string_lossy().to_string()),
+                ..BackendConfig::default()
+            },
+            &GeminiConfig {
+                max_retries: 0,
+                state_file: String::new(),
+                ..GeminiConfig::default()
+            },
+        )
+        .unwrap();
+        (backend, credentials)
+    }
+
+    #[tokio::test]
+    async fn test_generate_content() {
+        let mut server = mockito::Server::new_async().await;
+        let (backend, _credentials) = backend(&mut server).await;
+        let mock = server
+            .mock(
+                "POST",
+                format!("{}/gemini-2.5-pro:generateContent", MODELS_PATH).as_str(),
+            )
+            .match_header("authorization", "Bearer ya29.vertex")
+            .with_body(bare_response("hello"))
+            .create_async()
+            .await;
+
+        let response = backend
+            .generate_content(hello_request(), "gemini-2.5-pro")
+            .await
+            .unwrap();
+
+        assert_eq!(first_text(&response), "hello");
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_stream_generate_content() {
+        let mut server = mockito::Server::new_async().await;
+        let (backend, _credentials) = backend(&mut server).await;
+        server
+            .mock(
+                "POST",
+                format!("{}/gemini-2.5-pro:streamGenerateContent", MODELS_PATH).as_str(),
+            )
+            .match_query(mockito::Matcher::UrlEncoded("alt".into(), "sse".into()))
+            .with_header("content-type", "text/event-stream")
+            .with_body(format!(
+                "data: {}\r\n\r\ndata: {}\r\n\r\n",
+                bare_response("Hel"),
+                bare_response("lo")
+            ))
+            .create_async()
+            .await;
+
+        let stream = backend
+            .stream_generate_content(hello_request(), "gemini-2.5-pro")
+            .await
+            .unwrap();
+        let chunks: Vec<_> = stream.collect().await;
+
+        let text: String = chunks
+            .iter()
+            .map(|c| first_text(c.as_ref().unwrap()))
+            .collect();
+        assert_eq!(text, "Hello");
+    }
+
+    #[tokio::test]
+    async fn test_count_tokens_and_connectivity() {
+        let mut server = mockito::Server::new_async().await;
+        let (backend, _credentials) = backend(&mut server).await;
+        server
+            .mock(
+                "POST",
+                mockito::Matcher::Regex(format!("^{}/[^/]+:countTokens$", MODELS_PATH)),
+            )
+            .match_header("authorization", "Bearer ya29.vertex")
+            .with_body(r#"{"totalTokens": 7, "totalBillableCharacters": 2}"#)
+            .create_async()
+            .await;
+
+        assert_eq!(
+            backend
+                .count_tokens(&hello_request(), "gemini-2.5-pro")
+                .await
+                .unwrap(),
+            7
+        );
+        assert!(backend
+            .check_connectivity("gemini-2.5-flash-lite")
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_server_error_is_mapped() {
+        let mut server = mockito::Server::new_async().await;
+        let (backend, _credentials) = backend(&mut server).await;
+        server
+            .mock(
+                "POST",
+                format!("{}/gemini-2.5-pro:generateContent", MODELS_PATH).as_str(),
+            )
+            .with_status(503)
+            .with_body(r#"{"error": {"code": 503, "status": "UNAVAILABLE"}}"#)
+            .create_async()
+            .await;
+
+        let result = backend
+            .generate_content(hello_request(), "gemini-2.5-pro")
+            .await;
+
+        assert!(matches!(result, Err(ProxyError::ServiceUnavailable(_))));
+    }
+}
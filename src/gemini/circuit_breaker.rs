@@ -0,0 +1,421 @@
+//! Circuit breaker for upstream endpoints.
+//!
+//! During an upstream outage every request would otherwise wait out
+//! `gemini.timeout_seconds` before failing. Each (endpoint, model) pair has a
+//! circuit that opens after `failure_threshold` consecutive failures (5xx,
+//! 529, timeouts and connection errors). While open, requests fail fast with
+//! `ServiceUnavailable`, which also lets the fallback chain move on. Once
+//! `open_seconds` have passed the circuit is half-open: a single probe request
+//! is let through, and its outcome closes or reopens the circuit.
+//!
+//! Streams hold their permit until the first chunk or the first error in the
+//! body, so upstreams that accept requests and then hang still trip the
+//! circuit.
+
+// Author: kelexine (https://github.com/kelexine)
+
+use crate::config::CircuitBreakerConfig;
+use crate::error::{ProxyError, Result};
+use crate::utils::clock::{Clock, SystemClock};
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// State of one circuit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests flow normally.
+    Closed,
+    /// Requests fail fast until the open period ends.
+    Open,
+    /// The open period has ended; one probe request decides the next state.
+    HalfOpen,
+}
+
+impl CircuitState {
+    /// Label used in logs and `/health`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "half_open",
+        }
+    }
+
+    /// Value reported by the `gemini_circuit_state` gauge.
+    fn gauge_value(&self) -> f64 {
+        match self {
+            CircuitState::Closed => 0.0,
+            CircuitState::HalfOpen => 1.0,
+            CircuitState::Open => 2.0,
+        }
+    }
+}
+
+/// Snapshot of a circuit that is not closed, for `/health`.
+#[derive(Debug, Clone)]
+pub struct CircuitStatus {
+    pub endpoint: String,
+    pub model: String,
+    pub state: CircuitState,
+}
+
+#[derive(Debug, Default)]
+struct Circuit {
+    /// Consecutive failures since the last success.
+    failures: u32,
+    /// When the circuit last opened (`None` while closed).
+    opened_at: Option<DateTime<Utc>>,
+    /// Whether the half-open probe is in flight.
+    probing: bool,
+}
+
+/// Per-endpoint, per-model circuit breakers.
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    circuits: Mutex<HashMap<(String, String), Circuit>>,
+    clock: Arc<dyn Clock>,
+}
+
+/// Permission to send one request through a circuit.
+///
+/// Report the outcome with [`CircuitPermit::record`]. A permit dropped without
+/// an outcome (e.g. the client disconnected) frees the half-open probe slot
+/// without changing the circuit. The permit owns a handle to the breaker, so
+/// a stream can carry it until its first chunk or error.
+pub struct CircuitPermit {
+    breaker: Arc<CircuitBreaker>,
+    key: Option<(String, String)>,
+}
+
+impl CircuitPermit {
+    /// Records the request outcome. Only errors that suggest the endpoint is
+    /// down count as failures; other errors show it is answering.
+    pub fn record<T>(self, result: &Result<T>) {
+        match result {
+            Ok(_) => self.record_success(),
+            Err(e) => self.record_error(e),
+        }
+    }
+
+    /// Records that the endpoint answered.
+    pub fn record_success(mut self) {
+        if let Some(key) = self.key.take() {
+            self.breaker.record(key, false);
+        }
+    }
+
+    /// Records a failed request, counting it only if `error` suggests the
+    /// endpoint is down.
+    pub fn record_error(mut self, error: &ProxyError) {
+        if let Some(key) = self.key.take() {
+            self.breaker.record(key, is_endpoint_failure(error));
+        }
+    }
+}
+
+/// Whether an error suggests the endpoint is down: any 5xx or 529 response,
+/// a timeout or a connection failure. Other 4xx responses show it answering.
+fn is_endpoint_failure(error: &ProxyError) -> bool {
+    match error {
+        ProxyError::ServiceUnavailable(_) | ProxyError::Overloaded(_) => true,
+        ProxyError::UpstreamStatus(status, _) => *status >= 500,
+        ProxyError::Http(e) => e.is_timeout() || e.is_connect(),
+        _ => false,
+    }
+}
+
+impl Drop for CircuitPermit {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            if let Some(circuit) = self.breaker.circuits.lock().get_mut(&key) {
+                circuit.probing = false;
+            }
+        }
+    }
+}
+
+impl CircuitBreaker {
+    /// Creates a breaker using the system clock.
+    pub fn new(config: &CircuitBreakerConfig) -> Self {
+        Self::with_clock(config, Arc::new(SystemClock))
+    }
+
+    /// Creates a breaker reading time from `clock`.
+    pub fn with_clock(config: &CircuitBreakerConfig, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            config: config.clone(),
+            circuits: Mutex::new(HashMap::new()),
+            clock,
+        }
+    }
+
+    fn open_duration(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.config.open_seconds as i64)
+    }
+
+    fn state_of(&self, circuit: &Circuit, now: DateTime<Utc>) -> CircuitState {
+        match circuit.opened_at {
+            None => CircuitState::Closed,
+            Some(opened_at) if now < opened_at + self.open_duration() => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Asks to send a request for `model` to `endpoint`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProxyError::ServiceUnavailable` while the circuit is open, or
+    /// while it is half-open and the probe is already in flight.
+    pub fn try_acquire(self: &Arc<Self>, endpoint: &str, model: &str) -> Result<CircuitPermit> {
+        if !self.config.enabled {
+            return Ok(CircuitPermit {
+                breaker: self.clone(),
+                key: None,
+            });
+        }
+
+        let key = (endpoint.to_string(), model.to_string());
+        let now = self.clock.now();
+        let mut circuits = self.circuits.lock();
+        let circuit = circuits.entry(key.clone()).or_default();
+
+        match self.state_of(circuit, now) {
+            CircuitState::Closed => {}
+            CircuitState::Open => {
+                let retry_in = circuit
+                    .opened_at
+                    .map(|opened_at| (opened_at + self.open_duration() - now).num_seconds())
+                    .unwrap_or_default();
+                return Err(ProxyError::ServiceUnavailable(format!(
+                    "Circuit open for {} at {} after repeated failures; retrying in {}s",
+                    model, endpoint, retry_in
+                )));
+            }
+            CircuitState::HalfOpen if circuit.probing => {
+                return Err(ProxyError::ServiceUnavailable(format!(
+                    "Circuit half-open for {} at {}; waiting for the probe request",
+                    model, endpoint
+                )));
+            }
+            CircuitState::HalfOpen => {
+                info!(
+                    "Circuit half-open for {} at {}, sending probe",
+                    model, endpoint
+                );
+                circuit.probing = true;
+                crate::metrics::record_circuit_state(
+                    endpoint,
+                    model,
+                    CircuitState::HalfOpen.gauge_value(),
+                );
+            }
+        }
+
+        Ok(CircuitPermit {
+            breaker: self.clone(),
+            key: Some(key),
+        })
+    }
+
+    /// Records an error that surfaced after the request's permit was spent,
+    /// such as a stream stalling after its first chunk.
+    pub fn record_late_error(&self, endpoint: &str, model: &str, error: &ProxyError) {
+        if self.config.enabled && is_endpoint_failure(error) {
+            self.record((endpoint.to_string(), model.to_string()), true);
+        }
+    }
+
+    fn record(&self, key: (String, String), failed: bool) {
+        let now = self.clock.now();
+        let mut circuits = self.circuits.lock();
+        let circuit = circuits.entry(key.clone()).or_default();
+        let (endpoint, model) = &key;
+        let was_open = circuit.opened_at.is_some();
+        circuit.probing = false;
+
+        let state = if failed {
+            circuit.failures += 1;
+            if was_open || circuit.failures >= self.config.failure_threshold {
+                warn!(
+                    "Circuit opened for {} at {} after {} consecutive failures",
+                    model, endpoint, circuit.failures
+                );
+                circuit.opened_at = Some(now);
+                CircuitState::Open
+            } else {
+                CircuitState::Closed
+            }
+        } else {
+            if was_open {
+                info!("Circuit closed for {} at {}", model, endpoint);
+            }
+            circuit.failures = 0;
+            circuit.opened_at = None;
+            CircuitState::Closed
+        };
+        crate::metrics::record_circuit_state(endpoint, model, state.gauge_value());
+    }
+
+    /// Returns every circuit that is currently open or half-open.
+    pub fn tripped(&self) -> Vec<CircuitStatus> {
+        let now = self.clock.now();
+        let mut tripped: Vec<CircuitStatus> = self
+            .circuits
+            .lock()
+            .iter()
+            .map(|((endpoint, model), circuit)| CircuitStatus {
+                endpoint: endpoint.clone(),
+                model: model.clone(),
+                state: self.state_of(circuit, now),
+            })
+            .filter(|status| status.state != CircuitState::Closed)
+            .collect();
+        tripped.sort_by(|a, b| (&a.endpoint, &a.model).cmp(&(&b.endpoint, &b.model)));
+        tripped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::clock::MockClock;
+
+    const ENDPOINT: &str = "https://cloudcode-pa.googleapis.com/v1internal";
+    const MODEL: &str = "gemini-2.5-pro";
+
+    fn breaker() -> (Arc<MockClock>, Arc<CircuitBreaker>) {
+        let clock = Arc::new(MockClock::new(Utc::now()));
+        let breaker = Arc::new(CircuitBreaker::with_clock(
+            &CircuitBreakerConfig {
+                enabled: true,
+                failure_threshold: 3,
+                open_seconds: 30,
+            },
+            clock.clone(),
+        ));
+        (clock, breaker)
+    }
+
+    fn unavailable() -> Result<()> {
+        Err(ProxyError::ServiceUnavailable("timeout".to_string()))
+    }
+
+    #[test]
+    fn test_opens_after_consecutive_failures() {
+        let (_clock, breaker) = breaker();
+
+        for _ in 0..2 {
+            breaker
+                .try_acquire(ENDPOINT, MODEL)
+                .unwrap()
+                .record(&unavailable());
+        }
+        // A response in between resets the count
+        breaker
+            .try_acquire(ENDPOINT, MODEL)
+            .unwrap()
+            .record::<()>(&Err(ProxyError::InvalidRequest("bad".to_string())));
+        for _ in 0..2 {
+            breaker
+                .try_acquire(ENDPOINT, MODEL)
+                .unwrap()
+                .record(&unavailable());
+        }
+        assert!(breaker.tripped().is_empty());
+
+        breaker
+            .try_acquire(ENDPOINT, MODEL)
+            .unwrap()
+            .record(&unavailable());
+        assert!(matches!(
+            breaker.try_acquire(ENDPOINT, MODEL),
+            Err(ProxyError::ServiceUnavailable(_))
+        ));
+        assert_eq!(breaker.tripped()[0].state, CircuitState::Open);
+
+        // Other models on the endpoint are unaffected
+        assert!(breaker.try_acquire(ENDPOINT, "gemini-2.5-flash").is_ok());
+    }
+
+    #[test]
+    fn test_server_errors_open_circuit() {
+        let (_clock, breaker) = breaker();
+        let server_error = || -> Result<()> {
+            Err(crate::gemini::backend::upstream_error(
+                500,
+                "Internal error encountered.".to_string(),
+            ))
+        };
+
+        for _ in 0..3 {
+            breaker
+                .try_acquire(ENDPOINT, MODEL)
+                .unwrap()
+                .record(&server_error());
+        }
+        assert_eq!(breaker.tripped()[0].state, CircuitState::Open);
+
+        // Client errors show the endpoint answering
+        for _ in 0..3 {
+            breaker
+                .try_acquire(ENDPOINT, "gemini-2.5-flash")
+                .unwrap()
+                .record::<()>(&Err(crate::gemini::backend::upstream_error(
+                    400,
+                    "Invalid argument".to_string(),
+                )));
+        }
+        assert_eq!(breaker.tripped().len(), 1);
+    }
+
+    #[test]
+    fn test_half_open_probe() {
+        let (clock, breaker) = breaker();
+        for _ in 0..3 {
+            breaker
+                .try_acquire(ENDPOINT, MODEL)
+                .unwrap()
+                .record(&unavailable());
+        }
+
+        clock.advance(chrono::Duration::seconds(31));
+        assert_eq!(breaker.tripped()[0].state, CircuitState::HalfOpen);
+
+        // Only one probe at a time; a failed probe reopens immediately
+        let probe = breaker.try_acquire(ENDPOINT, MODEL).unwrap();
+        assert!(breaker.try_acquire(ENDPOINT, MODEL).is_err());
+        probe.record(&unavailable());
+        assert_eq!(breaker.tripped()[0].state, CircuitState::Open);
+
+        // An abandoned probe frees the slot
+        clock.advance(chrono::Duration::seconds(31));
+        drop(breaker.try_acquire(ENDPOINT, MODEL).unwrap());
+
+        // A successful probe closes the circuit
+        breaker
+            .try_acquire(ENDPOINT, MODEL)
+            .unwrap()
+            .record(&Ok(()));
+        assert!(breaker.tripped().is_empty());
+        assert!(breaker.try_acquire(ENDPOINT, MODEL).is_ok());
+    }
+
+    #[test]
+    fn test_disabled() {
+        let breaker = Arc::new(CircuitBreaker::new(&CircuitBreakerConfig {
+            enabled: false,
+            ..CircuitBreakerConfig::default()
+        }));
+        for _ in 0..10 {
+            breaker
+                .try_acquire(ENDPOINT, MODEL)
+                .unwrap()
+                .record(&unavailable());
+        }
+        assert!(breaker.try_acquire(ENDPOINT, MODEL).is_ok());
+    }
+}
@@ -9,13 +9,28 @@
 
 // Author: kelexine (https://github.com/kelexine)
 
-use super::{ProjectResolutionRequest, ProjectResolutionResponse};
-use crate::config::GeminiConfig;
+use super::backend::{ResponseEnvelope, UpstreamBackend};
+use super::project_cache::{ProjectCache, ProjectSetup};
+use super::streaming::GeminiStream;
+use super::{
+    ClientMetadata, OnboardOperation, OnboardUserRequest, ProjectResolutionRequest,
+    ProjectResolutionResponse,
+};
+use crate::config::{BackendKind, GeminiConfig};
 use crate::error::{ProxyError, Result};
 use crate::oauth::OAuthManager;
+use async_trait::async_trait;
 use reqwest::Client;
+use std::sync::Arc;
 use std::time::Duration;
-use tracing::{debug, error, info};
+use tokio::sync::OnceCell;
+use tracing::{debug, error, info, warn};
+
+/// Delay between polls of the onboarding operation.
+const ONBOARD_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Polls before onboarding is considered stuck (two minutes).
+const ONBOARD_MAX_POLLS: u32 = 24;
 
 /// Client for the Google Gemini API.
 ///
@@ -26,23 +41,40 @@ pub struct GeminiClient {
     /// Optimized reqwest client with pooling and keep-alives.
     http_client: Client,
     /// Configuration settings for the Gemini API.
-    #[allow(dead_code)]
     config: GeminiConfig,
     /// Manager for Google OAuth2 credentials and token refreshes.
     oauth_manager: OAuthManager,
-    /// The resolved Google Cloud Project ID.
-    project_id: String,
+    /// Pool account name, used as the project cache key.
+    account: String,
+    /// Fingerprint of the account's credentials, recorded in the project cache.
+    credentials: String,
+    /// Project requested via `gemini.project_id` or `GOOGLE_CLOUD_PROJECT`.
+    requested_project: Option<String>,
+    /// The resolved project and tier, set once resolution succeeds. Replaced
+    /// with an empty cell when the upstream rejects the project.
+    project: parking_lot::RwLock<Arc<OnceCell<ProjectSetup>>>,
+    /// Why the most recent resolution attempt failed, if it did.
+    project_error: parking_lot::Mutex<Option<String>>,
+    /// On-disk cache of resolved projects (`None` when disabled).
+    project_cache: Option<ProjectCache>,
     /// Service tracking model health to avoid routing to failed models.
     availability_service: super::ModelAvailabilityService,
+    /// Latest remaining quota per model from `retrieveUserQuota`.
+    quota: super::QuotaCache,
+    /// Delay between polls of the onboarding operation.
+    onboard_poll_interval: Duration,
 }
 
 impl GeminiClient {
-    /// Create a new Gemini client and resolve project ID via `loadCodeAssist`.
+    /// Create a new Gemini client.
     ///
     /// This method will:
     /// 1. Configure an optimized HTTP client with connection pooling
-    /// 2. Authenticate using the OAuth manager
-    /// 3. Call the `loadCodeAssist` endpoint to resolve the GCP project ID
+    /// 2. Reuse the project cached for the account, if still fresh
+    ///
+    /// Otherwise the GCP project is resolved via `loadCodeAssist` on first use
+    /// (see [`GeminiClient::ensure_project`]), so creating a client never
+    /// blocks on the network.
     ///
     /// # Arguments
     ///
@@ -51,108 +83,308 @@ impl GeminiClient {
     ///
     /// # Errors
     ///
-    /// Returns `ProxyError::ProjectResolution` if the project ID cannot be determined.
+    /// Returns `ProxyError::Http` if the HTTP client cannot be built.
     pub async fn new(config: &GeminiConfig, oauth_manager: OAuthManager) -> Result<Self> {
-        // Configure HTTP client for optimal streaming performance
-        // We use a custom pool and keep-alive to minimize handshake overhead
-        let http_client = Client::builder()
-            .timeout(Duration::from_secs(config.timeout_seconds))
-            .connect_timeout(Duration::from_secs(10))
-            .pool_max_idle_per_host(10)
-            .pool_idle_timeout(Duration::from_secs(90))
-            .tcp_keepalive(Some(Duration::from_secs(60)))
-            .tcp_nodelay(true)
-            .use_rustls_tls()
-            .build()
-            .map_err(|e| ProxyError::Internal(format!("Failed to create HTTP client: {}", e)))?;
+        Self::new_for_account(config, oauth_manager, super::pool::DEFAULT_ACCOUNT).await
+    }
 
+    /// Create a client for a named pool account.
+    ///
+    /// Identical to [`GeminiClient::new`], except that availability metrics are
+    /// labelled with `account` and non-default accounts persist their state to
+    /// `state.<account>.json` beside `gemini.state_file`.
+    pub async fn new_for_account(
+        config: &GeminiConfig,
+        oauth_manager: OAuthManager,
+        account: &str,
+    ) -> Result<Self> {
+        let http_client = super::backend::http_client(config)?;
         debug!("Created HTTP client with connection pooling and keep-alive");
 
-        // Resolve project ID via loadCodeAssist (required for subsequent API calls)
-        let project_id =
-            Self::resolve_project_id(&http_client, &config.api_base_url, &oauth_manager).await?;
-
-        info!("Successfully resolved project ID: {}", project_id);
+        let requested_project = config
+            .project_id
+            .clone()
+            .or_else(|| std::env::var("GOOGLE_CLOUD_PROJECT").ok())
+            .filter(|project| !project.is_empty());
+        let credentials = oauth_manager.fingerprint().await;
+        let project_cache = ProjectCache::from_config(config);
+        let cached = project_cache
+            .as_ref()
+            .and_then(|cache| cache.load(account, &credentials, requested_project.as_deref()));
+        if let Some(setup) = &cached {
+            info!(
+                "Using cached project ID for account {}: {}",
+                account, setup.project_id
+            );
+        }
 
-        let availability_service = super::ModelAvailabilityService::new();
+        let availability_service = super::backend::availability_for(config, account);
 
         Ok(Self {
             http_client,
             config: config.clone(),
             oauth_manager,
-            project_id,
+            account: account.to_string(),
+            credentials,
+            requested_project,
+            project: parking_lot::RwLock::new(Arc::new(OnceCell::new_with(cached))),
+            project_error: parking_lot::Mutex::new(None),
+            project_cache,
             availability_service,
+            quota: super::QuotaCache::new(account),
+            onboard_poll_interval: ONBOARD_POLL_INTERVAL,
         })
     }
 
+    /// Returns the project ID, resolving it first if needed.
+    ///
+    /// Concurrent callers share a single resolution. A failed resolution is
+    /// retried by the next caller.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProxyError::ServiceUnavailable` while Code Assist cannot be
+    /// reached (e.g. offline), or `ProxyError::ProjectResolution` if the
+    /// project ID cannot be determined.
+    pub async fn ensure_project(&self) -> Result<String> {
+        let project = self.project.read().clone();
+        let setup = project
+            .get_or_try_init(|| async {
+                match self.resolve_project_id().await {
+                    Ok(setup) => {
+                        info!(
+                            "Account {} resolved project ID: {}",
+                            self.account, setup.project_id
+                        );
+                        *self.project_error.lock() = None;
+                        if let Some(cache) = &self.project_cache {
+                            cache.store(
+                                &self.account,
+                                &self.credentials,
+                                self.requested_project.as_deref(),
+                                &setup,
+                            );
+                        }
+                        Ok(setup)
+                    }
+                    Err(e) => {
+                        *self.project_error.lock() = Some(e.to_string());
+                        Err(e)
+                    }
+                }
+            })
+            .await?;
+        Ok(setup.project_id.clone())
+    }
+
+    /// Forgets the project when `result` shows the upstream rejecting it (an
+    /// error naming the project), both in memory and in the project cache, so
+    /// that the next request resolves it again.
+    fn check_project<T>(&self, result: Result<T>) -> Result<T> {
+        if let Err(ProxyError::UpstreamStatus(status, body)) = &result {
+            let rejected =
+                matches!(status, 400 | 403 | 404) && body.to_lowercase().contains("project");
+            if rejected {
+                warn!(
+                    "Upstream rejected the project of account {} (HTTP {}); resolving it again",
+                    self.account, status
+                );
+                *self.project.write() = Arc::new(OnceCell::new());
+                if let Some(cache) = &self.project_cache {
+                    cache.remove(&self.account);
+                }
+            }
+        }
+        result
+    }
+
     /// Resolve Cloud AI Companion project ID via loadCodeAssist
     ///
     /// This is a critical bootstrap step. Google's internal APIs often require
     /// an explicit project ID in the payload, even when using user-level credentials.
-    async fn resolve_project_id(
-        client: &Client,
-        base_url: &str,
-        oauth_manager: &OAuthManager,
-    ) -> Result<String> {
-        let url = format!("{}:loadCodeAssist", base_url);
-        let request_payload = ProjectResolutionRequest::default();
+    /// A configured project is sent along and used when Code Assist assigns none.
+    ///
+    /// Accounts that have not been onboarded yet (no current tier, as for a
+    /// brand-new free-tier account) are onboarded to their default tier first,
+    /// as Gemini CLI does on first launch.
+    async fn resolve_project_id(&self) -> Result<ProjectSetup> {
+        let load = self.load_code_assist().await?;
+        let current_tier = load.current_tier.as_ref().map(|tier| tier.id.clone());
+
+        if let Some(project_id) = load
+            .cloudaicompanion_project
+            .clone()
+            .or_else(|| current_tier.as_ref().and(self.requested_project.clone()))
+        {
+            debug!("Account is on tier {:?}", current_tier);
+            return Ok(ProjectSetup {
+                project_id,
+                tier: current_tier,
+            });
+        }
+        if let Some(tier) = current_tier {
+            return Err(ProxyError::ProjectResolution(
+                load.ineligible_reasons().unwrap_or_else(|| {
+                    format!(
+                        "Account is on tier {} but has no Google Cloud project; \
+                         set gemini.project_id or GOOGLE_CLOUD_PROJECT",
+                        tier
+                    )
+                }),
+            ));
+        }
+
+        let tier = load.onboard_tier();
+        info!(
+            "Account is not onboarded yet; onboarding to tier {}",
+            tier.id
+        );
+        let project_id = self
+            .onboard_user(&tier)
+            .await?
+            .or_else(|| self.requested_project.clone());
+        match project_id {
+            Some(project_id) => Ok(ProjectSetup {
+                project_id,
+                tier: Some(tier.id),
+            }),
+            None => Err(ProxyError::ProjectResolution(
+                load.ineligible_reasons().unwrap_or_else(|| {
+                    format!(
+                        "Onboarding to tier {} did not assign a Google Cloud project; \
+                         this tier requires your own project (gemini.project_id)",
+                        tier.id
+                    )
+                }),
+            )),
+        }
+    }
+
+    /// Client metadata naming the requested project, if any.
+    fn client_metadata(&self, project: Option<String>) -> ClientMetadata {
+        ClientMetadata {
+            duet_project: project,
+            ..ClientMetadata::default()
+        }
+    }
+
+    /// Calls `loadCodeAssist`, retrying transient failures.
+    async fn load_code_assist(&self) -> Result<ProjectResolutionResponse> {
+        let url = format!("{}:loadCodeAssist", self.config.api_base_url);
+        let request_payload = ProjectResolutionRequest {
+            cloudaicompanion_project: self.requested_project.clone(),
+            metadata: self.client_metadata(self.requested_project.clone()),
+        };
 
         debug!("Resolving project ID via {}", url);
 
-        // Clone for retry closure
-        let client = client.clone();
-        let url = url.clone();
-        let request_payload = request_payload.clone();
-        let oauth_manager = oauth_manager.clone();
-
-        crate::utils::retry::with_retry(
-            "Project Resolution",
-            || async {
-                let access_token = oauth_manager.get_token().await
-                    .map_err(|e| (500, format!("OAuth error: {}", e)))?;
-
-                let response = client
-                    .post(&url)
-                    .header("Authorization", format!("Bearer {}", access_token))
-                    .header("Content-Type", "application/json")
-                    .json(&request_payload)
-                    .send()
-                    .await
-                    .map_err(|e| (500, format!("HTTP error: {}", e)))?;
-
-                let status = response.status();
-                let response_text = response.text().await.unwrap_or_default();
-                if !status.is_success() {
-                    let error_msg = Self::extract_error_message(&response_text)
-                        .unwrap_or_else(|| response_text.clone());
-                    return Err((status.as_u16(), error_msg));
-                }
+        let access_token = self.oauth_manager.get_token().await?;
+        crate::utils::retry::with_retry("Project Resolution", || async {
+            let response = self
+                .http_client
+                .post(&url)
+                .header("Authorization", format!("Bearer {}", access_token))
+                .header("Content-Type", "application/json")
+                .json(&request_payload)
+                .send()
+                .await
+                .map_err(|e| (0, e.to_string()))?;
 
-                let project_response: ProjectResolutionResponse = serde_json::from_str(&response_text)
-                    .map_err(|e| (500, format!("Invalid response: {}", e)))?;
-
-                match project_response.cloudaicompanion_project {
-                    Some(project_id) => Ok(project_id),
-                    None => {
-                        let error_msg = Self::extract_error_message(&response_text)
-                            .unwrap_or_else(|| {
-                                "Account check failed: No Gemini Pro subscription detected.\n\
-                                 Please ensure you are using an account with 'Google One AI Premium' or 'Gemini Advanced'."
-                                 .to_string()
-                            });
-                        Err((403, error_msg))
-                    }
-                }
+            let status = response.status();
+            let response_text = response.text().await.unwrap_or_default();
+            if !status.is_success() {
+                let error_msg = Self::extract_error_message(&response_text)
+                    .unwrap_or_else(|| response_text.clone());
+                return Err((status.as_u16(), error_msg));
             }
-        )
+
+            serde_json::from_str::<ProjectResolutionResponse>(&response_text)
+                .map_err(|e| (500, format!("Invalid response: {}", e)))
+        })
         .await
-        .map_err(|(status, body)| match status {
-            403 => ProxyError::ProjectResolution(body),
+        .map_err(Self::resolution_error)
+    }
+
+    /// Runs the `onboardUser` long-running operation to completion, returning
+    /// the project it assigned.
+    async fn onboard_user(&self, tier: &super::UserTier) -> Result<Option<String>> {
+        let base_url = &self.config.api_base_url;
+        let onboard_url = format!("{}:onboardUser", base_url);
+        // The free tier always uses a Google-managed project
+        let project = (tier.id != super::FREE_TIER)
+            .then(|| self.requested_project.clone())
+            .flatten();
+        let request = OnboardUserRequest {
+            tier_id: tier.id.clone(),
+            cloudaicompanion_project: project.clone(),
+            metadata: self.client_metadata(project),
+        };
+
+        let mut operation: OnboardOperation = self
+            .call(self.http_client.post(&onboard_url).json(&request))
+            .await?;
+        let mut polls = 0;
+        while !operation.done {
+            if polls == ONBOARD_MAX_POLLS {
+                return Err(ProxyError::ProjectResolution(format!(
+                    "Onboarding to tier {} did not complete in time; try again later",
+                    tier.id
+                )));
+            }
+            polls += 1;
+            tokio::time::sleep(self.onboard_poll_interval).await;
+
+            // Poll the operation when it has a name, otherwise repeat the call
+            let next = match &operation.name {
+                Some(name) => self.http_client.get(format!("{}/{}", base_url, name)),
+                None => self.http_client.post(&onboard_url).json(&request),
+            };
+            operation = self.call(next).await?;
+        }
+
+        info!("Onboarding to tier {} completed", tier.id);
+        Ok(operation
+            .response
+            .and_then(|response| response.cloudaicompanion_project)
+            .map(|project| project.id))
+    }
+
+    /// Sends an authenticated Code Assist setup call and parses the JSON reply.
+    async fn call<T: serde::de::DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<T> {
+        let access_token = self.oauth_manager.get_token().await?;
+        let response = request
+            .bearer_auth(access_token)
+            .send()
+            .await
+            .map_err(|e| Self::resolution_error((0, e.to_string())))?;
+
+        let status = response.status();
+        let response_text = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            let error_msg = Self::extract_error_message(&response_text).unwrap_or(response_text);
+            return Err(Self::resolution_error((status.as_u16(), error_msg)));
+        }
+        serde_json::from_str(&response_text)
+            .map_err(|e| ProxyError::ProjectResolution(format!("Invalid response: {}", e)))
+    }
+
+    /// Maps a failed setup call to a `ProxyError`.
+    fn resolution_error((status, body): (u16, String)) -> ProxyError {
+        match status {
+            // Connection failures, e.g. while offline; resolution is retried later
+            0 => ProxyError::ServiceUnavailable(format!(
+                "Cannot reach Code Assist to resolve the project ID yet: {}",
+                body
+            )),
             429 => ProxyError::TooManyRequests(body),
             529 => ProxyError::Overloaded(format!("Gemini API overloaded: {}", body)),
             503 | 504 => ProxyError::ServiceUnavailable(format!("Upstream unavailable: {}", body)),
+            403 => ProxyError::ProjectResolution(body),
             _ => ProxyError::ProjectResolution(format!("HTTP {}: {}", status, body)),
-        })
+        }
     }
 
     /// Extracts a user-friendly error message from a Google API JSON response.
@@ -176,9 +408,18 @@ impl GeminiClient {
         None
     }
 
-    /// Returns the resolved Google Cloud Project ID.
-    pub fn project_id(&self) -> &str {
-        &self.project_id
+    /// Returns the resolved Google Cloud Project ID, or `None` while it is
+    /// still being resolved.
+    pub fn project_id(&self) -> Option<String> {
+        self.project
+            .read()
+            .get()
+            .map(|setup| setup.project_id.clone())
+    }
+
+    /// Returns why the last project resolution attempt failed, if it did.
+    pub fn project_error(&self) -> Option<String> {
+        self.project_error.lock().clone()
     }
 
     /// Returns the internal HTTP client. Useful for low-level stream testing.
@@ -186,11 +427,61 @@ impl GeminiClient {
         &self.http_client
     }
 
+    /// Returns the account's Code Assist tier, when known.
+    pub fn tier(&self) -> Option<String> {
+        self.project
+            .read()
+            .get()
+            .and_then(|setup| setup.tier.clone())
+    }
+
     /// Returns the active OAuth manager.
     pub fn oauth_manager(&self) -> &OAuthManager {
         &self.oauth_manager
     }
 
+    /// Returns the service tracking upstream model health.
+    pub fn availability(&self) -> &super::ModelAvailabilityService {
+        &self.availability_service
+    }
+
+    /// Returns the most recently retrieved quota.
+    pub fn quota(&self) -> &super::QuotaCache {
+        &self.quota
+    }
+
+    /// Queries the remaining quota per model via `retrieveUserQuota` and
+    /// refreshes the cache.
+    pub async fn refresh_quota(&self) -> Result<()> {
+        use super::quota::{RetrieveUserQuotaRequest, RetrieveUserQuotaResponse};
+
+        let url = format!("{}:retrieveUserQuota", self.config.api_base_url);
+        let access_token = self.oauth_manager.get_token().await?;
+
+        let response = self
+            .http_client
+            .post(&url)
+            .bearer_auth(access_token)
+            .json(&RetrieveUserQuotaRequest {
+                project: self.ensure_project().await?,
+            })
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(super::backend::upstream_error(status.as_u16(), body));
+        }
+
+        let quota: RetrieveUserQuotaResponse = response
+            .json()
+            .await
+            .map_err(|e| ProxyError::GeminiApi(format!("Invalid quota response: {}", e)))?;
+        debug!("Retrieved {} quota bucket(s)", quota.buckets.len());
+        self.quota.update(&quota.buckets);
+        Ok(())
+    }
+
     /// Returns the configured API base URL.
     pub fn base_url(&self) -> &str {
         &self.config.api_base_url
@@ -210,97 +501,27 @@ impl GeminiClient {
         request: crate::models::gemini::GenerateContentRequest,
         model: &str,
     ) -> Result<crate::models::gemini::GenerateContentResponse> {
-        // Track availability state (metrics only)
-        let is_available = self.availability_service.is_available(model);
-        if !is_available {
-            debug!(
-                "Requesting model {} which is marked unavailable/terminal",
-                model
-            );
-        }
-
         let url = format!("{}:generateContent", self.config.api_base_url);
         debug!("Calling generateContent API for model: {}", model);
 
         // Wrap request in internal API structure
-        let wrapped_request = crate::models::gemini::InternalApiRequest {
-            model: model.to_string(),
-            project: Some(self.project_id.clone()),
-            user_prompt_id: Some(format!("req_{}", uuid::Uuid::new_v4().simple())),
-            request,
-        };
-
-        let start_time = std::time::Instant::now();
+        let wrapped_request = self.wrap_request(request, model).await?;
         let access_token = self.oauth_manager.get_token().await?;
 
-        let response = self
+        let request = self
             .http_client
             .post(&url)
-            .header("Authorization", format!("Bearer {}", access_token))
-            .header("Content-Type", "application/json")
-            .json(&wrapped_request)
-            .send()
-            .await
-            .map_err(|e| ProxyError::GeminiApi(format!("HTTP error: {}", e)))?;
-
-        let duration = start_time.elapsed().as_secs_f64();
-        let status = response.status();
-
-        crate::metrics::record_gemini_call(model, status.as_u16(), false, duration);
-
-        if !status.is_success() {
-            let error_text = response.text().await.unwrap_or_default();
-            error!(
-                "Gemini API error: HTTP {} - Response body: {}",
-                status, error_text
-            );
-
-            // Update health scoring for subsequent requests
-            if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
-                let reason = if error_text.contains("Daily") {
-                    "daily_quota"
-                } else {
-                    "rate_limit"
-                };
-                crate::metrics::record_retry_attempt(model, reason);
-                if reason == "daily_quota" {
-                    self.availability_service
-                        .mark_terminal(model, error_text.clone());
-                } else {
-                    self.availability_service
-                        .mark_retry_once(model, error_text.clone());
-                }
-            } else if status.is_server_error() {
-                crate::metrics::record_retry_attempt(model, "server_error");
-            }
-
-            return Err(match status.as_u16() {
-                429 => ProxyError::TooManyRequests(format!(
-                    "Gemini API quota exceeded: {}",
-                    error_text
-                )),
-                529 => ProxyError::Overloaded(format!("Gemini API overloaded: {}", error_text)),
-                503 | 504 => {
-                    ProxyError::ServiceUnavailable(format!("Upstream unavailable: {}", error_text))
-                }
-                _ => ProxyError::GeminiApi(format!("HTTP {}: {}", status, error_text)),
-            });
-        }
-
-        self.availability_service.mark_healthy(model);
-
-        let response_text = response
-            .text()
-            .await
-            .map_err(|e| ProxyError::GeminiApi(format!("Failed to read response body: {}", e)))?;
-
-        let gemini_response: crate::models::gemini::GenerateContentResponse =
-            serde_json::from_str(&response_text).map_err(|e| {
-                error!("Failed to parse Gemini response: {}", e);
-                ProxyError::GeminiApi(format!("Response parsing error: {}", e))
-            })?;
-
-        Ok(gemini_response)
+            .bearer_auth(access_token)
+            .json(&wrapped_request);
+        let result = super::backend::send_generate(
+            request,
+            &self.availability_service,
+            model,
+            self.config.max_retries,
+            ResponseEnvelope::Wrapped,
+        )
+        .await;
+        self.check_project(result)
     }
 
     /// Executes a streaming content generation request using Server-Sent Events (SSE).
@@ -310,38 +531,66 @@ impl GeminiClient {
         &self,
         request: crate::models::gemini::GenerateContentRequest,
         model: &str,
-    ) -> Result<
-        impl futures::Stream<Item = Result<crate::models::gemini::GenerateContentResponse>> + Send,
-    > {
-        let is_available = self.availability_service.is_available(model);
-        if !is_available {
-            debug!(
-                "Streaming model {} which is marked unavailable/terminal",
-                model
-            );
-        }
-
+    ) -> Result<GeminiStream> {
         let url = format!("{}:streamGenerateContent?alt=sse", self.config.api_base_url);
         debug!("Calling streamGenerateContent API for model: {}", model);
 
-        let wrapped_request = crate::models::gemini::InternalApiRequest {
-            model: model.to_string(),
-            project: Some(self.project_id.clone()),
-            user_prompt_id: Some(format!("req_{}", uuid::Uuid::new_v4().simple())),
-            request,
-        };
-
-        let request_body = serde_json::to_string(&wrapped_request)
-            .map_err(|e| ProxyError::Internal(format!("Failed to serialize request: {}", e)))?;
+        let wrapped_request = self.wrap_request(request, model).await?;
+        let access_token = self.oauth_manager.get_token().await?;
 
-        crate::gemini::streaming::stream_generate_content(
-            &self.http_client,
-            url,
-            request_body,
-            &self.oauth_manager,
+        let request = self
+            .http_client
+            .post(&url)
+            .bearer_auth(access_token)
+            .json(&wrapped_request);
+        let result = crate::gemini::streaming::stream_generate_content(
+            request,
+            &self.availability_service,
             model,
+            self.config.max_retries,
+            ResponseEnvelope::Wrapped,
         )
-        .await
+        .await;
+        self.check_project(result)
+    }
+
+    /// Counts the input tokens of a request via `countTokens`.
+    ///
+    /// Code Assist counts only the conversation contents.
+    pub async fn count_tokens(
+        &self,
+        request: &crate::models::gemini::GenerateContentRequest,
+        model: &str,
+    ) -> Result<u32> {
+        let url = format!("{}:countTokens", self.config.api_base_url);
+        let access_token = self.oauth_manager.get_token().await?;
+
+        let request =
+            self.http_client
+                .post(&url)
+                .bearer_auth(access_token)
+                .json(&serde_json::json!({
+                    "request": {
+                        "model": format!("models/{}", model),
+                        "contents": request.contents,
+                    },
+                }));
+        super::backend::send_count_tokens(request).await
+    }
+
+    /// Wraps a request in the Code Assist envelope, resolving the project
+    /// first if needed.
+    async fn wrap_request(
+        &self,
+        request: crate::models::gemini::GenerateContentRequest,
+        model: &str,
+    ) -> Result<crate::models::gemini::InternalApiRequest> {
+        Ok(crate::models::gemini::InternalApiRequest {
+            model: model.to_string(),
+            project: Some(self.ensure_project().await?),
+            user_prompt_id: Some(format!("req_{}", uuid::Uuid::new_v4().simple())),
+            request,
+        })
     }
 
     /// Creates a persistent cached content entry in the Gemini API.
@@ -418,74 +667,496 @@ impl GeminiClient {
 
     /// Verifies the basic connectivity and authentication status of the Gemini API.
     ///
-    /// Sends a minimal placeholder request to confirm that the project resolution,
-    /// OAuth token acquisition, and upstream response cycle are functional.
-    pub async fn check_connectivity(&self) -> Result<Duration> {
-        let url = format!("{}:generateContent", self.config.api_base_url);
-        let start = std::time::Instant::now();
-
-        let request = crate::models::gemini::GenerateContentRequest {
-            contents: vec![crate::models::gemini::Content {
-                role: "user".to_string(),
-                parts: vec![crate::models::gemini::Part::Text {
-                    text: "hi".to_string(),
-                    thought: None,
-                    thought_signature: None,
-                }],
-            }],
-            system_instruction: None,
-            generation_config: Some(crate::models::gemini::GenerationConfig {
-                max_output_tokens: Some(1),
-                temperature: None,
-                top_p: None,
-                top_k: None,
-                stop_sequences: None,
-                candidate_count: None,
-                thinking_config: None,
-            }),
-            tools: None,
-            tool_config: None,
-            cached_content: None,
-        };
+    /// Counts the tokens of a placeholder prompt for `model`, which confirms
+    /// that OAuth token acquisition and the upstream response cycle work
+    /// without spending quota.
+    pub async fn check_connectivity(&self, model: &str) -> Result<Duration> {
+        let url = format!("{}:countTokens", self.config.api_base_url);
+        let access_token = self.oauth_manager.get_token().await?;
 
-        let wrapped_request = crate::models::gemini::InternalApiRequest {
-            model: "gemini-2.5-flash-lite".to_string(),
-            project: Some(self.project_id.clone()),
-            user_prompt_id: Some(format!("health_{}", uuid::Uuid::new_v4().simple())),
-            request,
-        };
+        let request =
+            self.http_client
+                .post(&url)
+                .bearer_auth(access_token)
+                .json(&serde_json::json!({
+                    "request": {
+                        "model": format!("models/{}", model),
+                        "contents": [{"role": "user", "parts": [{"text": "hi"}]}],
+                    },
+                }));
+        let latency = super::backend::send_probe(request).await?;
+        debug!("API connectivity check passed in {:?}", latency);
 
-        let access_token = self.oauth_manager.get_token().await?;
+        Ok(latency)
+    }
+}
 
-        let response = self
-            .http_client
-            .post(&url)
-            .header("Authorization", format!("Bearer {}", access_token))
-            .header("Content-Type", "application/json")
-            .json(&wrapped_request)
-            .timeout(Duration::from_secs(5))
-            .send()
-            .await
-            .map_err(|e| ProxyError::GeminiApi(format!("Health check request failed: {}", e)))?;
+#[async_trait]
+impl UpstreamBackend for GeminiClient {
+    fn kind(&self) -> BackendKind {
+        BackendKind::CodeAssist
+    }
 
-        let status = response.status();
-        if !status.is_success() {
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(ProxyError::GeminiApi(format!(
-                "API check failed: {}",
-                error_text
-            )));
-        }
+    fn endpoint(&self) -> &str {
+        &self.config.api_base_url
+    }
 
-        let latency = start.elapsed();
-        debug!("API connectivity check passed in {:?}", latency);
+    fn availability(&self) -> &super::ModelAvailabilityService {
+        &self.availability_service
+    }
 
-        Ok(latency)
+    fn code_assist(&self) -> Option<&GeminiClient> {
+        Some(self)
+    }
+
+    async fn generate_content(
+        &self,
+        request: crate::models::gemini::GenerateContentRequest,
+        model: &str,
+    ) -> Result<crate::models::gemini::GenerateContentResponse> {
+        GeminiClient::generate_content(self, request, model).await
+    }
+
+    async fn stream_generate_content(
+        &self,
+        request: crate::models::gemini::GenerateContentRequest,
+        model: &str,
+    ) -> Result<GeminiStream> {
+        GeminiClient::stream_generate_content(self, request, model).await
+    }
+
+    async fn count_tokens(
+        &self,
+        request: &crate::models::gemini::GenerateContentRequest,
+        model: &str,
+    ) -> Result<u32> {
+        GeminiClient::count_tokens(self, request, model).await
+    }
+
+    async fn check_connectivity(&self, model: &str) -> Result<Duration> {
+        GeminiClient::check_connectivity(self, model).await
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use crate::gemini::backend::test_support::{bare_response, first_text, hello_request};
+    use futures::StreamExt;
+
+    /// Connects a client to a mock Code Assist server with a valid token.
+    async fn connect(server: &mut mockito::Server) -> (GeminiClient, tempfile::NamedTempFile) {
+        server
+            .mock("POST", "/v1internal:loadCodeAssist")
+            .with_body(r#"{"cloudaicompanionProject": "mock-project"}"#)
+            .create_async()
+            .await;
+        let (oauth_manager, credentials) = oauth_manager().await;
+        let client = GeminiClient::new(&config(server), oauth_manager)
+            .await
+            .unwrap();
+        (client, credentials)
+    }
+
+    fn config(server: &mockito::Server) -> GeminiConfig {
+        GeminiConfig {
+            api_base_url: format!("{}/v1internal", server.url()),
+            max_retries: 0,
+            state_file: String::new(),
+            project_id: None,
+            project_cache_file: String::new(),
+            ..GeminiConfig::default()
+        }
+    }
+
+    /// Returns an OAuth manager holding a valid token.
+    async fn oauth_manager() -> (OAuthManager, tempfile::NamedTempFile) {
+        oauth_manager_for("refresh").await
+    }
+
+    /// Returns an OAuth manager for the credentials with `refresh_token`.
+    async fn oauth_manager_for(refresh_token: &str) -> (OAuthManager, tempfile::NamedTempFile) {
+        // NamedTempFile is created 0600, as OAuthManager requires
+        let credentials = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(
+            credentials.path(),
+            serde_json::json!({
+                "access_token": "ya29.code-assist",
+                "refresh_token": refresh_token,
+                "token_type": "Bearer",
+                "expiry_date": chrono::Utc::now().timestamp_millis() + 3_600_000,
+            })
+            .to_string(),
+        )
+        .unwrap();
+        let oauth_manager = OAuthManager::new(&crate::config::OAuthConfig {
+            credentials_path: credentials.path().to_string_lossy().to_string(),
+            ..crate::config::OAuthConfig::default()
+        })
+        .await
+        .unwrap();
+        (oauth_manager, credentials)
+    }
+
+    #[tokio::test]
+    async fn test_code_assist_backend() {
+        let mut server = mockito::Server::new_async().await;
+        let (client, _credentials) = connect(&mut server).await;
+        let wrapped = format!(r#"{{"response": {}}}"#, bare_response("hello"));
+        server
+            .mock("POST", "/v1internal:generateContent")
+            .match_header("authorization", "Bearer ya29.code-assist")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "project": "mock-project",
+            })))
+            .with_body(&wrapped)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/v1internal:streamGenerateContent")
+            .match_query(mockito::Matcher::UrlEncoded("alt".into(), "sse".into()))
+            .with_header("content-type", "text/event-stream")
+            .with_body(format!("data: {}\n\n", wrapped))
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/v1internal:countTokens")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "request": {"model": "models/gemini-2.5-pro"},
+            })))
+            .with_body(r#"{"totalTokens": 3}"#)
+            .create_async()
+            .await;
+
+        let backend: &dyn UpstreamBackend = &client;
+        assert_eq!(backend.kind(), BackendKind::CodeAssist);
+        // The project is resolved on first use
+        assert_eq!(client.project_id(), None);
+
+        let response = backend
+            .generate_content(hello_request(), "gemini-2.5-pro")
+            .await
+            .unwrap();
+        assert_eq!(first_text(&response), "hello");
+        assert_eq!(client.project_id().as_deref(), Some("mock-project"));
+
+        let chunks: Vec<_> = backend
+            .stream_generate_content(hello_request(), "gemini-2.5-pro")
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(first_text(chunks[0].as_ref().unwrap()), "hello");
+
+        assert_eq!(
+            backend
+                .count_tokens(&hello_request(), "gemini-2.5-pro")
+                .await
+                .unwrap(),
+            3
+        );
+        assert!(backend.check_connectivity("gemini-2.5-pro").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_quota() {
+        let mut server = mockito::Server::new_async().await;
+        let (client, _credentials) = connect(&mut server).await;
+        let mock = server
+            .mock("POST", "/v1internal:retrieveUserQuota")
+            .match_header("authorization", "Bearer ya29.code-assist")
+            .match_body(mockito::Matcher::Json(serde_json::json!({
+                "project": "mock-project",
+            })))
+            .with_body(
+                serde_json::json!({"buckets": [{
+                    "remainingAmount": "12",
+                    "remainingFraction": 0.06,
+                    "resetTime": "2026-01-16T08:00:00Z",
+                    "tokenType": "REQUESTS",
+                    "modelId": "gemini-2.5-pro",
+                }]})
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        client.refresh_quota().await.unwrap();
+
+        let quota = client.quota().get("gemini-2.5-pro").unwrap();
+        assert_eq!(quota.remaining, Some(12));
+        assert_eq!(quota.limit(), Some(200));
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_onboards_new_free_tier_account() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/v1internal:loadCodeAssist")
+            .with_body(
+                serde_json::json!({
+                    "allowedTiers": [
+                        {"id": "free-tier", "isDefault": true},
+                        {"id": "standard-tier", "userDefinedCloudaicompanionProject": true},
+                    ],
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+        let onboard = server
+            .mock("POST", "/v1internal:onboardUser")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "tierId": "free-tier",
+            })))
+            .with_body(r#"{"name": "operations/onboard-1", "done": false}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/v1internal/operations/onboard-1")
+            .with_body(r#"{"name": "operations/onboard-1", "done": false}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/v1internal/operations/onboard-1")
+            .with_body(
+                serde_json::json!({
+                    "name": "operations/onboard-1",
+                    "done": true,
+                    "response": {"cloudaicompanionProject": {"id": "free-project", "name": "Free"}},
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let (oauth_manager, _credentials) = oauth_manager().await;
+        let mut client = GeminiClient::new(&config(&server), oauth_manager)
+            .await
+            .unwrap();
+        client.onboard_poll_interval = Duration::from_millis(10);
+
+        assert_eq!(client.ensure_project().await.unwrap(), "free-project");
+        assert_eq!(client.tier().as_deref(), Some("free-tier"));
+        onboard.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_ineligible_account_reports_reason() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/v1internal:loadCodeAssist")
+            .with_body(
+                serde_json::json!({
+                    "currentTier": {"id": "standard-tier"},
+                    "ineligibleTiers": [{
+                        "reasonCode": "INELIGIBLE_ACCOUNT",
+                        "reasonMessage": "Your account is not eligible for the free tier",
+                        "tierId": "free-tier",
+                    }],
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let (oauth_manager, _credentials) = oauth_manager().await;
+        let client = GeminiClient::new(&config(&server), oauth_manager)
+            .await
+            .unwrap();
+
+        match client.ensure_project().await {
+            Err(ProxyError::ProjectResolution(message)) => {
+                assert!(message.contains("not eligible"), "{}", message)
+            }
+            _ => panic!("expected a project resolution error"),
+        }
+        assert!(client.project_id().is_none());
+        assert!(client.project_error().unwrap().contains("not eligible"));
+    }
+
+    #[tokio::test]
+    async fn test_offline_resolution_fails_fast_and_retries() {
+        let mut server = mockito::Server::new_async().await;
+        // Nothing listens on a port that was just released
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let (manager, _credentials) = oauth_manager().await;
+        let client = GeminiClient::new(
+            &GeminiConfig {
+                api_base_url: format!("http://127.0.0.1:{}/v1internal", port),
+                ..config(&server)
+            },
+            manager,
+        )
+        .await
+        .unwrap();
+        let result = client
+            .generate_content(hello_request(), "gemini-2.5-pro")
+            .await;
+        assert!(matches!(result, Err(ProxyError::ServiceUnavailable(_))));
+        assert!(client.project_error().is_some());
+
+        // A failed resolution is retried by the next caller
+        let (manager, _credentials) = oauth_manager().await;
+        let client = GeminiClient::new(&config(&server), manager).await.unwrap();
+        assert!(client.ensure_project().await.is_err());
+        server
+            .mock("POST", "/v1internal:loadCodeAssist")
+            .with_body(r#"{"cloudaicompanionProject": "mock-project"}"#)
+            .create_async()
+            .await;
+        assert_eq!(client.ensure_project().await.unwrap(), "mock-project");
+        assert!(client.project_error().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_configured_project_is_requested_and_cached() {
+        let mut server = mockito::Server::new_async().await;
+        let load = server
+            .mock("POST", "/v1internal:loadCodeAssist")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "cloudaicompanionProject": "my-project",
+                "metadata": {"duetProject": "my-project"},
+            })))
+            .with_body(r#"{"currentTier": {"id": "standard-tier"}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let cache_dir = tempfile::tempdir().unwrap();
+        let config = GeminiConfig {
+            project_id: Some("my-project".to_string()),
+            project_cache_file: cache_dir
+                .path()
+                .join("projects.json")
+                .to_string_lossy()
+                .to_string(),
+            ..config(&server)
+        };
+
+        let (manager, _credentials) = oauth_manager().await;
+        let client = GeminiClient::new(&config, manager).await.unwrap();
+        assert_eq!(client.ensure_project().await.unwrap(), "my-project");
+
+        // A second client starts with the cached project, without loadCodeAssist
+        let (manager, _credentials) = oauth_manager().await;
+        let client = GeminiClient::new(&config, manager).await.unwrap();
+        assert_eq!(client.project_id().as_deref(), Some("my-project"));
+        assert_eq!(client.tier().as_deref(), Some("standard-tier"));
+        load.assert_async().await;
+
+        // Other credentials do not reuse it
+        let (manager, _other) = oauth_manager_for("someone-else").await;
+        let other = GeminiClient::new(&config, manager).await.unwrap();
+        assert_eq!(other.project_id(), None);
+
+        // A rejected project is dropped from the cache
+        server
+            .mock("POST", "/v1internal:generateContent")
+            .with_status(403)
+            .with_body(
+                r#"{"error": {"message": "Permission denied on resource project my-project."}}"#,
+            )
+            .create_async()
+            .await;
+        assert!(client
+            .generate_content(hello_request(), "gemini-2.5-pro")
+            .await
+            .is_err());
+        let (manager, _credentials) = oauth_manager().await;
+        let client = GeminiClient::new(&config, manager).await.unwrap();
+        assert_eq!(client.project_id(), None);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_cached_project_is_resolved_again() {
+        let mut server = mockito::Server::new_async().await;
+        let cache_dir = tempfile::tempdir().unwrap();
+        let config = GeminiConfig {
+            project_cache_file: cache_dir
+                .path()
+                .join("projects.json")
+                .to_string_lossy()
+                .to_string(),
+            ..config(&server)
+        };
+        let (manager, _credentials) = oauth_manager().await;
+        ProjectCache::from_config(&config).unwrap().store(
+            crate::gemini::pool::DEFAULT_ACCOUNT,
+            &manager.fingerprint().await,
+            None,
+            &ProjectSetup {
+                project_id: "old-project".to_string(),
+                tier: None,
+            },
+        );
+        let client = GeminiClient::new(&config, manager).await.unwrap();
+        assert_eq!(client.project_id().as_deref(), Some("old-project"));
+
+        let load = server
+            .mock("POST", "/v1internal:loadCodeAssist")
+            .with_body(r#"{"cloudaicompanionProject": "new-project"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/v1internal:generateContent")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "model": "gemini-2.5-flash",
+            })))
+            .with_status(403)
+            .with_body(r#"{"error": {"message": "Permission denied on model."}}"#)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/v1internal:generateContent")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "model": "gemini-2.5-pro",
+                "project": "old-project",
+            })))
+            .with_status(403)
+            .with_body(
+                r#"{"error": {"message": "Permission denied on resource project old-project."}}"#,
+            )
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/v1internal:generateContent")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "project": "new-project",
+            })))
+            .with_body(format!(r#"{{"response": {}}}"#, bare_response("hello")))
+            .create_async()
+            .await;
+
+        // A 403 that does not name the project leaves it alone
+        assert!(client
+            .generate_content(hello_request(), "gemini-2.5-flash")
+            .await
+            .is_err());
+        assert_eq!(client.project_id().as_deref(), Some("old-project"));
+
+        // A rejected project is forgotten and the next call resolves it again
+        assert!(client
+            .generate_content(hello_request(), "gemini-2.5-pro")
+            .await
+            .is_err());
+        assert_eq!(client.project_id(), None);
+        let response = client
+            .generate_content(hello_request(), "gemini-2.5-pro")
+            .await
+            .unwrap();
+        assert_eq!(first_text(&response), "hello");
+        assert_eq!(client.project_id().as_deref(), Some("new-project"));
+        load.assert_async().await;
+    }
+
     #[test]
     fn test_project_resolution_request_format() {
         use super::ProjectResolutionRequest;
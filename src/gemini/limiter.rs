@@ -0,0 +1,238 @@
+//! Client-side concurrency and request rate limiting.
+//!
+//! Each (account, model) pair gets its own queue. A request first waits for a
+//! concurrency slot, then for room in the one-minute request window. Both waits
+//! are first-come first-served (tokio's semaphore and mutex queue waiters in
+//! order), so a burst of sub-agent requests is released in arrival order instead
+//! of all hitting Google at once and coming back as 429s.
+
+// Author: kelexine (https://github.com/kelexine)
+
+use crate::config::LimitsConfig;
+use crate::error::{ProxyError, Result};
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+use tracing::debug;
+
+/// Length of the request rate window.
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// A granted request slot. The concurrency slot is released on drop.
+#[derive(Debug)]
+pub struct RequestPermit {
+    _slot: Option<OwnedSemaphorePermit>,
+}
+
+/// Queue state for one (account, model) pair.
+struct ModelQueue {
+    /// Concurrency slots (`None` when unlimited).
+    slots: Option<Arc<Semaphore>>,
+    /// Requests allowed per window (`0` when unlimited).
+    requests_per_minute: u32,
+    /// Start times of requests in the current window. The async mutex makes
+    /// rate-limited waiters queue in order.
+    starts: tokio::sync::Mutex<VecDeque<Instant>>,
+}
+
+impl ModelQueue {
+    async fn acquire(&self) -> RequestPermit {
+        let slot = match &self.slots {
+            Some(slots) => Some(
+                slots
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("limiter semaphore is never closed"),
+            ),
+            None => None,
+        };
+
+        if self.requests_per_minute > 0 {
+            let mut starts = self.starts.lock().await;
+            loop {
+                let now = Instant::now();
+                while starts
+                    .front()
+                    .is_some_and(|&start| now.duration_since(start) >= WINDOW)
+                {
+                    starts.pop_front();
+                }
+                if starts.len() < self.requests_per_minute as usize {
+                    starts.push_back(now);
+                    break;
+                }
+                // The oldest start leaves the window first
+                tokio::time::sleep_until(starts[0] + WINDOW).await;
+            }
+        }
+
+        RequestPermit { _slot: slot }
+    }
+}
+
+/// Per-account, per-model request limiter.
+pub struct RequestLimiter {
+    config: LimitsConfig,
+    queues: Mutex<HashMap<(String, String), Arc<ModelQueue>>>,
+}
+
+impl RequestLimiter {
+    /// Creates a limiter with the configured defaults and model overrides.
+    pub fn new(config: &LimitsConfig) -> Self {
+        Self {
+            config: config.clone(),
+            queues: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the queue for an account and model, creating it on first use.
+    fn queue(&self, account: &str, model: &str) -> Arc<ModelQueue> {
+        self.queues
+            .lock()
+            .entry((account.to_string(), model.to_string()))
+            .or_insert_with(|| {
+                let limit = self.config.models.iter().find(|l| l.model == model);
+                let concurrency = limit
+                    .and_then(|l| l.max_concurrent_requests)
+                    .unwrap_or(self.config.max_concurrent_requests);
+                let requests_per_minute = limit
+                    .and_then(|l| l.requests_per_minute)
+                    .unwrap_or(self.config.requests_per_minute);
+                Arc::new(ModelQueue {
+                    slots: (concurrency > 0).then(|| Arc::new(Semaphore::new(concurrency))),
+                    requests_per_minute,
+                    starts: tokio::sync::Mutex::new(VecDeque::new()),
+                })
+            })
+            .clone()
+    }
+
+    /// Waits for a request slot for `model` on `account`.
+    ///
+    /// The time spent queued is recorded in `gemini_rate_limit_wait_seconds`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProxyError::Overloaded` if no slot frees up within
+    /// `limits.queue_timeout_seconds`.
+    pub async fn acquire(&self, account: &str, model: &str) -> Result<RequestPermit> {
+        let queue = self.queue(account, model);
+        let queued_at = Instant::now();
+        let timeout = Duration::from_secs(self.config.queue_timeout_seconds);
+
+        let result = tokio::time::timeout(timeout, queue.acquire()).await;
+        let waited = queued_at.elapsed();
+        crate::metrics::record_rate_limit_wait(model, waited.as_secs_f64());
+
+        match result {
+            Ok(permit) => {
+                if waited >= Duration::from_millis(100) {
+                    debug!(
+                        "Request for {} on account {} queued for {:.1}s",
+                        model,
+                        account,
+                        waited.as_secs_f64()
+                    );
+                }
+                Ok(permit)
+            }
+            Err(_) => Err(ProxyError::Overloaded(format!(
+                "Too many requests queued for {} on account {}; no slot within {}s",
+                model, account, self.config.queue_timeout_seconds
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ModelLimit;
+
+    fn limiter(max_concurrent_requests: usize, requests_per_minute: u32) -> Arc<RequestLimiter> {
+        Arc::new(RequestLimiter::new(&LimitsConfig {
+            max_concurrent_requests,
+            requests_per_minute,
+            queue_timeout_seconds: 120,
+            models: Vec::new(),
+        }))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_concurrency_queue_is_fifo() {
+        let limiter = limiter(1, 0);
+        let first = limiter.acquire("default", "gemini-2.5-pro").await.unwrap();
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut waiters = Vec::new();
+        for i in 0..3 {
+            let (limiter, order) = (limiter.clone(), order.clone());
+            waiters.push(tokio::spawn(async move {
+                let _permit = limiter.acquire("default", "gemini-2.5-pro").await.unwrap();
+                order.lock().push(i);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }));
+            // Let each waiter enqueue before spawning the next
+            tokio::task::yield_now().await;
+        }
+
+        // Other models and accounts have their own queues
+        limiter
+            .acquire("default", "gemini-2.5-flash")
+            .await
+            .unwrap();
+        limiter.acquire("second", "gemini-2.5-pro").await.unwrap();
+
+        drop(first);
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+        assert_eq!(*order.lock(), vec![0, 1, 2]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_requests_per_minute() {
+        let limiter = limiter(0, 2);
+        let start = Instant::now();
+
+        limiter.acquire("default", "gemini-2.5-pro").await.unwrap();
+        limiter.acquire("default", "gemini-2.5-pro").await.unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // The third request waits for the first to leave the window
+        limiter.acquire("default", "gemini-2.5-pro").await.unwrap();
+        assert!(start.elapsed() >= WINDOW);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_queue_timeout_is_overloaded() {
+        let limiter = RequestLimiter::new(&LimitsConfig {
+            max_concurrent_requests: 8,
+            requests_per_minute: 0,
+            queue_timeout_seconds: 5,
+            models: vec![ModelLimit {
+                model: "gemini-2.5-pro".to_string(),
+                max_concurrent_requests: Some(1),
+                requests_per_minute: None,
+            }],
+        });
+
+        let _held = limiter.acquire("default", "gemini-2.5-pro").await.unwrap();
+        let result = limiter.acquire("default", "gemini-2.5-pro").await;
+        assert!(matches!(result, Err(ProxyError::Overloaded(_))));
+
+        // The default limit still applies to other models
+        let _a = limiter
+            .acquire("default", "gemini-2.5-flash")
+            .await
+            .unwrap();
+        let _b = limiter
+            .acquire("default", "gemini-2.5-flash")
+            .await
+            .unwrap();
+    }
+}
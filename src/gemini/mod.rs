@@ -2,19 +2,34 @@
 // Author: kelexine (https://github.com/kelexine)
 
 pub mod availability;
+pub mod backend;
 pub mod cache_models;
+pub mod circuit_breaker;
 mod client;
+pub mod limiter;
+pub mod pool;
+pub mod project_cache;
+pub mod quota;
+pub mod sse;
 pub mod streaming;
+pub mod watchdog;
 
 pub use availability::ModelAvailabilityService;
+pub use backend::{BackendRegistry, UpstreamBackend};
 pub use cache_models::{CachedContentResponse, CreateCachedContentRequest};
+pub use circuit_breaker::{CircuitBreaker, CircuitPermit};
 pub use client::GeminiClient;
+pub use limiter::{RequestLimiter, RequestPermit};
+pub use pool::{Account, AccountPool};
+pub use project_cache::{ProjectCache, ProjectSetup};
+pub use quota::{ModelQuota, QuotaCache};
+pub use watchdog::StreamWatchdog;
 
 use serde::{Deserialize, Serialize};
 
 /// Request for project resolution (loadCodeAssist)
 /// Based on Gemini CLI source: packages/core/src/code_assist/types.ts
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct ProjectResolutionRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -36,23 +51,117 @@ pub struct ClientMetadata {
     pub duet_project: Option<String>,
 }
 
-/// Response from project resolution
-#[derive(Debug, Deserialize)]
+/// Response from project resolution (loadCodeAssist)
+#[derive(Debug, Default, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ProjectResolutionResponse {
     pub cloudaicompanion_project: Option<String>,
+    /// Tier the user is onboarded to; absent until onboarding has completed
+    pub current_tier: Option<UserTier>,
+    /// Tiers the user may onboard to
+    #[serde(default)]
+    pub allowed_tiers: Option<Vec<UserTier>>,
+    /// Tiers the user may not use, with the reason
+    #[serde(default)]
+    pub ineligible_tiers: Option<Vec<IneligibleTier>>,
+}
+
+impl ProjectResolutionResponse {
+    /// Tier to onboard to: the default allowed tier, or the legacy tier (which
+    /// needs a user-defined project) when none is marked default.
+    pub fn onboard_tier(&self) -> UserTier {
+        self.allowed_tiers
+            .iter()
+            .flatten()
+            .find(|tier| tier.is_default.unwrap_or(false))
+            .cloned()
+            .unwrap_or_else(|| UserTier {
+                id: LEGACY_TIER.to_string(),
+                name: None,
+                is_default: None,
+                user_defined_cloudaicompanion_project: Some(true),
+            })
+    }
+
+    /// Reasons given for ineligible tiers, joined for error messages.
+    pub fn ineligible_reasons(&self) -> Option<String> {
+        let reasons: Vec<&str> = self
+            .ineligible_tiers
+            .iter()
+            .flatten()
+            .filter_map(|tier| tier.reason_message.as_deref())
+            .collect();
+        (!reasons.is_empty()).then(|| reasons.join("; "))
+    }
+}
+
+/// Code Assist tier ID of the free tier
+pub const FREE_TIER: &str = "free-tier";
+
+/// Code Assist tier ID of the legacy tier
+pub const LEGACY_TIER: &str = "legacy-tier";
+
+/// A Code Assist user tier (free, legacy, standard)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserTier {
+    pub id: String,
+    pub name: Option<String>,
+    pub is_default: Option<bool>,
+    /// Whether the tier requires the user to supply their own project
+    pub user_defined_cloudaicompanion_project: Option<bool>,
+}
+
+/// A tier the user is not eligible for
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IneligibleTier {
+    pub reason_code: Option<String>,
+    pub reason_message: Option<String>,
+    pub tier_id: Option<String>,
+}
+
+/// Request for onboarding a user to a tier (onboardUser)
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OnboardUserRequest {
+    pub tier_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cloudaicompanion_project: Option<String>,
+    pub metadata: ClientMetadata,
+}
+
+/// Long-running operation returned by onboardUser and getOperation
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OnboardOperation {
+    /// Operation name to poll, e.g. `operations/123`
+    pub name: Option<String>,
+    #[serde(default)]
+    pub done: bool,
+    pub response: Option<OnboardUserResponse>,
+}
+
+/// Result of a completed onboarding operation
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OnboardUserResponse {
+    pub cloudaicompanion_project: Option<OnboardedProject>,
+}
+
+/// Project assigned during onboarding
+#[derive(Debug, Default, Deserialize)]
+pub struct OnboardedProject {
+    pub id: String,
 }
 
-impl Default for ProjectResolutionRequest {
+impl Default for ClientMetadata {
     fn default() -> Self {
         Self {
-            cloudaicompanion_project: None,
-            metadata: ClientMetadata {
-                ide_type: Some("GEMINI_CLI".to_string()),
-                platform: Some("PLATFORM_UNSPECIFIED".to_string()),
-                plugin_type: Some("GEMINI".to_string()),
-                duet_project: None,
-            },
+            ide_type: Some("GEMINI_CLI".to_string()),
+            platform: Some("PLATFORM_UNSPECIFIED".to_string()),
+            plugin_type: Some("GEMINI".to_string()),
+            duet_project: None,
         }
     }
 }
@@ -0,0 +1,419 @@
+//! Multi-account credential pool.
+//!
+//! Each pooled account has its own `OAuthManager`, Code Assist project and
+//! model availability state (all held by its Code Assist `GeminiClient`). Requests prefer
+//! the account that previously served their conversation, so server-side
+//! caching keeps working, and otherwise follow the configured strategy.
+
+// Author: kelexine (https://github.com/kelexine)
+
+use super::{GeminiClient, UpstreamBackend};
+use crate::config::{AppConfig, GeminiConfig, OAuthConfig, PoolConfig, PoolStrategy};
+use crate::error::{ProxyError, Result};
+use crate::models::anthropic::{MessageContent, MessagesRequest};
+use chrono::{DateTime, Utc};
+use lru::LruCache;
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tracing::{debug, info, warn};
+
+/// Name of the account built from `oauth.credentials_path` when no pool is configured.
+pub const DEFAULT_ACCOUNT: &str = "default";
+
+/// First delay before retrying a failed background project resolution.
+const PROJECT_RETRY_INITIAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Longest delay between background project resolution attempts.
+const PROJECT_RETRY_MAX: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// A single upstream credential: a Google account in the pool, or a named
+/// `[[backends]]` entry.
+pub struct Account {
+    /// Account name used in logs, metrics and `/health`.
+    name: String,
+    /// Backend bound to this account's credentials and project.
+    backend: Arc<dyn UpstreamBackend>,
+    /// When this account last hit a rate limit.
+    last_rate_limited: Mutex<Option<DateTime<Utc>>>,
+}
+
+impl Account {
+    /// Wraps a connected Code Assist client as a pool account.
+    pub fn new(name: impl Into<String>, client: GeminiClient) -> Self {
+        Self::with_backend(name, Arc::new(client))
+    }
+
+    /// Wraps any upstream backend.
+    pub fn with_backend(name: impl Into<String>, backend: Arc<dyn UpstreamBackend>) -> Self {
+        Self {
+            name: name.into(),
+            backend,
+            last_rate_limited: Mutex::new(None),
+        }
+    }
+
+    /// Returns the account name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the account's upstream backend.
+    pub fn backend(&self) -> &Arc<dyn UpstreamBackend> {
+        &self.backend
+    }
+
+    /// Returns when the account last hit a rate limit.
+    pub fn last_rate_limited(&self) -> Option<DateTime<Utc>> {
+        *self.last_rate_limited.lock()
+    }
+
+    /// Returns the account's last known quota for `model` (Code Assist only).
+    pub fn quota(&self, model: &str) -> Option<super::ModelQuota> {
+        self.backend.code_assist()?.quota().get(model)
+    }
+
+    /// Records that the account was just rate limited.
+    pub fn mark_rate_limited(&self) {
+        let now = Utc::now();
+        *self.last_rate_limited.lock() = Some(now);
+        crate::metrics::update_account_rate_limited(&self.name, now.timestamp());
+    }
+}
+
+/// Pool of accounts that requests are spread across.
+pub struct AccountPool {
+    accounts: Vec<Arc<Account>>,
+    strategy: PoolStrategy,
+    /// Round-robin cursor.
+    next: AtomicUsize,
+    /// Conversation key → account index, when sticky sessions are enabled.
+    sticky: Option<Mutex<LruCache<String, usize>>>,
+}
+
+impl AccountPool {
+    /// Builds the pool from already connected accounts.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `accounts` is empty.
+    pub fn new(accounts: Vec<Account>, config: &PoolConfig) -> Self {
+        assert!(!accounts.is_empty(), "account pool must not be empty");
+        let sticky = config
+            .sticky_sessions
+            .then(|| NonZeroUsize::new(config.sticky_capacity))
+            .flatten()
+            .map(|capacity| Mutex::new(LruCache::new(capacity)));
+
+        Self {
+            accounts: accounts.into_iter().map(Arc::new).collect(),
+            strategy: config.strategy,
+            next: AtomicUsize::new(0),
+            sticky,
+        }
+    }
+
+    /// Loads credentials for every configured account.
+    ///
+    /// Without `[[pool.accounts]]`, the pool holds a single account built from
+    /// `oauth.credentials_path`. Projects are resolved later, see
+    /// [`AccountPool::spawn_project_resolution`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProxyError::Config` for missing or duplicate account names, or the
+    /// first credential error.
+    pub async fn connect(config: &AppConfig) -> Result<Self> {
+        let specs: Vec<(String, OAuthConfig, GeminiConfig)> = if config.pool.accounts.is_empty() {
+            vec![(
+                DEFAULT_ACCOUNT.to_string(),
+                config.oauth.clone(),
+                config.gemini.clone(),
+            )]
+        } else {
+            config
+                .pool
+                .accounts
+                .iter()
+                .map(|account| {
+                    (
+                        account.name.clone(),
+                        OAuthConfig {
+                            credentials_path: account.credentials_path.clone(),
+                            ..config.oauth.clone()
+                        },
+                        GeminiConfig {
+                            project_id: account
+                                .project_id
+                                .clone()
+                                .or_else(|| config.gemini.project_id.clone()),
+                            ..config.gemini.clone()
+                        },
+                    )
+                })
+                .collect()
+        };
+
+        let mut accounts = Vec::with_capacity(specs.len());
+        for (name, oauth_config, gemini_config) in specs {
+            if name.is_empty() {
+                return Err(ProxyError::Config(
+                    "Pool accounts must have a name".to_string(),
+                ));
+            }
+            if accounts.iter().any(|a: &Account| a.name == name) {
+                return Err(ProxyError::Config(format!(
+                    "Duplicate pool account name: {}",
+                    name
+                )));
+            }
+
+            info!(
+                "Loading OAuth credentials for account {} from {}",
+                name, oauth_config.credentials_path
+            );
+            let oauth_manager = crate::oauth::OAuthManager::new(&oauth_config).await?;
+            let client =
+                GeminiClient::new_for_account(&gemini_config, oauth_manager, &name).await?;
+            accounts.push(Account::new(name, client));
+        }
+
+        Ok(Self::new(accounts, &config.pool))
+    }
+
+    /// Returns all accounts in configuration order.
+    pub fn accounts(&self) -> &[Arc<Account>] {
+        &self.accounts
+    }
+
+    /// Returns the first configured account.
+    pub fn primary(&self) -> &Arc<Account> {
+        &self.accounts[0]
+    }
+
+    /// Resolves the project of every Code Assist account in the background,
+    /// retrying failed accounts with a growing delay until all have one.
+    pub fn spawn_project_resolution(self: Arc<Self>) {
+        tokio::spawn(async move {
+            let mut delay = PROJECT_RETRY_INITIAL;
+            loop {
+                let mut pending = 0;
+                for account in &self.accounts {
+                    let Some(client) = account.backend.code_assist() else {
+                        continue;
+                    };
+                    if let Err(e) = client.ensure_project().await {
+                        warn!(
+                            "Project resolution for account {} failed (retrying in {}s): {}",
+                            account.name,
+                            delay.as_secs(),
+                            e
+                        );
+                        pending += 1;
+                    }
+                }
+                if pending == 0 {
+                    break;
+                }
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(PROJECT_RETRY_MAX);
+            }
+        });
+    }
+
+    /// Polls every Code Assist account's quota every `interval`, starting now.
+    /// Accounts whose project is not resolved yet are skipped until it is.
+    pub fn spawn_quota_refresh(self: Arc<Self>, interval: std::time::Duration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                for account in &self.accounts {
+                    let Some(client) = account.backend.code_assist() else {
+                        continue;
+                    };
+                    // Resolution is retried (and its failures logged) by
+                    // spawn_project_resolution, e.g. while offline
+                    if client.project_id().is_none() {
+                        debug!(
+                            "Skipping quota query for account {}: project not resolved yet",
+                            account.name
+                        );
+                        continue;
+                    }
+                    if let Err(e) = client.refresh_quota().await {
+                        warn!("Quota query for account {} failed: {}", account.name, e);
+                    }
+                }
+            }
+        });
+    }
+
+    /// Returns accounts in the order a request should try them: the
+    /// conversation's sticky account first, then by strategy.
+    pub fn candidates(&self, conversation: Option<&str>) -> Vec<Arc<Account>> {
+        let sticky = conversation
+            .zip(self.sticky.as_ref())
+            .and_then(|(key, sticky)| sticky.lock().get(key).copied());
+        let last_limited: Vec<Option<DateTime<Utc>>> = self
+            .accounts
+            .iter()
+            .map(|account| account.last_rate_limited())
+            .collect();
+        let start = match self.strategy {
+            PoolStrategy::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed),
+            PoolStrategy::LeastRecentlyRateLimited => 0,
+        };
+
+        order_accounts(self.strategy, start, &last_limited, sticky)
+            .into_iter()
+            .map(|index| self.accounts[index].clone())
+            .collect()
+    }
+
+    /// Pins a conversation to the account that served it.
+    pub fn assign(&self, conversation: Option<&str>, account: &Account) {
+        let (Some(key), Some(sticky)) = (conversation, &self.sticky) else {
+            return;
+        };
+        if let Some(index) = self
+            .accounts
+            .iter()
+            .position(|a| std::ptr::eq(a.as_ref(), account))
+        {
+            if sticky.lock().put(key.to_string(), index) != Some(index) {
+                debug!("Conversation {} pinned to account {}", key, account.name);
+            }
+        }
+    }
+}
+
+/// Orders account indices by strategy, with the sticky account (if any) first.
+fn order_accounts(
+    strategy: PoolStrategy,
+    start: usize,
+    last_limited: &[Option<DateTime<Utc>>],
+    sticky: Option<usize>,
+) -> Vec<usize> {
+    let count = last_limited.len();
+    let mut order: Vec<usize> = match strategy {
+        PoolStrategy::RoundRobin => (0..count).map(|i| (start + i) % count).collect(),
+        PoolStrategy::LeastRecentlyRateLimited => {
+            let mut order: Vec<usize> = (0..count).collect();
+            // Never-limited accounts sort first (`None < Some`), then oldest limit
+            order.sort_by_key(|&i| last_limited[i]);
+            order
+        }
+    };
+
+    if let Some(index) = sticky.filter(|&i| i < count) {
+        order.retain(|&i| i != index);
+        order.insert(0, index);
+    }
+    order
+}
+
+/// Identifies the conversation a request belongs to.
+///
+/// Uses `metadata.user_id` (Claude Code includes its session ID there), falling
+/// back to a hash of the first message, which stays the same on every turn.
+pub fn conversation_key(req: &MessagesRequest) -> Option<String> {
+    if let Some(user_id) = req.metadata.as_ref().and_then(|m| m.user_id.as_deref()) {
+        return Some(user_id.to_string());
+    }
+
+    let first = req.messages.first()?;
+    let text = match &first.content {
+        MessageContent::Text(text) => text.clone(),
+        MessageContent::Blocks(blocks) => serde_json::to_string(blocks).ok()?,
+    };
+    Some(hex::encode(Sha256::digest(text.as_bytes())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: i64) -> Option<DateTime<Utc>> {
+        DateTime::from_timestamp(secs, 0)
+    }
+
+    #[test]
+    fn test_least_recently_rate_limited_order() {
+        let last_limited = [at(200), None, at(100)];
+        assert_eq!(
+            order_accounts(
+                PoolStrategy::LeastRecentlyRateLimited,
+                0,
+                &last_limited,
+                None
+            ),
+            vec![1, 2, 0]
+        );
+        // The sticky account always goes first
+        assert_eq!(
+            order_accounts(
+                PoolStrategy::LeastRecentlyRateLimited,
+                0,
+                &last_limited,
+                Some(0)
+            ),
+            vec![0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn test_round_robin_order() {
+        let last_limited = [None, None, None];
+        assert_eq!(
+            order_accounts(PoolStrategy::RoundRobin, 0, &last_limited, None),
+            vec![0, 1, 2]
+        );
+        assert_eq!(
+            order_accounts(PoolStrategy::RoundRobin, 4, &last_limited, None),
+            vec![1, 2, 0]
+        );
+        assert_eq!(
+            order_accounts(PoolStrategy::RoundRobin, 4, &last_limited, Some(2)),
+            vec![2, 1, 0]
+        );
+    }
+
+    #[test]
+    fn test_conversation_key() {
+        let request = |value: serde_json::Value| -> MessagesRequest {
+            serde_json::from_value(value).unwrap()
+        };
+
+        let with_session = request(serde_json::json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 1024,
+            "metadata": {"user_id": "user_abc_session_123"},
+            "messages": [{"role": "user", "content": "hi"}],
+        }));
+        assert_eq!(
+            conversation_key(&with_session).as_deref(),
+            Some("user_abc_session_123")
+        );
+
+        // Later turns of the same conversation hash to the same key
+        let first_turn = request(serde_json::json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 1024,
+            "messages": [{"role": "user", "content": "hi"}],
+        }));
+        let later_turn = request(serde_json::json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 1024,
+            "messages": [
+                {"role": "user", "content": "hi"},
+                {"role": "assistant", "content": "hello"},
+                {"role": "user", "content": "more"},
+            ],
+        }));
+        assert_eq!(conversation_key(&first_turn), conversation_key(&later_turn));
+    }
+}
@@ -0,0 +1,223 @@
+//! On-disk cache of resolved Code Assist projects.
+//!
+//! Resolving an account's project takes a `loadCodeAssist` round-trip (and,
+//! for new accounts, onboarding). The result rarely changes, so it is cached
+//! per account in `gemini.project_cache_file` and reused until
+//! `gemini.project_cache_ttl_seconds` have passed. Entries record the
+//! credentials they were resolved with and the project that was requested, so
+//! logging in again (possibly as another Google account) or changing
+//! `project_id` in the config invalidates them. Entries are also dropped when
+//! the upstream rejects the project.
+
+// Author: kelexine (https://github.com/kelexine)
+
+use crate::config::GeminiConfig;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::{debug, warn};
+
+/// Serializes read-modify-write cycles of the shared cache file.
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Project and tier resolved for an account.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProjectSetup {
+    pub project_id: String,
+    pub tier: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    project_id: String,
+    #[serde(default)]
+    tier: Option<String>,
+    /// Fingerprint of the credentials this entry was resolved with.
+    #[serde(default)]
+    credentials: Option<String>,
+    /// Project configured when this entry was resolved.
+    #[serde(default)]
+    requested_project: Option<String>,
+    resolved_at: DateTime<Utc>,
+}
+
+/// Per-account project cache file.
+#[derive(Debug, Clone)]
+pub struct ProjectCache {
+    path: PathBuf,
+    ttl: chrono::Duration,
+}
+
+impl ProjectCache {
+    /// Returns the configured cache, or `None` when caching is disabled.
+    pub fn from_config(config: &GeminiConfig) -> Option<Self> {
+        if config.project_cache_file.is_empty() || config.project_cache_ttl_seconds == 0 {
+            return None;
+        }
+        Some(Self::new(
+            crate::utils::paths::expand_home(&config.project_cache_file),
+            chrono::Duration::seconds(config.project_cache_ttl_seconds as i64),
+        ))
+    }
+
+    /// Creates a cache stored at `path` whose entries expire after `ttl`.
+    pub fn new(path: PathBuf, ttl: chrono::Duration) -> Self {
+        Self { path, ttl }
+    }
+
+    /// Returns the cached project for `account` if it is fresh and was
+    /// resolved with the same credentials (see `OAuthManager::fingerprint`)
+    /// for the same requested project.
+    pub fn load(
+        &self,
+        account: &str,
+        credentials: &str,
+        requested: Option<&str>,
+    ) -> Option<ProjectSetup> {
+        let entry = read_entries(&self.path).remove(account)?;
+        if entry.credentials.as_deref() != Some(credentials) {
+            debug!(
+                "Cached project for account {} was for other credentials",
+                account
+            );
+            return None;
+        }
+        if entry.requested_project.as_deref() != requested {
+            debug!(
+                "Cached project for account {} was for another project",
+                account
+            );
+            return None;
+        }
+        if Utc::now() - entry.resolved_at > self.ttl {
+            debug!("Cached project for account {} has expired", account);
+            return None;
+        }
+        Some(ProjectSetup {
+            project_id: entry.project_id,
+            tier: entry.tier,
+        })
+    }
+
+    /// Records the project resolved for `account`. Failures are logged only.
+    pub fn store(
+        &self,
+        account: &str,
+        credentials: &str,
+        requested: Option<&str>,
+        setup: &ProjectSetup,
+    ) {
+        let _guard = WRITE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut entries = read_entries(&self.path);
+        entries.insert(
+            account.to_string(),
+            CacheEntry {
+                project_id: setup.project_id.clone(),
+                tier: setup.tier.clone(),
+                credentials: Some(credentials.to_string()),
+                requested_project: requested.map(str::to_string),
+                resolved_at: Utc::now(),
+            },
+        );
+        if let Err(e) = write_entries(&self.path, &entries) {
+            warn!(
+                "Failed to write project cache {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+
+    /// Drops the entry for `account`, if any. Failures are logged only.
+    pub fn remove(&self, account: &str) {
+        let _guard = WRITE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut entries = read_entries(&self.path);
+        if entries.remove(account).is_none() {
+            return;
+        }
+        if let Err(e) = write_entries(&self.path, &entries) {
+            warn!(
+                "Failed to write project cache {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+fn read_entries(path: &Path) -> HashMap<String, CacheEntry> {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// Writes the cache via a temporary sibling and rename.
+fn write_entries(path: &Path, entries: &HashMap<String, CacheEntry>) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(entries)?)?;
+    std::fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup(project_id: &str) -> ProjectSetup {
+        ProjectSetup {
+            project_id: project_id.to_string(),
+            tier: Some("free-tier".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_round_trip_per_account() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ProjectCache::new(dir.path().join("projects.json"), chrono::Duration::hours(1));
+
+        assert!(cache.load("default", "alice", None).is_none());
+        cache.store("default", "alice", None, &setup("project-a"));
+        cache.store("work", "bob", Some("pinned"), &setup("pinned"));
+
+        assert_eq!(
+            cache.load("default", "alice", None),
+            Some(setup("project-a"))
+        );
+        assert_eq!(
+            cache.load("work", "bob", Some("pinned")),
+            Some(setup("pinned"))
+        );
+        // A different requested project invalidates the entry
+        assert!(cache.load("work", "bob", Some("other")).is_none());
+        assert!(cache.load("work", "bob", None).is_none());
+        // So does logging in with other credentials
+        assert!(cache.load("default", "carol", None).is_none());
+
+        cache.remove("default");
+        assert!(cache.load("default", "alice", None).is_none());
+        assert!(cache.load("work", "bob", Some("pinned")).is_some());
+    }
+
+    #[test]
+    fn test_expired_entries_are_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("projects.json");
+        ProjectCache::new(path.clone(), chrono::Duration::hours(1)).store(
+            "default",
+            "alice",
+            None,
+            &setup("project-a"),
+        );
+
+        let expired = ProjectCache::new(
+            path,
+            chrono::Duration::zero() - chrono::Duration::seconds(1),
+        );
+        assert!(expired.load("default", "alice", None).is_none());
+    }
+}
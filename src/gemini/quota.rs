@@ -0,0 +1,315 @@
+//! Remaining Code Assist quota.
+//!
+//! Code Assist reports per-model quota buckets through `retrieveUserQuota`.
+//! Each account's buckets are polled periodically and cached here, so that
+//! `/health`, metrics, the `anthropic-ratelimit-*` response headers and
+//! dispatch can see a model running out before it starts returning 429s.
+
+// Author: kelexine (https://github.com/kelexine)
+
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Request body for `retrieveUserQuota`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct RetrieveUserQuotaRequest {
+    pub project: String,
+}
+
+/// Response from `retrieveUserQuota`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RetrieveUserQuotaResponse {
+    #[serde(default)]
+    pub buckets: Vec<QuotaBucket>,
+}
+
+/// One quota bucket as reported by Code Assist.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuotaBucket {
+    /// Remaining amount, as a decimal string.
+    pub remaining_amount: Option<String>,
+    /// Remaining share of the bucket, from 0 to 1.
+    pub remaining_fraction: Option<f64>,
+    /// When the bucket refills (RFC 3339).
+    pub reset_time: Option<String>,
+    /// What the bucket counts, e.g. `REQUESTS`.
+    pub token_type: Option<String>,
+    /// Gemini model the bucket applies to.
+    pub model_id: Option<String>,
+}
+
+/// Cached quota for one model.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelQuota {
+    /// Remaining share of the quota, from 0 to 1.
+    pub remaining_fraction: Option<f64>,
+    /// Remaining requests, when reported.
+    pub remaining: Option<u64>,
+    /// When the quota refills.
+    pub reset_at: Option<DateTime<Utc>>,
+}
+
+impl ModelQuota {
+    fn from_bucket(bucket: &QuotaBucket) -> Self {
+        Self {
+            remaining_fraction: bucket.remaining_fraction.map(|f| f.clamp(0.0, 1.0)),
+            remaining: bucket
+                .remaining_amount
+                .as_deref()
+                .and_then(|amount| amount.parse().ok()),
+            reset_at: bucket
+                .reset_time
+                .as_deref()
+                .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+                .map(|time| time.with_timezone(&Utc)),
+        }
+    }
+
+    /// Total quota implied by the remaining amount and fraction.
+    pub fn limit(&self) -> Option<u64> {
+        let fraction = self.remaining_fraction.filter(|&f| f > 0.0)?;
+        Some((self.remaining? as f64 / fraction).round() as u64)
+    }
+
+    /// Whether the quota is at or below `threshold` and has not reset yet.
+    pub fn is_low(&self, threshold: f64, now: DateTime<Utc>) -> bool {
+        self.remaining_fraction.is_some_and(|f| f <= threshold)
+            && self.reset_at.is_none_or(|reset_at| reset_at > now)
+    }
+
+    /// `anthropic-ratelimit-requests-*` headers describing this quota.
+    pub fn rate_limit_headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = Vec::new();
+        if let Some(limit) = self.limit() {
+            headers.push(("anthropic-ratelimit-requests-limit", limit.to_string()));
+        }
+        if let Some(remaining) = self.remaining {
+            headers.push((
+                "anthropic-ratelimit-requests-remaining",
+                remaining.to_string(),
+            ));
+        }
+        if let Some(reset_at) = self.reset_at {
+            headers.push((
+                "anthropic-ratelimit-requests-reset",
+                reset_at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            ));
+        }
+        headers
+    }
+
+    /// Short description for `/health`, e.g. `42% (resets 2026-01-16T08:00:00Z)`.
+    pub fn describe(&self) -> String {
+        let mut description = match (self.remaining_fraction, self.remaining) {
+            (Some(fraction), _) => format!("{:.0}%", fraction * 100.0),
+            (None, Some(remaining)) => format!("{} left", remaining),
+            (None, None) => "unknown".to_string(),
+        };
+        if let Some(reset_at) = self.reset_at {
+            description.push_str(&format!(
+                " (resets {})",
+                reset_at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+            ));
+        }
+        description
+    }
+}
+
+/// Latest quota per model for one account.
+#[derive(Debug)]
+pub struct QuotaCache {
+    account: String,
+    models: RwLock<HashMap<String, ModelQuota>>,
+}
+
+impl QuotaCache {
+    /// Creates an empty cache for `account`.
+    pub fn new(account: impl Into<String>) -> Self {
+        Self {
+            account: account.into(),
+            models: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Replaces the cached quota with freshly retrieved buckets and updates
+    /// the quota gauges, removing those of models no longer reported.
+    ///
+    /// Request buckets are preferred; when a model has several, the one with
+    /// the least remaining wins.
+    pub fn update(&self, buckets: &[QuotaBucket]) {
+        let mut models: HashMap<String, ModelQuota> = HashMap::new();
+        for bucket in buckets {
+            let Some(model) = bucket.model_id.as_deref() else {
+                continue;
+            };
+            if bucket
+                .token_type
+                .as_deref()
+                .is_some_and(|t| t != "REQUESTS")
+            {
+                continue;
+            }
+            let quota = ModelQuota::from_bucket(bucket);
+            let lower = |existing: &ModelQuota| {
+                quota.remaining_fraction.unwrap_or(1.0) < existing.remaining_fraction.unwrap_or(1.0)
+            };
+            if models.get(model).is_none_or(lower) {
+                models.insert(model.to_string(), quota);
+            }
+        }
+
+        for (model, quota) in &models {
+            crate::metrics::update_quota(
+                &self.account,
+                model,
+                quota.remaining_fraction,
+                quota.reset_at.map(|reset_at| reset_at.timestamp()),
+            );
+        }
+        let previous = std::mem::replace(&mut *self.models.write(), models);
+        // Models no longer reported would otherwise keep their last values
+        for model in previous.keys().filter(|model| self.get(model).is_none()) {
+            crate::metrics::update_quota(&self.account, model, None, None);
+        }
+    }
+
+    /// Returns the cached quota for `model`.
+    pub fn get(&self, model: &str) -> Option<ModelQuota> {
+        self.models.read().get(model).cloned()
+    }
+
+    /// Returns all cached quotas, sorted by model.
+    pub fn snapshot(&self) -> Vec<(String, ModelQuota)> {
+        let mut models: Vec<(String, ModelQuota)> = self
+            .models
+            .read()
+            .iter()
+            .map(|(model, quota)| (model.clone(), quota.clone()))
+            .collect();
+        models.sort_by(|a, b| a.0.cmp(&b.0));
+        models
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket(model: &str, fraction: f64, amount: &str) -> QuotaBucket {
+        QuotaBucket {
+            remaining_amount: Some(amount.to_string()),
+            remaining_fraction: Some(fraction),
+            reset_time: Some("2026-01-16T08:00:00Z".to_string()),
+            token_type: Some("REQUESTS".to_string()),
+            model_id: Some(model.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let response: RetrieveUserQuotaResponse = serde_json::from_str(
+            r#"{"buckets": [{
+                "remainingAmount": "40",
+                "remainingFraction": 0.4,
+                "resetTime": "2026-01-16T08:00:00Z",
+                "tokenType": "REQUESTS",
+                "modelId": "gemini-2.5-pro"
+            }]}"#,
+        )
+        .unwrap();
+        let cache = QuotaCache::new("default");
+        cache.update(&response.buckets);
+
+        let quota = cache.get("gemini-2.5-pro").unwrap();
+        assert_eq!(quota.remaining, Some(40));
+        assert_eq!(quota.limit(), Some(100));
+        assert_eq!(quota.describe(), "40% (resets 2026-01-16T08:00:00Z)");
+        assert_eq!(
+            quota.rate_limit_headers(),
+            vec![
+                ("anthropic-ratelimit-requests-limit", "100".to_string()),
+                ("anthropic-ratelimit-requests-remaining", "40".to_string()),
+                (
+                    "anthropic-ratelimit-requests-reset",
+                    "2026-01-16T08:00:00Z".to_string()
+                ),
+            ]
+        );
+        assert!(serde_json::from_str::<RetrieveUserQuotaResponse>("{}").is_ok());
+    }
+
+    #[test]
+    fn test_update_keeps_lowest_request_bucket() {
+        let cache = QuotaCache::new("default");
+        cache.update(&[
+            bucket("gemini-2.5-pro", 0.5, "50"),
+            bucket("gemini-2.5-pro", 0.2, "20"),
+            QuotaBucket {
+                token_type: Some("TOKENS".to_string()),
+                ..bucket("gemini-2.5-pro", 0.0, "0")
+            },
+            bucket("gemini-2.5-flash", 0.9, "900"),
+        ]);
+
+        assert_eq!(cache.get("gemini-2.5-pro").unwrap().remaining, Some(20));
+        assert_eq!(cache.snapshot().len(), 2);
+
+        // A later refresh replaces everything
+        cache.update(&[bucket("gemini-2.5-flash", 0.8, "800")]);
+        assert!(cache.get("gemini-2.5-pro").is_none());
+    }
+
+    #[test]
+    fn test_update_removes_gauges_of_unreported_models() {
+        use prometheus::core::Collector;
+        let models_with_gauges = || -> Vec<String> {
+            crate::metrics::QUOTA_REMAINING_FRACTION.collect()[0]
+                .get_metric()
+                .iter()
+                .filter(|metric| {
+                    metric
+                        .get_label()
+                        .iter()
+                        .any(|label| label.get_value() == "quota-gauge-test")
+                })
+                .flat_map(|metric| {
+                    metric
+                        .get_label()
+                        .iter()
+                        .filter(|label| label.get_name() == "model")
+                        .map(|label| label.get_value().to_string())
+                        .collect::<Vec<_>>()
+                })
+                .collect()
+        };
+        let cache = QuotaCache::new("quota-gauge-test");
+
+        cache.update(&[
+            bucket("gemini-2.5-pro", 0.5, "50"),
+            bucket("gemini-2.5-flash", 0.9, "900"),
+        ]);
+        assert_eq!(models_with_gauges().len(), 2);
+
+        cache.update(&[bucket("gemini-2.5-flash", 0.8, "800")]);
+        assert_eq!(models_with_gauges(), vec!["gemini-2.5-flash"]);
+    }
+
+    #[test]
+    fn test_is_low() {
+        let quota = ModelQuota::from_bucket(&bucket("gemini-2.5-pro", 0.03, "3"));
+        let before = DateTime::parse_from_rfc3339("2026-01-16T07:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let after = DateTime::parse_from_rfc3339("2026-01-16T09:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert!(quota.is_low(0.05, before));
+        assert!(!quota.is_low(0.01, before));
+        // Once the reset time passes the cached value is stale
+        assert!(!quota.is_low(0.05, after));
+    }
+}
@@ -0,0 +1,284 @@
+//! Incremental Server-Sent Events decoder.
+//!
+//! Implements the event stream interpretation rules of the HTML Living
+//! Standard (§9.2.6) over raw bytes. Bytes are buffered until a complete line
+//! has arrived, so a multi-byte UTF-8 character split across network chunks
+//! is decoded intact. Lines may end in LF, CRLF or CR; `data` fields of one
+//! event are joined with newlines; `event`, `id` and `retry` are tracked and
+//! comment lines are ignored.
+
+// Author: kelexine (https://github.com/kelexine)
+
+use std::time::Duration;
+
+/// A dispatched event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SseEvent {
+    /// The `event` field, if one was given.
+    pub event: Option<String>,
+    /// All `data` fields of the event, joined with `\n`.
+    pub data: String,
+    /// Last event ID at the time of dispatch, if any was set.
+    pub id: Option<String>,
+}
+
+/// Byte-oriented SSE decoder. Feed it chunks as they arrive.
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    /// Bytes not yet forming a complete line.
+    buffer: Vec<u8>,
+    /// Whether the leading byte-order mark has been checked for.
+    started: bool,
+    /// Whether the previous chunk ended in CR, so a leading LF belongs to it.
+    after_cr: bool,
+    /// Data of the event being assembled, one `\n` after each field.
+    data: String,
+    /// `event` field of the event being assembled.
+    event: Option<String>,
+    /// Last event ID, which persists across events.
+    last_event_id: Option<String>,
+    /// Reconnection time requested by the server.
+    retry: Option<Duration>,
+}
+
+impl SseDecoder {
+    /// Creates a decoder at the start of a stream.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reconnection time from the most recent valid `retry` field.
+    pub fn retry(&self) -> Option<Duration> {
+        self.retry
+    }
+
+    /// Decodes `chunk`, returning every event it completes.
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        let mut chunk = chunk;
+        if self.after_cr && !chunk.is_empty() {
+            self.after_cr = false;
+            if chunk[0] == b'\n' {
+                chunk = &chunk[1..];
+            }
+        }
+        self.buffer.extend_from_slice(chunk);
+
+        if !self.started {
+            const BOM: &[u8] = b"\xEF\xBB\xBF";
+            if self.buffer.len() < BOM.len() && BOM.starts_with(&self.buffer) {
+                return Vec::new();
+            }
+            self.started = true;
+            if self.buffer.starts_with(BOM) {
+                self.buffer.drain(..BOM.len());
+            }
+        }
+
+        let mut events = Vec::new();
+        let mut start = 0;
+        while let Some(offset) = self.buffer[start..]
+            .iter()
+            .position(|&b| b == b'\n' || b == b'\r')
+        {
+            let end = start + offset;
+            let mut next = end + 1;
+            if self.buffer[end] == b'\r' {
+                match self.buffer.get(next) {
+                    Some(b'\n') => next += 1,
+                    Some(_) => {}
+                    // The LF of a CRLF may arrive with the next chunk
+                    None => self.after_cr = true,
+                }
+            }
+
+            let line = String::from_utf8_lossy(&self.buffer[start..end]).into_owned();
+            if let Some(event) = self.process_line(&line) {
+                events.push(event);
+            }
+            start = next;
+        }
+        self.buffer.drain(..start);
+        events
+    }
+
+    /// Ends the stream, returning a final event that was not followed by a
+    /// blank line.
+    ///
+    /// The standard discards such an incomplete event; it is dispatched here
+    /// so that a stream cut short after its last `data` line loses nothing.
+    pub fn finish(&mut self) -> Option<SseEvent> {
+        let mut event = None;
+        if !self.buffer.is_empty() {
+            let line = String::from_utf8_lossy(&std::mem::take(&mut self.buffer)).into_owned();
+            event = self.process_line(&line);
+        }
+        event.or_else(|| self.dispatch())
+    }
+
+    /// Applies one line, returning the event it completes, if any.
+    fn process_line(&mut self, line: &str) -> Option<SseEvent> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line.starts_with(':') {
+            // Comment, e.g. a keep-alive
+            return None;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event = Some(value.to_string()),
+            "data" => {
+                self.data.push_str(value);
+                self.data.push('\n');
+            }
+            "id" if !value.contains('\0') => self.last_event_id = Some(value.to_string()),
+            "retry" if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+                if let Ok(millis) = value.parse() {
+                    self.retry = Some(Duration::from_millis(millis));
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Completes the current event. Events without data are dropped.
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        if self.data.is_empty() {
+            return None;
+        }
+        let mut data = std::mem::take(&mut self.data);
+        data.pop();
+        Some(SseEvent {
+            event,
+            data,
+            id: self.last_event_id.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn decode_chunks(chunks: &[&[u8]]) -> Vec<SseEvent> {
+        let mut decoder = SseDecoder::new();
+        let mut events: Vec<SseEvent> = chunks
+            .iter()
+            .flat_map(|chunk| decoder.feed(chunk))
+            .collect();
+        events.extend(decoder.finish());
+        events
+    }
+
+    fn data(data: &str) -> SseEvent {
+        SseEvent {
+            event: None,
+            data: data.to_string(),
+            id: None,
+        }
+    }
+
+    #[test]
+    fn test_fields() {
+        let stream = b"\xEF\xBB\xBF: keep-alive\nevent: message\nid: 7\nretry: 3000\ndata: first\ndata:second\ndata\n\nid\nretry: soon\ndata: next\n\n";
+        let mut decoder = SseDecoder::new();
+        let events = decoder.feed(stream);
+
+        assert_eq!(
+            events,
+            vec![
+                SseEvent {
+                    event: Some("message".to_string()),
+                    data: "first\nsecond\n".to_string(),
+                    id: Some("7".to_string()),
+                },
+                SseEvent {
+                    event: None,
+                    data: "next".to_string(),
+                    id: Some(String::new()),
+                },
+            ]
+        );
+        // Invalid retry values are ignored
+        assert_eq!(decoder.retry(), Some(Duration::from_millis(3000)));
+    }
+
+    #[test]
+    fn test_line_endings_and_empty_events() {
+        let events = decode_chunks(&[b"data: a\r\n\r\ndata: b\r\rdata: c\n\nevent: ping\n\n"]);
+        assert_eq!(events, vec![data("a"), data("b"), data("c")]);
+    }
+
+    #[test]
+    fn test_finish_flushes_unterminated_event() {
+        assert_eq!(
+            decode_chunks(&[b"data: a\n\ndata: b"]),
+            vec![data("a"), data("b")]
+        );
+        assert_eq!(decode_chunks(&[b"data: a\n"]), vec![data("a")]);
+    }
+
+    #[test]
+    fn test_every_split_offset() {
+        let stream = "\u{feff}data: {\"text\": \"你好 👋🏽\"}\r\n\r\n: ping\rdata: é\r\ndata: ✓\n\n"
+            .as_bytes();
+        let expected = decode_chunks(&[stream]);
+        assert_eq!(
+            expected,
+            vec![data("{\"text\": \"你好 👋🏽\"}"), data("é\n✓")]
+        );
+
+        for split in 0..=stream.len() {
+            let (head, tail) = stream.split_at(split);
+            assert_eq!(decode_chunks(&[head, tail]), expected, "split at {}", split);
+        }
+        // One byte at a time
+        let bytes: Vec<&[u8]> = stream.chunks(1).collect();
+        assert_eq!(decode_chunks(&bytes), expected);
+    }
+
+    /// Serializes events with the given line ending.
+    fn encode(events: &[Vec<String>], ending: &str) -> Vec<u8> {
+        let mut stream = String::new();
+        for lines in events {
+            for line in lines {
+                stream.push_str(&format!("data: {}{}", line, ending));
+            }
+            stream.push_str(ending);
+        }
+        stream.into_bytes()
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn prop_split_anywhere_decodes_the_same(
+            events in prop::collection::vec(
+                prop::collection::vec("[^\r\n]{0,12}", 1..4),
+                1..5,
+            ),
+            ending in prop::sample::select(vec!["\n", "\r\n", "\r"]),
+        ) {
+            let stream = encode(&events, ending);
+            // Every event has a data line (possibly empty), so all are dispatched
+            let expected: Vec<SseEvent> = events
+                .iter()
+                .map(|lines| data(&lines.join("\n")))
+                .collect();
+            prop_assert_eq!(&decode_chunks(&[&stream]), &expected);
+
+            for split in 0..=stream.len() {
+                let (head, tail) = stream.split_at(split);
+                prop_assert_eq!(&decode_chunks(&[head, tail]), &expected, "split at {}", split);
+            }
+        }
+    }
+}
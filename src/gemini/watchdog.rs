@@ -0,0 +1,220 @@
+//! Stall detection for upstream streams.
+//!
+//! Gemini occasionally stops sending mid-stream without closing the
+//! connection. Left alone, the client would only see keep-alive pings until
+//! its own timeout. The watchdog aborts a stream that sends nothing for
+//! `stream.idle_timeout_seconds` (including before its first chunk), or that
+//! keeps thinking without visible output for longer than
+//! `stream.max_thinking_seconds`, and reports it as `ServiceUnavailable`.
+//!
+//! The watchdog runs inside the response body, after the headers have been
+//! sent, so the client sees keep-alives while it waits. A stream that stalls
+//! before any content reached the client is reissued like any other
+//! retryable failure (see `stream.retries_before_content`).
+
+// Author: kelexine (https://github.com/kelexine)
+
+use super::streaming::GeminiStream;
+use crate::config::StreamConfig;
+use crate::error::ProxyError;
+use crate::models::gemini::{GenerateContentResponse, Part};
+use futures::StreamExt;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::warn;
+
+/// Applies the configured stall limits to upstream streams.
+#[derive(Debug, Clone)]
+pub struct StreamWatchdog {
+    idle_timeout: Option<Duration>,
+    max_thinking: Option<Duration>,
+}
+
+impl StreamWatchdog {
+    /// Creates a watchdog from `[stream]` settings.
+    pub fn new(config: &StreamConfig) -> Self {
+        let limit = |seconds: u64| (seconds > 0).then(|| Duration::from_secs(seconds));
+        Self {
+            idle_timeout: limit(config.idle_timeout_seconds),
+            max_thinking: limit(config.max_thinking_seconds),
+        }
+    }
+
+    /// Wraps `stream` so that it ends with a `ServiceUnavailable` error when
+    /// the upstream stalls. `started` is when the request was sent, from which
+    /// thinking time is measured.
+    pub fn watch(&self, stream: GeminiStream, model: &str, started: Instant) -> GeminiStream {
+        let watchdog = self.clone();
+        let model = model.to_string();
+        Box::pin(async_stream::stream! {
+            futures::pin_mut!(stream);
+            let mut thinking = true;
+            let mut received = false;
+            loop {
+                let idle_timeout = watchdog.idle_timeout;
+                let idle = async move {
+                    match idle_timeout {
+                        Some(timeout) => tokio::time::sleep(timeout).await,
+                        None => std::future::pending().await,
+                    }
+                };
+                let max_thinking = watchdog.max_thinking.filter(|_| thinking);
+                let thinking_cap = async move {
+                    match max_thinking {
+                        Some(cap) => tokio::time::sleep_until(started + cap).await,
+                        None => std::future::pending().await,
+                    }
+                };
+
+                tokio::select! {
+                    item = stream.next() => match item {
+                        Some(Ok(chunk)) => {
+                            received = true;
+                            thinking = thinking && !has_visible_output(&chunk);
+                            yield Ok(chunk);
+                        }
+                        Some(Err(e)) => {
+                            yield Err(e);
+                            break;
+                        }
+                        None => break,
+                    },
+                    _ = idle => {
+                        let seconds = watchdog.idle_timeout.unwrap_or_default().as_secs();
+                        let reason = if received {
+                            warn!("{} stalled mid-stream: nothing for {}s", model, seconds);
+                            "idle"
+                        } else {
+                            warn!("{} sent nothing for {}s after the request", model, seconds);
+                            "first_chunk"
+                        };
+                        crate::metrics::record_stream_stall(&model, reason);
+                        yield Err(ProxyError::ServiceUnavailable(format!(
+                            "Upstream stalled: {} sent nothing for {}s",
+                            model, seconds
+                        )));
+                        break;
+                    }
+                    _ = thinking_cap => {
+                        let seconds = watchdog.max_thinking.unwrap_or_default().as_secs();
+                        warn!("{} thought for over {}s without answering", model, seconds);
+                        crate::metrics::record_stream_stall(&model, "thinking");
+                        yield Err(ProxyError::ServiceUnavailable(format!(
+                            "Upstream stalled: {} thought for over {}s without answering",
+                            model, seconds
+                        )));
+                        break;
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// Whether a chunk carries anything beyond thoughts: answer text, a tool
+/// call or a finish reason.
+fn has_visible_output(chunk: &GenerateContentResponse) -> bool {
+    let Some(response) = &chunk.response else {
+        return false;
+    };
+    response.candidates.iter().any(|candidate| {
+        candidate.finish_reason.is_some()
+            || candidate.content.parts.iter().any(|part| match part {
+                Part::Text { text, thought, .. } => thought != &Some(true) && !text.is_empty(),
+                Part::Thought { .. } => false,
+                _ => true,
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Result;
+
+    fn chunk(text: &str, thought: bool) -> Result<GenerateContentResponse> {
+        Ok(serde_json::from_value(serde_json::json!({
+            "response": {"candidates": [{
+                "content": {"role": "model", "parts": [{"text": text, "thought": thought}]},
+            }]},
+        }))
+        .unwrap())
+    }
+
+    fn watchdog(idle_timeout_seconds: u64, max_thinking_seconds: u64) -> StreamWatchdog {
+        StreamWatchdog::new(&StreamConfig {
+            idle_timeout_seconds,
+            max_thinking_seconds,
+            ..StreamConfig::default()
+        })
+    }
+
+    /// A stream yielding `items`, then never ending.
+    fn hanging(items: Vec<Result<GenerateContentResponse>>) -> GeminiStream {
+        futures::stream::iter(items)
+            .chain(futures::stream::pending())
+            .boxed()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_stall_ends_stream_with_error() {
+        let watched = watchdog(30, 0).watch(
+            hanging(vec![chunk("Hello", false)]),
+            "gemini-2.5-pro",
+            Instant::now(),
+        );
+        let items: Vec<_> = watched.collect().await;
+
+        assert_eq!(items.len(), 2);
+        assert!(items[0].is_ok());
+        assert!(matches!(items[1], Err(ProxyError::ServiceUnavailable(_))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_thinking_cap() {
+        let started = Instant::now();
+        // A thought every 10s never trips the idle timeout
+        let thoughts = futures::stream::unfold((), |_| async {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            Some((chunk("hmm", true), ()))
+        })
+        .boxed();
+        let items: Vec<_> = watchdog(30, 60)
+            .watch(thoughts, "gemini-2.5-pro", started)
+            .collect()
+            .await;
+
+        assert!(matches!(
+            items.last(),
+            Some(Err(ProxyError::ServiceUnavailable(_)))
+        ));
+        assert_eq!(started.elapsed(), Duration::from_secs(60));
+
+        // Visible output stops the thinking clock
+        let answered = futures::stream::iter(vec![chunk("hmm", true), chunk("Hi", false)])
+            .chain(futures::stream::unfold((), |_| async {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+                Some((chunk("more", false), ()))
+            }))
+            .take(12)
+            .boxed();
+        let items: Vec<_> = watchdog(30, 60)
+            .watch(answered, "gemini-2.5-pro", Instant::now())
+            .collect()
+            .await;
+        assert!(items.iter().all(|item| item.is_ok()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stall_before_first_chunk() {
+        let started = Instant::now();
+        let items: Vec<_> = watchdog(30, 0)
+            .watch(hanging(Vec::new()), "gemini-2.5-pro", started)
+            .collect()
+            .await;
+
+        assert_eq!(items.len(), 1);
+        assert!(matches!(items[0], Err(ProxyError::ServiceUnavailable(_))));
+        assert_eq!(started.elapsed(), Duration::from_secs(30));
+    }
+}
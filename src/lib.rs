@@ -13,6 +13,7 @@
 //! - [`server`]: Axum-based HTTP server implementation.
 //! - [`metrics`]: Prometheus metrics collection.
 //! - [`cache`]: Context caching implementation.
+//! - [`files`]: Local Files API storage for `file_id` references.
 
 // Author: kelexine (https://github.com/kelexine)
 
@@ -20,6 +21,7 @@ pub mod cache;
 pub mod cli;
 pub mod config;
 pub mod error;
+pub mod files;
 pub mod gemini;
 pub mod metrics;
 pub mod models;
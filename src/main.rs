@@ -13,10 +13,14 @@
 //! 3.  **Logging**: Initializes the `tracing` subscriber for structured logging.
 //! 4.  **Authentication**:
 //!     *   If `--login` is passed: Executes the interactive OAuth flow and exits.
-//!     *   Otherwise: Loads existing credentials and initializes the `OAuthManager`.
-//! 5.  **Initialization**: performs the `loadCodeAssist` handshake to resolve the
-//!     Google Cloud Project ID.
-//! 6.  **Server Startup**: Binds the Axum router to the configured port and starts listening.
+//!     *   Otherwise: Loads existing credentials for every pooled account.
+//! 5.  **Server Startup**: Binds the Axum router to the configured port and starts
+//!     listening, without waiting for the network.
+//! 6.  **Project Resolution**: Each Code Assist account's project is read from the
+//!     project cache (`gemini.project_cache_file`) with its credentials. Accounts
+//!     without a valid entry resolve it in the background through the
+//!     `loadCodeAssist` handshake (onboarding new accounts), retrying until it
+//!     succeeds; requests arriving earlier share that resolution.
 //! 7.  **Shutdown**: Waits for SIGINT/SIGTERM to perform a graceful shutdown.
 
 // Author: kelexine (https://github.com/kelexine)
@@ -25,8 +29,8 @@ use anyhow::Result;
 use clap::Parser;
 use gem2claude::cli::Args;
 use gem2claude::config::AppConfig;
-use gem2claude::gemini::GeminiClient;
-use gem2claude::oauth::{login, OAuthManager};
+use gem2claude::gemini::AccountPool;
+use gem2claude::oauth::login;
 use gem2claude::server::create_router;
 use gem2claude::utils::logging;
 use std::net::SocketAddr;
@@ -58,26 +62,21 @@ async fn main() -> Result<()> {
         login::run().await?;
     }
 
-    // Phase 3: Load OAuth credentials
-    info!(
-        "Loading OAuth credentials from {}",
-        config.oauth.credentials_path
-    );
-    let oauth_manager = OAuthManager::new(&config.oauth).await?;
+    // Phase 3: Load OAuth credentials (and cached project IDs) for every
+    // pooled account
+    info!("Loading account credentials...");
+    let accounts = AccountPool::connect(&config).await?;
+    info!("{} account(s) ready", accounts.accounts().len());
 
-    // Phase 4: Resolve project ID (loadCodeAssist handshake)
-    info!("Resolving Gemini Cloud Code project ID...");
-    let gemini_client = GeminiClient::new(&config.gemini, oauth_manager.clone()).await?;
-    info!("Project ID resolved: {}", gemini_client.project_id());
-
-    // Phase 5: Build and start HTTP server
-    let app = create_router(config.clone(), gemini_client, oauth_manager)?;
+    // Phase 4: Build and start HTTP server. Building the router starts the
+    // background loadCodeAssist handshake for accounts without a cached project.
+    let app = create_router(config.clone(), accounts)?;
     let addr: SocketAddr = format!("{}:{}", config.server.host, config.server.port).parse()?;
 
     info!("Starting server on {}", addr);
     let listener = tokio::net::TcpListener::bind(addr).await?;
 
-    // Phase 6: Run server with graceful shutdown
+    // Phase 5: Run server with graceful shutdown
     axum::serve(listener, app)
         .with_graceful_shutdown(shutdown_signal())
         .await?;
@@ -10,10 +10,12 @@
 mod registry;
 
 pub use registry::{
-    gather_metrics, CACHE_ENTRIES, CACHE_OPERATIONS, GEMINI_API_CALLS, GEMINI_API_DURATION,
-    GEMINI_MODEL_AVAILABILITY, GEMINI_RATE_LIMIT_WAIT_SECONDS, GEMINI_RETRIES, OAUTH_REFRESHES,
-    OAUTH_TOKEN_EXPIRY, REQUESTS_TOTAL, REQUEST_DURATION, SSE_CONNECTIONS, SSE_EVENTS,
-    TOKENS_TOTAL, TRANSLATION_CACHE_OPERATIONS, TRANSLATION_ERRORS,
+    gather_metrics, ACCOUNT_LAST_RATE_LIMITED, ACCOUNT_REQUESTS, CACHE_ENTRIES, CACHE_OPERATIONS,
+    GEMINI_API_CALLS, GEMINI_API_DURATION, GEMINI_CIRCUIT_STATE, GEMINI_MODEL_AVAILABILITY,
+    GEMINI_RATE_LIMIT_WAIT_SECONDS, GEMINI_RETRIES, OAUTH_REFRESHES, OAUTH_TOKEN_EXPIRY,
+    QUOTA_REMAINING_FRACTION, QUOTA_RESET_TIMESTAMP, REQUESTS_TOTAL, REQUEST_DURATION,
+    ROUTING_DECISIONS, SERVED_MODELS, SSE_CONNECTIONS, SSE_EVENTS, STREAM_STALLS, TOKENS_TOTAL,
+    TRANSLATION_CACHE_OPERATIONS, TRANSLATION_ERRORS,
 };
 
 /// Records an incoming HTTP request's completion status and latency.
@@ -147,6 +149,11 @@ pub fn record_sse_connection(status: &str) {
     SSE_CONNECTIONS.with_label_values(&[status]).inc();
 }
 
+/// Records an upstream stream aborted by the stall watchdog.
+pub fn record_stream_stall(model: &str, reason: &str) {
+    STREAM_STALLS.with_label_values(&[model, reason]).inc();
+}
+
 /// Records a translation failure between Anthropic and Gemini formats.
 pub fn record_translation_error(direction: &str, error_type: &str) {
     TRANSLATION_ERRORS
@@ -154,26 +161,88 @@ pub fn record_translation_error(direction: &str, error_type: &str) {
         .inc();
 }
 
+/// Records which routing layer (and rule, if any) picked the Gemini model.
+pub fn record_routing_decision(source: &str, rule: &str, gemini_model: &str) {
+    ROUTING_DECISIONS
+        .with_label_values(&[source, rule, gemini_model])
+        .inc();
+}
+
+/// Records the Gemini model that served a request routed to `routed_model`.
+pub fn record_served_model(routed_model: &str, served_model: &str) {
+    SERVED_MODELS
+        .with_label_values(&[routed_model, served_model])
+        .inc();
+}
+
 /// Updates the reported availability status of a model.
 ///
 /// This sets the gauge for the current status to 1.0 and resets all other
 /// unique statuses to 0.0 to ensure a single active state per model.
-pub fn record_model_health(model: &str, status: &str, unique_statuses: &[&str]) {
+pub fn record_model_health(account: &str, model: &str, status: &str, unique_statuses: &[&str]) {
     // Reset other statuses to 0 so we only have one "1" per model
     for s in unique_statuses {
         let val = if *s == status { 1.0 } else { 0.0 };
         GEMINI_MODEL_AVAILABILITY
-            .with_label_values(&[model, s])
+            .with_label_values(&[account, model, s])
             .set(val);
     }
 }
 
+/// Sets the circuit breaker state for an endpoint and model
+/// (0 = closed, 1 = half-open, 2 = open).
+pub fn record_circuit_state(endpoint: &str, model: &str, state: f64) {
+    GEMINI_CIRCUIT_STATE
+        .with_label_values(&[endpoint, model])
+        .set(state);
+}
+
+/// Updates the quota gauges for an account and model. Gauges without a value
+/// are removed rather than left at a stale one.
+pub fn update_quota(
+    account: &str,
+    model: &str,
+    remaining_fraction: Option<f64>,
+    reset_unix_secs: Option<i64>,
+) {
+    match remaining_fraction {
+        Some(fraction) => QUOTA_REMAINING_FRACTION
+            .with_label_values(&[account, model])
+            .set(fraction),
+        None => {
+            let _ = QUOTA_REMAINING_FRACTION.remove_label_values(&[account, model]);
+        }
+    }
+    match reset_unix_secs {
+        Some(reset) => QUOTA_RESET_TIMESTAMP
+            .with_label_values(&[account, model])
+            .set(reset as f64),
+        None => {
+            let _ = QUOTA_RESET_TIMESTAMP.remove_label_values(&[account, model]);
+        }
+    }
+}
+
+/// Records the outcome of an upstream attempt made with a pooled account.
+pub fn record_account_request(account: &str, outcome: &str) {
+    ACCOUNT_REQUESTS
+        .with_label_values(&[account, outcome])
+        .inc();
+}
+
+/// Records when a pooled account was last rate limited.
+pub fn update_account_rate_limited(account: &str, unix_secs: i64) {
+    ACCOUNT_LAST_RATE_LIMITED
+        .with_label_values(&[account])
+        .set(unix_secs as f64);
+}
+
 /// Records a client-side retry attempt for a specific model and reason.
 pub fn record_retry_attempt(model: &str, reason: &str) {
     GEMINI_RETRIES.with_label_values(&[model, reason]).inc();
 }
 
-/// Observes the duration waited due to upstream rate limiting (429).
+/// Observes the time a request waited in the rate limiter queue.
 pub fn record_rate_limit_wait(model: &str, duration_secs: f64) {
     GEMINI_RATE_LIMIT_WAIT_SECONDS
         .with_label_values(&[model])
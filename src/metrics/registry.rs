@@ -134,6 +134,13 @@ lazy_static! {
         REGISTRY
     ).unwrap();
 
+    /// Counts upstream streams aborted by the stall watchdog.
+    pub static ref STREAM_STALLS: CounterVec = register_counter_vec_with_registry!(
+        Opts::new("gemini_stream_stalls_total", "Upstream streams aborted by the stall watchdog"),
+        &["model", "reason"], // reason: first_chunk, idle, thinking
+        REGISTRY
+    ).unwrap();
+
     // ============================================================================
     // TRANSLATION METRICS (Internal transformation logic)
     // ============================================================================
@@ -152,6 +159,24 @@ lazy_static! {
         REGISTRY
     ).unwrap();
 
+    // ============================================================================
+    // ROUTING METRICS
+    // ============================================================================
+
+    /// Model routing decisions by the layer that made them.
+    pub static ref ROUTING_DECISIONS: CounterVec = register_counter_vec_with_registry!(
+        Opts::new("gemini_routing_decisions_total", "Model routing decisions"),
+        &["source", "rule", "gemini_model"], // source: rule, config, builtin, fallback
+        REGISTRY
+    ).unwrap();
+
+    /// Requests by routed Gemini model and the model that actually served them.
+    pub static ref SERVED_MODELS: CounterVec = register_counter_vec_with_registry!(
+        Opts::new("gemini_served_model_total", "Requests served per routed and serving Gemini model"),
+        &["routed_model", "served_model"],
+        REGISTRY
+    ).unwrap();
+
     // ============================================================================
     // AVAILABILITY & RATE LIMIT METRICS
     // ============================================================================
@@ -160,7 +185,42 @@ lazy_static! {
     /// 1 = Available/Healthy, 0 = Marked for Retry or Terminal Error.
     pub static ref GEMINI_MODEL_AVAILABILITY: GaugeVec = register_gauge_vec_with_registry!(
         Opts::new("gemini_model_availability", "Reported upstream model health status (1=Available, 0=Unavailable)"),
-        &["model", "status"], // status: healthy, sticky_retry, terminal
+        &["account", "model", "status"], // status: healthy, sticky_retry, terminal, half_open
+        REGISTRY
+    ).unwrap();
+
+    /// Circuit breaker state per upstream endpoint and model.
+    pub static ref GEMINI_CIRCUIT_STATE: GaugeVec = register_gauge_vec_with_registry!(
+        Opts::new("gemini_circuit_state", "Upstream circuit breaker state (0=closed, 1=half-open, 2=open)"),
+        &["endpoint", "model"],
+        REGISTRY
+    ).unwrap();
+
+    /// Remaining share of each account's Code Assist quota per model.
+    pub static ref QUOTA_REMAINING_FRACTION: GaugeVec = register_gauge_vec_with_registry!(
+        Opts::new("gemini_quota_remaining_fraction", "Remaining Code Assist quota per account and model (0-1)"),
+        &["account", "model"],
+        REGISTRY
+    ).unwrap();
+
+    /// Unix time at which each account's quota for a model refills.
+    pub static ref QUOTA_RESET_TIMESTAMP: GaugeVec = register_gauge_vec_with_registry!(
+        Opts::new("gemini_quota_reset_timestamp_seconds", "Unix time at which the Code Assist quota refills"),
+        &["account", "model"],
+        REGISTRY
+    ).unwrap();
+
+    /// Upstream attempts per pooled account.
+    pub static ref ACCOUNT_REQUESTS: CounterVec = register_counter_vec_with_registry!(
+        Opts::new("gemini_account_requests_total", "Upstream attempts per pooled account"),
+        &["account", "outcome"], // outcome: success, rate_limited, error
+        REGISTRY
+    ).unwrap();
+
+    /// Unix time of each account's most recent rate limit.
+    pub static ref ACCOUNT_LAST_RATE_LIMITED: GaugeVec = register_gauge_vec_with_registry!(
+        Opts::new("gemini_account_last_rate_limited_timestamp_seconds", "Unix time of the account's most recent rate limit"),
+        &["account"],
         REGISTRY
     ).unwrap();
 
@@ -171,10 +231,10 @@ lazy_static! {
         REGISTRY
     ).unwrap();
 
-    /// Wait duration spent due to 429 Rate Limit responses.
+    /// Time requests spent queued by the client-side concurrency and RPM limiter.
     pub static ref GEMINI_RATE_LIMIT_WAIT_SECONDS: HistogramVec = register_histogram_vec_with_registry!(
         prometheus::HistogramOpts::new("gemini_rate_limit_wait_seconds", "Total time spent waiting on rate limits")
-            .buckets(vec![1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0]),
+            .buckets(vec![0.01, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0]),
         &["model"],
         REGISTRY
     ).unwrap();
@@ -52,6 +52,18 @@ pub struct MessagesRequest {
     /// Whether to incrementally stream the response using server-sent events.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stream: Option<bool>,
+
+    /// Request metadata (Claude Code puts its session ID in `user_id`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<RequestMetadata>,
+}
+
+/// Metadata attached to a messages request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestMetadata {
+    /// Opaque identifier of the end user or session.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
 }
 
 /// System prompt can be either a simple string or structured blocks
@@ -111,13 +123,30 @@ pub enum ContentBlock {
         cache_control: Option<CacheControl>,
     },
     /// Extended thinking block (Claude's thinking feature)
-    Thinking { thinking: String },
+    Thinking {
+        thinking: String,
+        /// Opaque signature; wraps Gemini's thoughtSignature for blocks this proxy emitted.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signature: Option<String>,
+    },
+    /// Thinking content encrypted by the provider, returned verbatim in history.
+    RedactedThinking { data: String },
     /// An image content block.
     Image {
         source: ImageSource,
         #[serde(skip_serializing_if = "Option::is_none")]
         cache_control: Option<CacheControl>,
     },
+    /// A document content block (PDF or plain text).
+    Document {
+        source: DocumentSource,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        title: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        context: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cache_control: Option<CacheControl>,
+    },
     /// A tool use request from the model.
     ToolUse {
         id: String,
@@ -184,11 +213,71 @@ pub enum ImageSource {
         media_type: Option<String>,
         data: String,
     },
+    /// Image referenced by URL, fetched by the proxy before forwarding.
+    Url { url: String },
+    /// Image previously uploaded through the Files API.
+    File { file_id: String },
+}
+
+/// Document source for document content blocks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum DocumentSource {
+    /// Base64-encoded document data (e.g. a PDF).
+    Base64 {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        media_type: Option<String>,
+        data: String,
+    },
+    /// Inline plain-text document.
+    Text {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        media_type: Option<String>,
+        data: String,
+    },
+    /// Document referenced by URL, fetched by the proxy before forwarding.
+    Url { url: String },
+    /// Document previously uploaded through the Files API.
+    File { file_id: String },
 }
 
 /// Image block convenience type
 pub type ImageBlock = ContentBlock;
 
+/// Mutable reference to the source of an image or document block.
+pub enum MediaSourceMut<'a> {
+    Image(&'a mut ImageSource),
+    Document(&'a mut DocumentSource),
+}
+
+/// Collects the source of every image and document block in `messages`,
+/// including those nested in tool results.
+pub fn media_sources_mut(messages: &mut [Message]) -> Vec<MediaSourceMut<'_>> {
+    fn walk<'a>(blocks: &'a mut [ContentBlock], sources: &mut Vec<MediaSourceMut<'a>>) {
+        for block in blocks {
+            match block {
+                ContentBlock::Image { source, .. } => sources.push(MediaSourceMut::Image(source)),
+                ContentBlock::Document { source, .. } => {
+                    sources.push(MediaSourceMut::Document(source))
+                }
+                ContentBlock::ToolResult {
+                    content: ToolResultContent::Blocks(nested),
+                    ..
+                } => walk(nested, sources),
+                _ => {}
+            }
+        }
+    }
+
+    let mut sources = Vec::new();
+    for message in messages {
+        if let MessageContent::Blocks(blocks) = &mut message.content {
+            walk(blocks, &mut sources);
+        }
+    }
+    sources
+}
+
 /// Tool definition
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Tool {
@@ -278,3 +367,36 @@ pub struct ThinkingConfig {
     /// Maximum number of tokens allowed for thinking.
     pub budget_tokens: u32,
 }
+
+/// Model object returned by `GET /v1/models`.
+///
+/// Besides Anthropic's fields it reports the Gemini backend and its capabilities.
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelInfo {
+    /// Object type (always "model").
+    #[serde(rename = "type")]
+    pub object_type: String,
+    /// Model name accepted in `/v1/messages`.
+    pub id: String,
+    /// Human-readable name.
+    pub display_name: String,
+    /// RFC 3339 release timestamp (the proxy reports the Unix epoch).
+    pub created_at: String,
+    /// Gemini model requests are routed to.
+    pub gemini_model: String,
+    /// Capabilities of the Gemini model.
+    pub capabilities: crate::models::capabilities::ModelCapabilities,
+}
+
+/// Response for `GET /v1/models`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelList {
+    /// Models, sorted by ID.
+    pub data: Vec<ModelInfo>,
+    /// ID of the first model.
+    pub first_id: Option<String>,
+    /// ID of the last model.
+    pub last_id: Option<String>,
+    /// Always false; every model is returned in one page.
+    pub has_more: bool,
+}
@@ -0,0 +1,380 @@
+// Model capability registry
+// Author: kelexine (https://github.com/kelexine)
+//
+// Describes what each Gemini model accepts: output and context limits,
+// sampling parameters, tools/vision/thinking support and stop-sequence limits.
+// Gemini models also differ in how thinking is controlled: Gemini 3.x takes a
+// `thinkingLevel`, Gemini 2.5 a token `thinkingBudget` with model-specific
+// limits. Entries from `[[models]]` in the config are checked first, field by
+// field, then the built-in table below.
+
+use crate::config::{
+    ModelConfig, ModelThinkingConfig, ThinkingBucket, ThinkingMapping, ThinkingMode,
+};
+use crate::error::{ProxyError, Result};
+use crate::models::gemini::ThinkingConfig as GeminiThinkingConfig;
+use serde::Serialize;
+use tracing::debug;
+
+/// Fully resolved capabilities of one Gemini model.
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelCapabilities {
+    /// Largest `max_tokens` forwarded upstream.
+    pub max_output_tokens: u32,
+    /// Input context window in tokens.
+    pub context_window: u32,
+    /// Whether `temperature` is forwarded.
+    pub supports_temperature: bool,
+    /// Whether `top_p` is forwarded.
+    pub supports_top_p: bool,
+    /// Whether `top_k` is forwarded.
+    pub supports_top_k: bool,
+    /// Whether tool definitions are forwarded.
+    pub supports_tools: bool,
+    /// Whether images and documents are forwarded.
+    pub supports_vision: bool,
+    /// Whether extended thinking is enabled.
+    pub supports_thinking: bool,
+    /// Maximum number of stop sequences forwarded.
+    pub max_stop_sequences: usize,
+    /// Thinking budget translation table.
+    pub thinking: ModelThinkingConfig,
+}
+
+/// Resolves capabilities for Gemini model names.
+#[derive(Debug, Clone)]
+pub struct ModelRegistry {
+    /// Configured entries followed by the built-in table (ending in `*`).
+    entries: Vec<ModelConfig>,
+}
+
+impl Default for ModelRegistry {
+    fn default() -> Self {
+        Self {
+            entries: builtin_models(),
+        }
+    }
+}
+
+impl ModelRegistry {
+    /// Build a registry with `overrides` taking precedence over the built-in table.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProxyError::Config` if a thinking table is inconsistent (e.g.
+    /// level mode without levelled buckets, or `min_budget > max_budget`).
+    pub fn new(overrides: &[ModelConfig]) -> Result<Self> {
+        let mut entries = overrides.to_vec();
+        for entry in &mut entries {
+            if let Some(thinking) = &mut entry.thinking {
+                validate_thinking(&entry.name, thinking)?;
+                thinking.buckets.sort_by_key(|b| b.up_to);
+            }
+        }
+        entries.extend(builtin_models());
+
+        Ok(Self { entries })
+    }
+
+    /// Resolve every capability of a Gemini model.
+    pub fn capabilities(&self, gemini_model: &str) -> ModelCapabilities {
+        ModelCapabilities {
+            max_output_tokens: self.resolve(gemini_model, |e| e.max_output_tokens),
+            context_window: self.resolve(gemini_model, |e| e.context_window),
+            supports_temperature: self.resolve(gemini_model, |e| e.supports_temperature),
+            supports_top_p: self.resolve(gemini_model, |e| e.supports_top_p),
+            supports_top_k: self.resolve(gemini_model, |e| e.supports_top_k),
+            supports_tools: self.resolve(gemini_model, |e| e.supports_tools),
+            supports_vision: self.resolve(gemini_model, |e| e.supports_vision),
+            supports_thinking: self.resolve(gemini_model, |e| e.supports_thinking),
+            max_stop_sequences: self.resolve(gemini_model, |e| e.max_stop_sequences),
+            thinking: self.thinking(gemini_model).clone(),
+        }
+    }
+
+    /// Thinking table for a Gemini model.
+    pub fn thinking(&self, gemini_model: &str) -> &ModelThinkingConfig {
+        self.entries
+            .iter()
+            .filter(|entry| matches_model(&entry.name, gemini_model))
+            .find_map(|entry| entry.thinking.as_ref())
+            .expect("built-in model table ends with a catch-all entry")
+    }
+
+    /// First value of a field among the entries matching `gemini_model`.
+    fn resolve<T>(&self, gemini_model: &str, field: impl Fn(&ModelConfig) -> Option<T>) -> T {
+        self.entries
+            .iter()
+            .filter(|entry| matches_model(&entry.name, gemini_model))
+            .find_map(field)
+            .expect("built-in model table ends with a catch-all entry")
+    }
+
+    /// Translate an Anthropic thinking budget into the model's thinking config.
+    pub fn thinking_config(&self, gemini_model: &str, budget_tokens: u32) -> GeminiThinkingConfig {
+        let table = self.thinking(gemini_model);
+        let clamped = budget_tokens.clamp(table.min_budget, table.max_budget);
+
+        let config = match (table.mode, table.mapping) {
+            (ThinkingMode::Level, _) => GeminiThinkingConfig {
+                include_thoughts: Some(true),
+                thinking_budget: None,
+                thinking_level: bucket_for(&table.buckets, clamped).and_then(|b| b.level.clone()),
+            },
+            (ThinkingMode::Budget, ThinkingMapping::Passthrough) => GeminiThinkingConfig {
+                include_thoughts: Some(true),
+                thinking_budget: Some(clamped),
+                thinking_level: None,
+            },
+            (ThinkingMode::Budget, ThinkingMapping::Buckets) => GeminiThinkingConfig {
+                include_thoughts: Some(true),
+                thinking_budget: bucket_for(&table.buckets, clamped)
+                    .and_then(|b| b.budget)
+                    .map(|budget| budget.clamp(table.min_budget, table.max_budget)),
+                thinking_level: None,
+            },
+        };
+
+        debug!(
+            "Thinking budget {} for {} -> level: {:?}, budget: {:?}",
+            budget_tokens, gemini_model, config.thinking_level, config.thinking_budget
+        );
+        config
+    }
+}
+
+/// Match a model name against an exact name or a `prefix*` pattern.
+pub fn matches_model(pattern: &str, model: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => model.starts_with(prefix),
+        None => pattern == model,
+    }
+}
+
+/// First bucket covering `budget`, or the last bucket for larger budgets.
+fn bucket_for(buckets: &[ThinkingBucket], budget: u32) -> Option<&ThinkingBucket> {
+    buckets
+        .iter()
+        .find(|b| budget <= b.up_to)
+        .or_else(|| buckets.last())
+}
+
+fn validate_thinking(name: &str, thinking: &ModelThinkingConfig) -> Result<()> {
+    let invalid = |reason: &str| {
+        Err(ProxyError::Config(format!(
+            "Invalid thinking table for model {}: {}",
+            name, reason
+        )))
+    };
+
+    if thinking.min_budget > thinking.max_budget {
+        return invalid("min_budget is greater than max_budget");
+    }
+    match (thinking.mode, thinking.mapping) {
+        (ThinkingMode::Level, _)
+            if thinking.buckets.is_empty()
+                || thinking.buckets.iter().any(|b| b.level.is_none()) =>
+        {
+            invalid("level mode needs buckets that each set `level`")
+        }
+        (ThinkingMode::Budget, ThinkingMapping::Buckets)
+            if thinking.buckets.is_empty()
+                || thinking.buckets.iter().any(|b| b.budget.is_none()) =>
+        {
+            invalid("bucket mapping needs buckets that each set `budget`")
+        }
+        _ => Ok(()),
+    }
+}
+
+fn budget_passthrough(name: &str, min_budget: u32, max_budget: u32) -> ModelConfig {
+    ModelConfig {
+        name: name.to_string(),
+        thinking: Some(ModelThinkingConfig {
+            mode: ThinkingMode::Budget,
+            min_budget,
+            max_budget,
+            mapping: ThinkingMapping::Passthrough,
+            buckets: Vec::new(),
+        }),
+        ..ModelConfig::default()
+    }
+}
+
+/// Built-in capability table, most specific entries first.
+fn builtin_models() -> Vec<ModelConfig> {
+    let level = |up_to: u32, level: &str| ThinkingBucket {
+        up_to,
+        level: Some(level.to_string()),
+        budget: None,
+    };
+
+    vec![
+        ModelConfig {
+            name: "gemini-3*".to_string(),
+            thinking: Some(ModelThinkingConfig {
+                mode: ThinkingMode::Level,
+                min_budget: 0,
+                max_budget: u32::MAX,
+                mapping: ThinkingMapping::Buckets,
+                buckets: vec![
+                    level(15_000, "LOW"),
+                    level(20_000, "MEDIUM"),
+                    level(u32::MAX, "HIGH"),
+                ],
+            }),
+            ..ModelConfig::default()
+        },
+        budget_passthrough("gemini-2.5-pro*", 128, 32_768),
+        budget_passthrough("gemini-2.5-flash-lite*", 512, 24_576),
+        budget_passthrough("gemini-2.5-flash*", 0, 24_576),
+        // Catch-all: every field must be set here so resolution always succeeds
+        ModelConfig {
+            max_output_tokens: Some(65_536),
+            context_window: Some(1_048_576),
+            supports_temperature: Some(true),
+            supports_top_p: Some(true),
+            supports_top_k: Some(true),
+            supports_tools: Some(true),
+            supports_vision: Some(true),
+            supports_thinking: Some(true),
+            max_stop_sequences: Some(5),
+            ..budget_passthrough("*", 0, 32_768)
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(registry: &ModelRegistry, model: &str, budget: u32) -> Option<String> {
+        registry.thinking_config(model, budget).thinking_level
+    }
+
+    fn budget(registry: &ModelRegistry, model: &str, budget: u32) -> Option<u32> {
+        registry.thinking_config(model, budget).thinking_budget
+    }
+
+    #[test]
+    fn test_gemini_3_level_boundaries() {
+        let registry = ModelRegistry::default();
+        let model = "gemini-3-pro-preview";
+
+        assert_eq!(level(&registry, model, 1_024).as_deref(), Some("LOW"));
+        assert_eq!(level(&registry, model, 15_000).as_deref(), Some("LOW"));
+        assert_eq!(level(&registry, model, 15_001).as_deref(), Some("MEDIUM"));
+        assert_eq!(level(&registry, model, 20_000).as_deref(), Some("MEDIUM"));
+        assert_eq!(level(&registry, model, 20_001).as_deref(), Some("HIGH"));
+        assert_eq!(budget(&registry, model, 20_001), None);
+    }
+
+    #[test]
+    fn test_gemini_2_5_passthrough_is_clamped() {
+        let registry = ModelRegistry::default();
+
+        assert_eq!(budget(&registry, "gemini-2.5-pro", 2_048), Some(2_048));
+        assert_eq!(budget(&registry, "gemini-2.5-pro", 64), Some(128));
+        assert_eq!(budget(&registry, "gemini-2.5-pro", 32_768), Some(32_768));
+        assert_eq!(budget(&registry, "gemini-2.5-pro", 32_769), Some(32_768));
+        assert_eq!(budget(&registry, "gemini-2.5-flash", 30_000), Some(24_576));
+        assert_eq!(budget(&registry, "gemini-2.5-flash-lite", 100), Some(512));
+        assert_eq!(budget(&registry, "some-future-model", 50_000), Some(32_768));
+    }
+
+    #[test]
+    fn test_configured_buckets_override_builtin() {
+        let overrides = vec![ModelConfig {
+            name: "gemini-2.5-pro".to_string(),
+            thinking: Some(ModelThinkingConfig {
+                mode: ThinkingMode::Budget,
+                min_budget: 128,
+                max_budget: 16_000,
+                mapping: ThinkingMapping::Buckets,
+                buckets: vec![
+                    ThinkingBucket {
+                        up_to: 20_000,
+                        level: None,
+                        budget: Some(24_000),
+                    },
+                    ThinkingBucket {
+                        up_to: 4_096,
+                        level: None,
+                        budget: Some(4_096),
+                    },
+                ],
+            }),
+            ..ModelConfig::default()
+        }];
+        let registry = ModelRegistry::new(&overrides).unwrap();
+
+        assert_eq!(budget(&registry, "gemini-2.5-pro", 4_096), Some(4_096));
+        assert_eq!(budget(&registry, "gemini-2.5-pro", 4_097), Some(16_000));
+        assert_eq!(budget(&registry, "gemini-2.5-pro", 90_000), Some(16_000));
+        // Other models still use the built-in table
+        assert_eq!(budget(&registry, "gemini-2.5-pro-exp", 4_097), Some(4_097));
+    }
+
+    #[test]
+    fn test_invalid_tables_are_rejected() {
+        let table = |mode, mapping, buckets| {
+            vec![ModelConfig {
+                name: "gemini-x".to_string(),
+                thinking: Some(ModelThinkingConfig {
+                    mode,
+                    min_budget: 0,
+                    max_budget: 1_000,
+                    mapping,
+                    buckets,
+                }),
+                ..ModelConfig::default()
+            }]
+        };
+
+        assert!(ModelRegistry::new(&table(
+            ThinkingMode::Level,
+            ThinkingMapping::Buckets,
+            vec![]
+        ))
+        .is_err());
+        assert!(ModelRegistry::new(&table(
+            ThinkingMode::Budget,
+            ThinkingMapping::Buckets,
+            vec![ThinkingBucket {
+                up_to: 10,
+                level: Some("LOW".to_string()),
+                budget: None,
+            }],
+        ))
+        .is_err());
+        assert!(ModelRegistry::new(&table(
+            ThinkingMode::Budget,
+            ThinkingMapping::Passthrough,
+            vec![]
+        ))
+        .is_ok());
+    }
+
+    #[test]
+    fn test_capability_fields_fall_through() {
+        let overrides = vec![ModelConfig {
+            name: "gemini-2.5-flash*".to_string(),
+            max_output_tokens: Some(8_192),
+            supports_top_k: Some(false),
+            ..ModelConfig::default()
+        }];
+        let registry = ModelRegistry::new(&overrides).unwrap();
+
+        let flash = registry.capabilities("gemini-2.5-flash");
+        assert_eq!(flash.max_output_tokens, 8_192);
+        assert!(!flash.supports_top_k);
+        assert!(flash.supports_top_p);
+        assert_eq!(flash.max_stop_sequences, 5);
+        // Thinking still comes from the built-in flash entry
+        assert_eq!(flash.thinking.max_budget, 24_576);
+
+        let pro = registry.capabilities("gemini-2.5-pro");
+        assert_eq!(pro.max_output_tokens, 65_536);
+        assert!(pro.supports_top_k);
+    }
+}
@@ -53,6 +53,13 @@ pub fn map_model(claude_model: &str) -> Result<String> {
         })
 }
 
+/// All supported Claude model names with their Gemini backends, sorted by name
+pub fn supported_models() -> Vec<(&'static str, &'static str)> {
+    let mut models: Vec<_> = MODEL_MAP.entries().map(|(k, v)| (*k, *v)).collect();
+    models.sort_unstable();
+    models
+}
+
 /// Strip date suffix from model names (e.g., "claude-sonnet-4-5-20250929" -> "claude-sonnet-4-5")
 fn strip_date_suffix(model: &str) -> String {
     // Date suffixes are 8 digits at the end: YYYYMMDD
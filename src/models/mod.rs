@@ -4,22 +4,28 @@
 //! - The inbound Anthropic-compatible API (`anthropic`)
 //! - The upstream Google Gemini API (`gemini`)
 //! - Model name mapping utilities (`mapping`)
+//! - Per-model capability table (`capabilities`)
+//! - Runtime model routing table (`routing`)
 //! - Streaming event types (`streaming`)
 
 // Author: kelexine (https://github.com/kelexine)
 
 pub mod anthropic;
+pub mod capabilities;
 pub mod gemini;
 pub mod mapping;
+pub mod routing;
 pub mod streaming;
 
 pub use anthropic::{
     ContentBlock, Message, MessageContent, MessagesRequest, MessagesResponse,
     ThinkingConfig as AnthropicThinkingConfig, Tool,
 };
+pub use capabilities::ModelRegistry;
 pub use gemini::{
     Content, GenerateContentRequest, GenerateContentResponse, Part,
     ThinkingConfig as GeminiThinkingConfig,
 };
 pub use mapping::map_model;
+pub use routing::{ModelRouter, ResolvedRoute, RouteSource};
 pub use streaming::*;
@@ -0,0 +1,630 @@
+// Runtime model routing (Claude model name → Gemini model)
+// Author: kelexine (https://github.com/kelexine)
+//
+// Resolution order:
+// 1. Configured `[[routing.rules]]` on request characteristics, first match wins
+// 2. Configured `[[routing.routes]]`, first match wins (exact, glob or regex)
+// 3. The built-in `MODEL_MAP`
+// 4. `gemini.default_model`, when `routing.fallback_to_default_model` is set
+//
+// `[[routing.fallbacks]]` chains then list the Gemini models that may serve a
+// request instead of the routed one.
+
+use crate::config::{RouteConfig, RoutingConfig, RoutingRule};
+use crate::error::{ProxyError, Result};
+use crate::models::anthropic::{ContentBlock, MessageContent, MessagesRequest};
+use crate::models::mapping::{map_model, supported_models};
+use regex::Regex;
+use tracing::{debug, info};
+
+/// Which layer of the routing table produced a route.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteSource {
+    /// A request rule.
+    Rule,
+    /// A configured route.
+    Config,
+    /// The built-in model map.
+    Builtin,
+    /// The `default_model` fallback.
+    Fallback,
+}
+
+impl RouteSource {
+    /// Label used in logs and metrics.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RouteSource::Rule => "rule",
+            RouteSource::Config => "config",
+            RouteSource::Builtin => "builtin",
+            RouteSource::Fallback => "fallback",
+        }
+    }
+}
+
+/// Result of routing a model name.
+#[derive(Debug, Clone)]
+pub struct ResolvedRoute {
+    /// Gemini model to call.
+    pub gemini_model: String,
+    /// Layer that produced the route.
+    pub source: RouteSource,
+    /// Matching configured route, if any (carries request overrides).
+    pub route: Option<RouteConfig>,
+    /// Name of the matching request rule, if any.
+    pub rule: Option<String>,
+    /// Named backend to serve the request (`None` for the Code Assist pool).
+    pub backend: Option<String>,
+}
+
+impl ResolvedRoute {
+    /// Apply the route's request overrides.
+    pub fn apply_overrides(&self, req: &mut MessagesRequest) {
+        let Some(route) = &self.route else {
+            return;
+        };
+
+        if let Some(cap) = route.max_tokens {
+            req.max_tokens = req.max_tokens.min(cap);
+        }
+        if route.temperature.is_some() {
+            req.temperature = route.temperature;
+        }
+        if route.top_p.is_some() {
+            req.top_p = route.top_p;
+        }
+        if route.top_k.is_some() {
+            req.top_k = route.top_k;
+        }
+        if let (Some(budget), Some(thinking)) = (route.thinking_budget, req.thinking.as_mut()) {
+            thinking.budget_tokens = budget;
+        }
+    }
+}
+
+/// Routes Claude model names to Gemini models.
+#[derive(Debug, Clone)]
+pub struct ModelRouter {
+    rules: Vec<CompiledRule>,
+    routes: Vec<(Regex, RouteConfig)>,
+    fallbacks: Vec<Vec<String>>,
+    default_model: String,
+    fallback_to_default_model: bool,
+}
+
+impl ModelRouter {
+    /// Compile the routing table.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProxyError::Config` if a route sets neither or both of `model`
+    /// and `regex`, or if a pattern does not compile.
+    pub fn new(config: &RoutingConfig, default_model: &str) -> Result<Self> {
+        let rules = config
+            .rules
+            .iter()
+            .map(CompiledRule::new)
+            .collect::<Result<Vec<_>>>()?;
+
+        let routes = config
+            .routes
+            .iter()
+            .map(|route| {
+                let pattern = match (&route.model, &route.regex) {
+                    (Some(glob), None) => glob_to_regex(glob),
+                    (None, Some(regex)) => format!("^(?:{})$", regex),
+                    _ => {
+                        return Err(ProxyError::Config(format!(
+                            "Route to {} must set exactly one of `model` or `regex`",
+                            route.gemini_model
+                        )))
+                    }
+                };
+                let regex = Regex::new(&pattern).map_err(|e| {
+                    ProxyError::Config(format!("Invalid route pattern {}: {}", pattern, e))
+                })?;
+                Ok((regex, route.clone()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            rules,
+            routes,
+            fallbacks: config
+                .fallbacks
+                .iter()
+                .map(|chain| chain.models.clone())
+                .collect(),
+            default_model: default_model.to_string(),
+            fallback_to_default_model: config.fallback_to_default_model,
+        })
+    }
+
+    /// Route a request, checking request rules before the model name.
+    ///
+    /// The decision is logged and counted in metrics.
+    ///
+    /// # Errors
+    ///
+    /// See [`ModelRouter::resolve`].
+    pub fn route_request(&self, req: &MessagesRequest, client: &str) -> Result<ResolvedRoute> {
+        let input_tokens = estimate_input_tokens(req);
+        let route = match self
+            .rules
+            .iter()
+            .find(|rule| rule.matches(req, client, input_tokens))
+        {
+            Some(rule) => ResolvedRoute {
+                gemini_model: rule.rule.gemini_model.clone(),
+                source: RouteSource::Rule,
+                route: None,
+                rule: Some(rule.rule.name.clone()),
+                backend: rule.rule.backend.clone(),
+            },
+            None => self.resolve(&req.model)?,
+        };
+
+        info!(
+            "Routing {} -> {}{} ({}{}, ~{} input tokens, client: {})",
+            req.model,
+            route.gemini_model,
+            route
+                .backend
+                .as_deref()
+                .map(|name| format!(" on backend {}", name))
+                .unwrap_or_default(),
+            route.source.as_str(),
+            route
+                .rule
+                .as_deref()
+                .map(|name| format!(" {}", name))
+                .unwrap_or_default(),
+            input_tokens,
+            client
+        );
+        crate::metrics::record_routing_decision(
+            route.source.as_str(),
+            route.rule.as_deref().unwrap_or(""),
+            &route.gemini_model,
+        );
+
+        Ok(route)
+    }
+
+    /// Resolve the Gemini model for a requested model name.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProxyError::InvalidRequest` for unknown models when the
+    /// `default_model` fallback is disabled.
+    pub fn resolve(&self, model: &str) -> Result<ResolvedRoute> {
+        if let Some((_, route)) = self.routes.iter().find(|(re, _)| re.is_match(model)) {
+            debug!(
+                "Routing {} -> {} (configured route)",
+                model, route.gemini_model
+            );
+            return Ok(ResolvedRoute {
+                gemini_model: route.gemini_model.clone(),
+                source: RouteSource::Config,
+                route: Some(route.clone()),
+                rule: None,
+                backend: route.backend.clone(),
+            });
+        }
+
+        match map_model(model) {
+            Ok(gemini_model) => Ok(ResolvedRoute {
+                gemini_model,
+                source: RouteSource::Builtin,
+                route: None,
+                rule: None,
+                backend: None,
+            }),
+            Err(_) if self.fallback_to_default_model => {
+                info!(
+                    "No route for model {}, falling back to default model {}",
+                    model, self.default_model
+                );
+                Ok(ResolvedRoute {
+                    gemini_model: self.default_model.clone(),
+                    source: RouteSource::Fallback,
+                    route: None,
+                    rule: None,
+                    backend: None,
+                })
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The Gemini model followed by the models after it in the first
+    /// fallback chain that contains it.
+    pub fn fallback_chain(&self, gemini_model: &str) -> Vec<String> {
+        let mut chain = vec![gemini_model.to_string()];
+        if let Some(models) = self
+            .fallbacks
+            .iter()
+            .find(|models| models.iter().any(|m| m == gemini_model))
+        {
+            chain.extend(
+                models
+                    .iter()
+                    .skip_while(|m| *m != gemini_model)
+                    .skip(1)
+                    .cloned(),
+            );
+        }
+        chain
+    }
+
+    /// Backend names referenced by rules and routes.
+    pub fn backend_names(&self) -> impl Iterator<Item = &str> {
+        self.rules
+            .iter()
+            .filter_map(|rule| rule.rule.backend.as_deref())
+            .chain(
+                self.routes
+                    .iter()
+                    .filter_map(|(_, route)| route.backend.as_deref()),
+            )
+    }
+
+    /// Model names with a known route (exact configured names plus the
+    /// built-in map), paired with their Gemini model and sorted by name.
+    pub fn known_models(&self) -> Vec<(String, String)> {
+        let mut names: Vec<String> = self
+            .routes
+            .iter()
+            .filter_map(|(_, route)| route.model.clone())
+            .filter(|name| !name.contains(['*', '?']))
+            .chain(
+                supported_models()
+                    .into_iter()
+                    .map(|(name, _)| name.to_string()),
+            )
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let gemini_model = self.resolve(&name).ok()?.gemini_model;
+                Some((name, gemini_model))
+            })
+            .collect()
+    }
+}
+
+/// A request rule with its patterns compiled.
+#[derive(Debug, Clone)]
+struct CompiledRule {
+    rule: RoutingRule,
+    model: Option<Regex>,
+    system_contains: Vec<String>,
+}
+
+impl CompiledRule {
+    fn new(rule: &RoutingRule) -> Result<Self> {
+        let model = rule
+            .model
+            .as_deref()
+            .map(|glob| {
+                Regex::new(&glob_to_regex(glob)).map_err(|e| {
+                    ProxyError::Config(format!(
+                        "Invalid model pattern in rule {}: {}",
+                        rule.name, e
+                    ))
+                })
+            })
+            .transpose()?;
+
+        Ok(Self {
+            rule: rule.clone(),
+            model,
+            system_contains: rule
+                .system_contains
+                .iter()
+                .map(|s| s.to_lowercase())
+                .collect(),
+        })
+    }
+
+    /// Whether every predicate set on the rule holds for the request.
+    fn matches(&self, req: &MessagesRequest, client: &str, input_tokens: u32) -> bool {
+        let rule = &self.rule;
+        let has_tools = req.tools.as_ref().is_some_and(|tools| !tools.is_empty());
+        let thinking = req
+            .thinking
+            .as_ref()
+            .is_some_and(|thinking| thinking.type_ == "enabled");
+
+        self.model.as_ref().is_none_or(|re| re.is_match(&req.model))
+            && rule.has_tools.is_none_or(|want| want == has_tools)
+            && rule.thinking.is_none_or(|want| want == thinking)
+            && rule
+                .input_tokens_at_least
+                .is_none_or(|min| input_tokens >= min)
+            && rule
+                .input_tokens_at_most
+                .is_none_or(|max| input_tokens <= max)
+            && rule
+                .max_tokens_at_least
+                .is_none_or(|min| req.max_tokens >= min)
+            && rule
+                .max_tokens_at_most
+                .is_none_or(|max| req.max_tokens <= max)
+            && rule
+                .client
+                .as_deref()
+                .is_none_or(|name| name.eq_ignore_ascii_case(client))
+            && (self.system_contains.is_empty() || {
+                let system = req
+                    .system
+                    .as_ref()
+                    .map(|system| system.to_text().to_lowercase())
+                    .unwrap_or_default();
+                self.system_contains
+                    .iter()
+                    .any(|needle| system.contains(needle.as_str()))
+            })
+    }
+}
+
+/// Rough input token estimate (about four bytes per token) over the system
+/// prompt, message text, tool inputs/results and tool definitions.
+fn estimate_input_tokens(req: &MessagesRequest) -> u32 {
+    let system = req.system.as_ref().map_or(0, |s| s.to_text().len());
+    let messages: usize = req
+        .messages
+        .iter()
+        .map(|message| match &message.content {
+            MessageContent::Text(text) => text.len(),
+            MessageContent::Blocks(blocks) => blocks
+                .iter()
+                .map(|block| match block {
+                    ContentBlock::Text { text, .. } => text.len(),
+                    ContentBlock::Thinking { thinking, .. } => thinking.len(),
+                    ContentBlock::ToolUse { input, .. } => input.to_string().len(),
+                    ContentBlock::ToolResult { content, .. } => content.to_string().len(),
+                    _ => 0,
+                })
+                .sum(),
+        })
+        .sum();
+    let tools = req
+        .tools
+        .as_ref()
+        .and_then(|tools| serde_json::to_string(tools).ok())
+        .map_or(0, |json| json.len());
+
+    ((system + messages + tools) / 4) as u32
+}
+
+/// Convert a glob (`*`, `?`) into an anchored regular expression.
+fn glob_to_regex(glob: &str) -> String {
+    let mut pattern = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => pattern.push_str(".*"),
+            '?' => pattern.push('.'),
+            other => pattern.push_str(&regex::escape(&other.to_string())),
+        }
+    }
+    pattern.push('$');
+    pattern
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(model: Option<&str>, regex: Option<&str>, gemini_model: &str) -> RouteConfig {
+        RouteConfig {
+            model: model.map(str::to_string),
+            regex: regex.map(str::to_string),
+            gemini_model: gemini_model.to_string(),
+            ..RouteConfig::default()
+        }
+    }
+
+    fn router(routes: Vec<RouteConfig>, fallback: bool) -> ModelRouter {
+        ModelRouter::new(
+            &RoutingConfig {
+                fallback_to_default_model: fallback,
+                rules: Vec::new(),
+                routes,
+                fallbacks: Vec::new(),
+            },
+            "gemini-3-flash-preview",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_route_precedence() {
+        let router = router(
+            vec![
+                route(Some("claude-sonnet-4-5"), None, "gemini-2.5-pro"),
+                route(Some("claude-*-haiku-*"), None, "gemini-2.5-flash-lite"),
+                route(None, Some(r"claude-opus-\d+"), "gemini-3-pro-preview"),
+            ],
+            true,
+        );
+
+        // Exact configured name overrides the built-in map
+        let exact = router.resolve("claude-sonnet-4-5").unwrap();
+        assert_eq!(exact.gemini_model, "gemini-2.5-pro");
+        assert_eq!(exact.source, RouteSource::Config);
+
+        assert_eq!(
+            router
+                .resolve("claude-3-5-haiku-20241022")
+                .unwrap()
+                .gemini_model,
+            "gemini-2.5-flash-lite"
+        );
+        assert_eq!(
+            router.resolve("claude-opus-5").unwrap().gemini_model,
+            "gemini-3-pro-preview"
+        );
+
+        // Regexes are anchored to the whole name
+        let builtin = router.resolve("claude-opus-4-5").unwrap();
+        assert_eq!(builtin.gemini_model, "gemini-3-pro-preview");
+        assert_eq!(builtin.source, RouteSource::Builtin);
+
+        let fallback = router.resolve("claude-mystery-9").unwrap();
+        assert_eq!(fallback.gemini_model, "gemini-3-flash-preview");
+        assert_eq!(fallback.source, RouteSource::Fallback);
+    }
+
+    #[test]
+    fn test_unknown_model_without_fallback() {
+        let router = router(Vec::new(), false);
+        assert!(router.resolve("claude-mystery-9").is_err());
+        assert!(router.resolve("claude-sonnet-4").is_ok());
+    }
+
+    #[test]
+    fn test_invalid_routes_are_rejected() {
+        let config = |routes| RoutingConfig {
+            fallback_to_default_model: true,
+            rules: Vec::new(),
+            routes,
+            fallbacks: Vec::new(),
+        };
+
+        assert!(ModelRouter::new(&config(vec![route(None, None, "g")]), "d").is_err());
+        assert!(ModelRouter::new(&config(vec![route(Some("a"), Some("b"), "g")]), "d").is_err());
+        assert!(ModelRouter::new(&config(vec![route(None, Some("("), "g")]), "d").is_err());
+    }
+
+    #[test]
+    fn test_route_overrides() {
+        let router = router(
+            vec![RouteConfig {
+                max_tokens: Some(4_096),
+                temperature: Some(0.2),
+                thinking_budget: Some(2_048),
+                ..route(Some("claude-*"), None, "gemini-2.5-pro")
+            }],
+            true,
+        );
+        let mut req: MessagesRequest = serde_json::from_value(serde_json::json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 32000,
+            "temperature": 1.0,
+            "thinking": {"type": "enabled", "budget_tokens": 16000},
+            "messages": [{"role": "user", "content": "hi"}],
+        }))
+        .unwrap();
+
+        router
+            .resolve(&req.model)
+            .unwrap()
+            .apply_overrides(&mut req);
+
+        assert_eq!(req.max_tokens, 4_096);
+        assert_eq!(req.temperature, Some(0.2));
+        assert_eq!(req.thinking.unwrap().budget_tokens, 2_048);
+    }
+
+    #[test]
+    fn test_request_rules() {
+        let router = ModelRouter::new(
+            &RoutingConfig {
+                fallback_to_default_model: true,
+                rules: vec![
+                    RoutingRule {
+                        name: "side_request".to_string(),
+                        gemini_model: "gemini-2.5-flash-lite".to_string(),
+                        has_tools: Some(false),
+                        max_tokens_at_most: Some(512),
+                        system_contains: vec!["CONVERSATION TITLE".to_string()],
+                        ..RoutingRule::default()
+                    },
+                    RoutingRule {
+                        name: "long_context".to_string(),
+                        gemini_model: "gemini-2.5-pro".to_string(),
+                        input_tokens_at_least: Some(1_000),
+                        client: Some("claude-cli".to_string()),
+                        ..RoutingRule::default()
+                    },
+                ],
+                routes: Vec::new(),
+                fallbacks: Vec::new(),
+            },
+            "gemini-3-flash-preview",
+        )
+        .unwrap();
+
+        let request = |system: &str, max_tokens: u32, text: &str| -> MessagesRequest {
+            serde_json::from_value(serde_json::json!({
+                "model": "claude-sonnet-4-5",
+                "max_tokens": max_tokens,
+                "system": system,
+                "messages": [{"role": "user", "content": text}],
+            }))
+            .unwrap()
+        };
+
+        let title = router
+            .route_request(&request("Write a conversation title.", 256, "hi"), "x")
+            .unwrap();
+        assert_eq!(title.gemini_model, "gemini-2.5-flash-lite");
+        assert_eq!(title.source, RouteSource::Rule);
+        assert_eq!(title.rule.as_deref(), Some("side_request"));
+
+        // max_tokens predicate fails, so the name route applies
+        let main = router
+            .route_request(&request("Write a conversation title.", 4096, "hi"), "x")
+            .unwrap();
+        assert_eq!(main.source, RouteSource::Builtin);
+
+        let long = request("", 4096, &"word ".repeat(1_000));
+        assert_eq!(
+            router
+                .route_request(&long, "claude-cli")
+                .unwrap()
+                .rule
+                .as_deref(),
+            Some("long_context")
+        );
+        assert_eq!(
+            router.route_request(&long, "other").unwrap().source,
+            RouteSource::Builtin
+        );
+    }
+
+    #[test]
+    fn test_fallback_chain() {
+        let router = ModelRouter::new(
+            &RoutingConfig {
+                fallbacks: vec![crate::config::FallbackChain {
+                    models: vec![
+                        "gemini-3-pro-preview".to_string(),
+                        "gemini-2.5-pro".to_string(),
+                        "gemini-2.5-flash".to_string(),
+                    ],
+                }],
+                ..RoutingConfig::default()
+            },
+            "gemini-3-flash-preview",
+        )
+        .unwrap();
+
+        assert_eq!(
+            router.fallback_chain("gemini-3-pro-preview"),
+            vec!["gemini-3-pro-preview", "gemini-2.5-pro", "gemini-2.5-flash"]
+        );
+        assert_eq!(
+            router.fallback_chain("gemini-2.5-pro"),
+            vec!["gemini-2.5-pro", "gemini-2.5-flash"]
+        );
+        assert_eq!(
+            router.fallback_chain("gemini-3-flash-preview"),
+            vec!["gemini-3-flash-preview"]
+        );
+    }
+}
@@ -190,7 +190,7 @@ impl OAuthManager {
                 .form(&params)
                 .send()
                 .await
-                .map_err(|e| (500, format!("Google OAuth2 network error: {}", e)))?;
+                .map_err(|e| (0, format!("Google OAuth2 unreachable: {}", e)))?;
 
             let status = response.status();
             if !status.is_success() {
@@ -208,6 +208,8 @@ impl OAuthManager {
         let response = crate::utils::retry::with_retry("OAuth Refresh", request_logic)
             .await
             .map_err(|(status, body)| match status {
+                // Offline: fail fast with a retryable error rather than an auth error
+                0 => ProxyError::ServiceUnavailable(body),
                 429 => ProxyError::TooManyRequests(body),
                 529 => ProxyError::Overloaded(format!("Google overloaded: {}", body)),
                 503 | 504 => ProxyError::ServiceUnavailable(body),
@@ -270,6 +272,22 @@ impl OAuthManager {
         Ok(())
     }
 
+    /// Returns a fingerprint of the signed-in credentials: a truncated
+    /// SHA-256 of the refresh token. It changes when someone logs in again,
+    /// possibly as another Google account, without revealing the token.
+    pub async fn fingerprint(&self) -> String {
+        use sha2::{Digest, Sha256};
+
+        let creds = self.credentials.read().await;
+        hex::encode(&Sha256::digest(creds.refresh_token.as_bytes())[..8])
+    }
+
+    /// Whether an expired token can be replaced without the user: auto-refresh
+    /// is enabled and the credentials hold a refresh token.
+    pub async fn can_refresh(&self) -> bool {
+        self.config.auto_refresh && !self.credentials.read().await.refresh_token.is_empty()
+    }
+
     /// Provides safe access to public token metadata (expiry time).
     pub async fn token_info(&self) -> (i64, bool) {
         let creds = self.credentials.read().await;
@@ -1,9 +1,11 @@
 // OAuth credential management module
 // Author: kelexine (https://github.com/kelexine)
 
+pub mod adc;
 pub mod login;
 mod manager;
 
+pub use adc::AdcTokenSource;
 pub use manager::OAuthManager;
 
 use serde::{Deserialize, Serialize};
@@ -6,6 +6,7 @@
 //!
 //! Author: kelexine (<https://github.com/kelexine>)
 
+use super::probe::ProbeOutcome;
 use super::routes::AppState;
 use axum::{
     extract::State,
@@ -14,11 +15,12 @@ use axum::{
 };
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::Arc;
 
 /// Response schema for the `/health` check endpoint.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct HealthResponse {
-    /// Overall system status (Healthy, Degraded, or Unhealthy).
+    /// Overall system status (Healthy, Degraded, Initializing, or Unhealthy).
     pub status: HealthStatus,
     /// Detailed results for individual subsystem checks.
     pub checks: HashMap<String, HealthCheck>,
@@ -34,6 +36,8 @@ pub enum HealthStatus {
     Healthy,
     /// System is operational but some non-critical issues were detected.
     Degraded,
+    /// The server is up but is still resolving account projects.
+    Initializing,
     /// System is not functioning correctly.
     Unhealthy,
 }
@@ -50,43 +54,163 @@ pub struct HealthCheck {
 /// Performs a comprehensive system health check.
 ///
 /// This handler verifies:
-/// 1. **OAuth2 Credentials**: Checks token expiration and validity.
-/// 2. **Project Resolution**: Ensures the Google Cloud Project ID is correctly identified.
-/// 3. **Configuration**: Validates that critical environment variables and URLs are set.
-/// 4. **API Connectivity**: Performs a latency check to the upstream Gemini API.
+/// 1. **Accounts**: For each pooled account (`account:<name>`), token expiry
+///    (an expired token that can be refreshed only degrades the status),
+///    the resolved Google Cloud Project ID (or `initializing` while it is still
+///    being resolved), any unavailable models and the remaining quota per
+///    model. Named
+///    backends (`backend:<name>`) report their kind and unavailable models.
+/// 2. **Configuration**: Validates that critical environment variables and URLs are set.
+/// 3. **API Connectivity**: Reports the latest cached upstream probe of the
+///    primary account (see `health.probe_interval_seconds`), so polling this
+///    endpoint never spends quota or waits on the upstream.
 pub async fn health_handler(State(state): State<AppState>) -> Json<HealthResponse> {
     let mut checks = HashMap::new();
     let mut overall_status = HealthStatus::Healthy;
 
-    // Check OAuth credentials status
-    let (expires_in, is_expired) = state.oauth_manager.token_info().await;
-    let oauth_check = if is_expired {
-        overall_status = HealthStatus::Unhealthy;
-        HealthCheck {
-            status: "error".to_string(),
-            message: "OAuth token is expired or invalid".to_string(),
+    // Check each pooled account's credentials, project and model availability
+    let accounts = state.accounts.accounts();
+    let mut expired_accounts = 0;
+    for account in accounts {
+        let Some(client) = account.backend().code_assist() else {
+            continue;
+        };
+        let (expires_in, is_expired) = client.oauth_manager().token_info().await;
+        let unavailable: Vec<String> = client
+            .availability()
+            .unhealthy_models()
+            .into_iter()
+            .map(|(model, status)| format!("{} ({})", model, status.as_str()))
+            .collect();
+
+        // An expired token is recoverable while it can still be refreshed,
+        // as after booting offline
+        let refreshable = is_expired && client.oauth_manager().can_refresh().await;
+        let token = if refreshable {
+            "OAuth token is expired; it is refreshed on the next request".to_string()
+        } else if is_expired {
+            expired_accounts += 1;
+            "OAuth token is expired or invalid".to_string()
+        } else if expires_in < 600 {
+            format!("OAuth token expires soon: {} seconds remaining", expires_in)
+        } else {
+            format!("OAuth token is valid (expires in {}s)", expires_in)
+        };
+        // A refreshable expired token, less than 10 minutes remaining or an
+        // unavailable model is considered Degraded
+        let status = if is_expired && !refreshable {
+            "error"
+        } else if is_expired || expires_in < 600 || !unavailable.is_empty() {
+            overall_status = HealthStatus::Degraded;
+            "warning"
+        } else if client.project_id().is_none() {
+            // A failed attempt (e.g. offline) is retried in the background
+            if client.project_error().is_some() {
+                overall_status = HealthStatus::Degraded;
+                "warning"
+            } else {
+                if overall_status == HealthStatus::Healthy {
+                    overall_status = HealthStatus::Initializing;
+                }
+                "initializing"
+            }
+        } else {
+            "ok"
+        };
+
+        let mut message = match client.project_id() {
+            Some(project_id) => format!("{}; project ID: {}", token, project_id),
+            None => match client.project_error() {
+                Some(error) => format!("{}; project resolution failed, retrying: {}", token, error),
+                None => format!("{}; resolving project ID", token),
+            },
+        };
+        if let Some(tier) = client.tier() {
+            message.push_str(&format!(" ({})", tier));
         }
-    } else if expires_in < 600 {
-        // Less than 10 minutes remaining is considered Degraded
+        if !unavailable.is_empty() {
+            message.push_str(&format!("; unavailable models: {}", unavailable.join(", ")));
+        }
+        let quota: Vec<String> = client
+            .quota()
+            .snapshot()
+            .into_iter()
+            .map(|(model, quota)| format!("{} {}", model, quota.describe()))
+            .collect();
+        if !quota.is_empty() {
+            message.push_str(&format!("; quota: {}", quota.join(", ")));
+        }
+        checks.insert(
+            format!("account:{}", account.name()),
+            HealthCheck {
+                status: status.to_string(),
+                message,
+            },
+        );
+    }
+    if expired_accounts == accounts.len() {
+        overall_status = HealthStatus::Unhealthy;
+    } else if expired_accounts > 0 {
         overall_status = HealthStatus::Degraded;
+    }
+
+    // Named backends report their kind and any unavailable models
+    for backend in state.backends.backends() {
+        let unavailable: Vec<String> = backend
+            .backend()
+            .availability()
+            .unhealthy_models()
+            .into_iter()
+            .map(|(model, status)| format!("{} ({})", model, status.as_str()))
+            .collect();
+        let (status, message) = if unavailable.is_empty() {
+            (
+                "ok",
+                format!("{} backend", backend.backend().kind().as_str()),
+            )
+        } else {
+            if overall_status == HealthStatus::Healthy {
+                overall_status = HealthStatus::Degraded;
+            }
+            (
+                "warning",
+                format!(
+                    "{} backend; unavailable models: {}",
+                    backend.backend().kind().as_str(),
+                    unavailable.join(", ")
+                ),
+            )
+        };
+        checks.insert(
+            format!("backend:{}", backend.name()),
+            HealthCheck {
+                status: status.to_string(),
+                message,
+            },
+        );
+    }
+
+    // Open circuits mean an endpoint is failing fast for a model
+    let tripped = state.circuit_breaker.tripped();
+    let circuit_check = if tripped.is_empty() {
         HealthCheck {
-            status: "warning".to_string(),
-            message: format!("OAuth token expires soon: {} seconds remaining", expires_in),
+            status: "ok".to_string(),
+            message: "All circuits closed".to_string(),
         }
     } else {
+        if overall_status == HealthStatus::Healthy {
+            overall_status = HealthStatus::Degraded;
+        }
+        let circuits: Vec<String> = tripped
+            .iter()
+            .map(|c| format!("{} at {} ({})", c.model, c.endpoint, c.state.as_str()))
+            .collect();
         HealthCheck {
-            status: "ok".to_string(),
-            message: format!("OAuth token is valid (expires in {}s)", expires_in),
+            status: "warning".to_string(),
+            message: format!("Tripped circuits: {}", circuits.join(", ")),
         }
     };
-    checks.insert("oauth_credentials".to_string(), oauth_check);
-
-    // Verify Cloud Project ID resolution
-    let project_check = HealthCheck {
-        status: "ok".to_string(),
-        message: format!("Resolved Project ID: {}", state.gemini_client.project_id()),
-    };
-    checks.insert("project_resolution".to_string(), project_check);
+    checks.insert("circuit_breaker".to_string(), circuit_check);
 
     // Check basic server configuration
     let config_check = HealthCheck {
@@ -95,29 +219,60 @@ pub async fn health_handler(State(state): State<AppState>) -> Json<HealthRespons
     };
     checks.insert("configuration".to_string(), config_check);
 
-    // Perform live connectivity check to Gemini API
-    let connectivity_check = match state.gemini_client.check_connectivity().await {
-        Ok(latency) => {
-            let millis = latency.as_millis();
-            let status = if millis > 1000 {
-                if overall_status == HealthStatus::Healthy {
-                    overall_status = HealthStatus::Degraded;
-                }
-                "warning".to_string()
-            } else {
-                "ok".to_string()
-            };
-
+    // Report the last upstream probe; /health itself never calls upstream
+    let connectivity_check = match state.probe.latest() {
+        None if !state.probe.enabled() => HealthCheck {
+            status: "ok".to_string(),
+            message: "Upstream probe disabled".to_string(),
+        },
+        None => {
+            if overall_status == HealthStatus::Healthy {
+                overall_status = HealthStatus::Initializing;
+            }
             HealthCheck {
-                status,
-                message: format!("API connectivity latency: {}ms", millis),
+                status: "initializing".to_string(),
+                message: "Waiting for the first upstream probe".to_string(),
             }
         }
-        Err(e) => {
-            overall_status = HealthStatus::Unhealthy;
-            HealthCheck {
-                status: "error".to_string(),
-                message: format!("Upstream API unreachable: {}", e),
+        Some(result) => {
+            let age = (chrono::Utc::now() - result.checked_at).num_seconds();
+            match result.outcome {
+                ProbeOutcome::Reachable(latency) => {
+                    let millis = latency.as_millis();
+                    let status = if millis > 1000 {
+                        if overall_status == HealthStatus::Healthy {
+                            overall_status = HealthStatus::Degraded;
+                        }
+                        "warning".to_string()
+                    } else {
+                        "ok".to_string()
+                    };
+
+                    HealthCheck {
+                        status,
+                        message: format!(
+                            "API connectivity latency: {}ms (checked {}s ago)",
+                            millis, age
+                        ),
+                    }
+                }
+                // Unreachable (e.g. offline) is expected to recover on its own
+                ProbeOutcome::Unreachable(e) => {
+                    if overall_status != HealthStatus::Unhealthy {
+                        overall_status = HealthStatus::Degraded;
+                    }
+                    HealthCheck {
+                        status: "warning".to_string(),
+                        message: format!("Upstream API unreachable (checked {}s ago): {}", age, e),
+                    }
+                }
+                ProbeOutcome::Failed(e) => {
+                    overall_status = HealthStatus::Unhealthy;
+                    HealthCheck {
+                        status: "error".to_string(),
+                        message: format!("Upstream API check failed (checked {}s ago): {}", age, e),
+                    }
+                }
             }
         }
     };
@@ -130,6 +285,46 @@ pub async fn health_handler(State(state): State<AppState>) -> Json<HealthRespons
     })
 }
 
+/// Liveness check: answers as long as the process is serving requests.
+///
+/// Never touches the upstream, so orchestrators can poll it freely.
+pub async fn livez_handler() -> Json<serde_json::Value> {
+    Json(serde_json::json!({"status": "ok"}))
+}
+
+/// Readiness check: `200` once the primary account can serve requests, else
+/// `503`.
+///
+/// Ready means the primary account's project is resolved (Code Assist) and
+/// the last upstream probe reached the API (unless probing is disabled). Only
+/// cached state is consulted.
+pub async fn readyz_handler(State(state): State<AppState>) -> Response {
+    let primary = state.accounts.primary().backend();
+    let reason = if primary
+        .code_assist()
+        .is_some_and(|client| client.project_id().is_none())
+    {
+        Some("Resolving the Google Cloud project ID".to_string())
+    } else {
+        match state.probe.latest().map(|result| result.outcome) {
+            None if state.probe.enabled() => {
+                Some("Waiting for the first upstream probe".to_string())
+            }
+            None | Some(ProbeOutcome::Reachable(_)) => None,
+            Some(ProbeOutcome::Unreachable(e) | ProbeOutcome::Failed(e)) => Some(e),
+        }
+    };
+
+    match reason {
+        None => Json(serde_json::json!({"status": "ready"})).into_response(),
+        Some(reason) => (
+            axum::http::StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({"status": "not_ready", "reason": reason})),
+        )
+            .into_response(),
+    }
+}
+
 /// Exposes Prometheus-compatible application metrics.
 ///
 /// Gathers metrics from the global registry, including request counts,
@@ -150,12 +345,12 @@ pub async fn metrics_handler() -> impl IntoResponse {
 /// This handler:
 /// 1. Validates and parses the Anthropic `MessagesRequest`.
 /// 2. Logs the request details for transparency.
-/// 3. Detects if a streaming response is requested.
+/// 3. Routes the request to a Gemini model and applies the system prompt policy.
 /// 4. Dispatches to either `stream_messages_handler` or `non_stream_messages_handler`.
 pub async fn messages_handler(
     State(state): State<AppState>,
     headers: axum::http::HeaderMap,
-    Json(req): Json<crate::models::anthropic::MessagesRequest>,
+    Json(mut req): Json<crate::models::anthropic::MessagesRequest>,
 ) -> Result<Response, crate::error::ProxyError> {
     use tracing::debug;
 
@@ -186,71 +381,318 @@ pub async fn messages_handler(
     };
     debug!("REQUEST BODY PREVIEW:\n{}", body_preview);
 
+    // Route the request and apply any per-route overrides
+    let client = crate::translation::client_name(&headers);
+    let route = state.model_router.route_request(&req, &client)?;
+    route.apply_overrides(&mut req);
+    let gemini_model = route.gemini_model.clone();
+
+    // Apply the configured system prompt injections before dispatch
+    if !state.prompt_policy.is_empty() {
+        let model = req.model.clone();
+        state.prompt_policy.apply(
+            &mut req,
+            &crate::translation::PromptVars {
+                model: &model,
+                gemini_model: &gemini_model,
+                client: &client,
+            },
+        );
+    }
+
     if req.stream.unwrap_or(false) {
-        stream_messages_handler(state, req).await
+        stream_messages_handler(state, req, route).await
     } else {
-        non_stream_messages_handler(state, req).await
+        non_stream_messages_handler(state, req, route).await
     }
 }
 
-/// Internal handler for non-streaming (unary) message requests.
-///
-/// This function performs the core request-response translation cycle:
-/// 1. Maps the Anthropic model name to its Gemini counterpart.
-/// 2. Attempts to retrieve or create a Gemini context cache for large prompts.
-/// 3. Translates the Anthropic request structure into a Gemini-compatible format.
-/// 4. Executes the upstream call to the Gemini API.
-/// 5. Translates the returned Gemini response back into the Anthropic format.
-/// 6. Records all relevant telemetry (latency, status, token usage).
-async fn non_stream_messages_handler(
-    state: AppState,
-    req: crate::models::anthropic::MessagesRequest,
-) -> Result<Response, crate::error::ProxyError> {
-    use crate::translation::{translate_request, translate_response};
-    use tracing::{debug, error};
+/// Response header naming the Gemini model that actually served the request.
+const SERVED_MODEL_HEADER: &str = "x-gem2claude-served-model";
 
-    let request_start = std::time::Instant::now();
+/// Outcome of [`dispatch_with_fallback`].
+struct Dispatched<T> {
+    /// The upstream result.
+    value: T,
+    /// Gemini model that served the request.
+    served_model: String,
+    /// Account (or named backend) that served the request.
+    account: Arc<crate::gemini::Account>,
+    /// Limiter slot, held until the response is complete.
+    _permit: crate::gemini::RequestPermit,
+    /// Circuit slot, whose outcome is recorded once the upstream has answered:
+    /// on receipt for unary responses, on the first chunk or error for streams.
+    circuit: crate::gemini::CircuitPermit,
+}
 
-    let gemini_model = crate::models::mapping::map_model(&req.model)?;
+impl<T> Dispatched<T> {
+    /// Response headers describing the served model and its remaining quota.
+    fn headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = vec![(SERVED_MODEL_HEADER, self.served_model.clone())];
+        if let Some(quota) = self.account.quota(&self.served_model) {
+            headers.extend(quota.rate_limit_headers());
+        }
+        headers
+    }
+}
 
+/// Translates a request for `model`, consulting the context cache only for the
+/// routed model (cache entries are model-specific) on Code Assist backends.
+async fn translate_for_model(
+    state: &AppState,
+    backend: &dyn crate::gemini::UpstreamBackend,
+    req: &crate::models::anthropic::MessagesRequest,
+    model: &str,
+    use_cache: bool,
+) -> Result<crate::models::gemini::GenerateContentRequest, crate::error::ProxyError> {
     // Context Cache Management: Optimize repeated large prompts.
-    let (cached_content, cached_translation) = if let Some(cache_mgr) = &state.cache_manager {
-        cache_mgr
-            .get_or_create_cache(&req, state.gemini_client.project_id(), &state.gemini_client)
-            .await?
-    } else {
-        (None, None)
+    let (cached_content, cached_translation) = match (&state.cache_manager, backend.code_assist()) {
+        (Some(cache_mgr), Some(client)) if use_cache => {
+            cache_mgr
+                .get_or_create_cache(req, &client.ensure_project().await?, client)
+                .await?
+        }
+        _ => (None, None),
     };
 
     let mut gemini_req = if let Some(cached_req) = cached_translation {
-        debug!("Request translation retrieved from internal LRU cache.");
+        tracing::debug!("Request translation retrieved from internal LRU cache.");
         cached_req
     } else {
-        translate_request(req.clone(), state.gemini_client.project_id(), None, None).await?
+        crate::translation::translate_request(
+            req.clone(),
+            model,
+            state.media_fetcher.as_deref(),
+            state.file_store.as_deref(),
+            &state.model_registry,
+        )
+        .await?
     };
 
     if let Some(cache_name) = cached_content {
         gemini_req.cached_content = Some(cache_name);
     }
 
-    debug!(
-        "Dispatching unary request to Gemini API (Model: {})",
-        gemini_model
-    );
+    Ok(gemini_req)
+}
 
-    let gemini_resp = match state
-        .gemini_client
-        .generate_content(gemini_req, &gemini_model)
-        .await
-    {
-        Ok(resp) => resp,
+/// Runs `attempt` against the routed model's fallback chain and the account pool
+/// (or the route's named backend).
+///
+/// Candidates are (account, model) pairs: each model of the chain in order,
+/// tried on the pooled accounts in preference order (the conversation's sticky
+/// account first). Pairs whose model is unavailable on that account (or whose
+/// single sticky retry or half-open probe is already taken), or whose quota is
+/// nearly used up, are skipped. A 429
+/// or 503 from the first pair triggers a single switch to the next one.
+///
+/// Each attempt fails fast while the circuit for the backend's endpoint and
+/// the model is open (see `CircuitBreaker`), and otherwise waits for a slot in
+/// the account's queue for the model (see `RequestLimiter`).
+///
+/// Models in `skip_models` are left out of the chain. When no pair is left to
+/// try, the upstream is not called: the error is a 429 naming the earliest
+/// time a model recovers if any was skipped for quota or availability, and a
+/// 503 otherwise.
+async fn dispatch_with_fallback<T, F, Fut>(
+    state: &AppState,
+    req: &crate::models::anthropic::MessagesRequest,
+    route: &crate::models::ResolvedRoute,
+    skip_models: &[String],
+    attempt: F,
+) -> Result<Dispatched<T>, crate::error::ProxyError>
+where
+    F: Fn(Arc<crate::gemini::Account>, String) -> Fut,
+    Fut: std::future::Future<Output = Result<T, crate::error::ProxyError>>,
+{
+    use crate::error::ProxyError;
+
+    let gemini_model = route.gemini_model.as_str();
+    let conversation = crate::gemini::pool::conversation_key(req);
+    let accounts = match route.backend.as_deref() {
+        Some(name) => vec![state
+            .backends
+            .get(name)
+            .ok_or_else(|| ProxyError::Config(format!("Unknown backend: {}", name)))?
+            .clone()],
+        None => state.accounts.candidates(conversation.as_deref()),
+    };
+    let chain: Vec<String> = state
+        .model_router
+        .fallback_chain(gemini_model)
+        .into_iter()
+        .filter(|model| !skip_models.contains(model))
+        .collect();
+    let pairs: Vec<(Arc<crate::gemini::Account>, String)> = chain
+        .iter()
+        .flat_map(|model| {
+            accounts
+                .iter()
+                .map(move |account| (account.clone(), model.clone()))
+        })
+        .collect();
+    // Acquire lazily so that only pairs actually attempted consume their retry
+    let mut pairs = pairs.into_iter();
+    let low_quota = state.config.quota.low_remaining_fraction;
+    let now = chrono::Utc::now();
+    // Why candidates were skipped, for the error when none is left
+    let mut limited = false;
+    let mut retry_at: Option<chrono::DateTime<chrono::Utc>> = None;
+    let mut next_candidate = move || {
+        for (account, model) in pairs.by_ref() {
+            if let Some(quota) = account
+                .quota(&model)
+                .filter(|quota| quota.is_low(low_quota, now))
+            {
+                tracing::info!(
+                    "Skipping model {} on account {}: quota nearly exhausted",
+                    model,
+                    account.name()
+                );
+                limited = true;
+                retry_at = retry_at.into_iter().chain(quota.reset_at).min();
+                continue;
+            }
+            let availability = account.backend().availability();
+            if availability.try_acquire(&model) {
+                return Ok((account, model));
+            }
+            tracing::info!(
+                "Skipping unavailable model {} on account {}",
+                model,
+                account.name()
+            );
+            if let crate::gemini::availability::AvailabilityStatus::StickyRetry { until, .. }
+            | crate::gemini::availability::AvailabilityStatus::Terminal { until, .. } =
+                availability.status(&model)
+            {
+                limited = true;
+                retry_at = retry_at.into_iter().chain(Some(until)).min();
+            }
+        }
+        let retry = retry_at
+            .map(|at| format!("; retry after {}", at.to_rfc3339()))
+            .unwrap_or_default();
+        Err(if limited {
+            ProxyError::TooManyRequests(format!(
+                "No model in the fallback chain of {} has quota left{}",
+                gemini_model, retry
+            ))
+        } else {
+            ProxyError::ServiceUnavailable(format!(
+                "No model in the fallback chain of {} is available",
+                gemini_model
+            ))
+        })
+    };
+
+    let run = |account: Arc<crate::gemini::Account>, model: String| {
+        let attempt = &attempt;
+        async move {
+            let circuit = state
+                .circuit_breaker
+                .try_acquire(account.backend().endpoint(), &model)?;
+            let permit = state.limiter.acquire(account.name(), &model).await?;
+            // Success is recorded by the caller, once the upstream has answered
+            let result = match attempt(account.clone(), model).await {
+                Ok(value) => Ok((value, permit, circuit)),
+                Err(e) => {
+                    circuit.record_error(&e);
+                    Err(e)
+                }
+            };
+            let outcome = match &result {
+                Ok(_) => "success",
+                Err(ProxyError::TooManyRequests(_)) => {
+                    account.mark_rate_limited();
+                    "rate_limited"
+                }
+                Err(_) => "error",
+            };
+            crate::metrics::record_account_request(account.name(), outcome);
+            result
+        }
+    };
+
+    // Models known to be unavailable are not called at all
+    let (first_account, first_model) = next_candidate()?;
+    let ((result, permit, circuit), account, served_model) =
+        match run(first_account.clone(), first_model.clone()).await {
+            Err(e @ (ProxyError::TooManyRequests(_) | ProxyError::ServiceUnavailable(_))) => {
+                match next_candidate() {
+                    Ok((account, model)) => {
+                        tracing::warn!(
+                        "Model {} on account {} failed ({}), switching to model {} on account {}",
+                        first_model,
+                        first_account.name(),
+                        e,
+                        model,
+                        account.name()
+                    );
+                        (run(account.clone(), model.clone()).await?, account, model)
+                    }
+                    Err(_) => return Err(e),
+                }
+            }
+            result => (result?, first_account, first_model),
+        };
+
+    state.accounts.assign(conversation.as_deref(), &account);
+    crate::metrics::record_served_model(gemini_model, &served_model);
+    Ok(Dispatched {
+        value: result,
+        served_model,
+        account,
+        _permit: permit,
+        circuit,
+    })
+}
+
+/// Internal handler for non-streaming (unary) message requests.
+///
+/// This function performs the core request-response translation cycle:
+/// 1. Receives the Gemini model chosen by the routing table.
+/// 2. Attempts to retrieve or create a Gemini context cache for large prompts.
+/// 3. Translates the Anthropic request structure into a Gemini-compatible format.
+/// 4. Executes the upstream call, switching to a fallback model on 429/503.
+/// 5. Translates the returned Gemini response back into the Anthropic format.
+/// 6. Records all relevant telemetry (latency, status, token usage).
+async fn non_stream_messages_handler(
+    state: AppState,
+    req: crate::models::anthropic::MessagesRequest,
+    route: crate::models::ResolvedRoute,
+) -> Result<Response, crate::error::ProxyError> {
+    use crate::translation::translate_response;
+    use tracing::{debug, error};
+
+    let request_start = std::time::Instant::now();
+
+    let dispatch = dispatch_with_fallback(&state, &req, &route, &[], |account, model| {
+        let state = state.clone();
+        let req = req.clone();
+        let routed = model == route.gemini_model;
+        async move {
+            let backend = account.backend().as_ref();
+            let gemini_req = translate_for_model(&state, backend, &req, &model, routed).await?;
+            debug!(
+                "Dispatching unary request to Gemini API (Model: {}, account: {})",
+                model,
+                account.name()
+            );
+            backend.generate_content(gemini_req, &model).await
+        }
+    });
+    let dispatched = match dispatch.await {
+        Ok(dispatched) => dispatched,
         Err(e) => {
             error!("Upstream Gemini API call failure: {}", e);
             return Err(e);
         }
     };
 
-    let anthropic_resp = match translate_response(gemini_resp, &req.model) {
+    let headers = dispatched.headers();
+    dispatched.circuit.record_success();
+    let anthropic_resp = match translate_response(dispatched.value, &req.model) {
         Ok(resp) => resp,
         Err(e) => {
             error!("Translation failure for Gemini response candidate: {}", e);
@@ -278,24 +720,31 @@ async fn non_stream_messages_handler(
         crate::metrics::record_cache_create();
     }
 
-    Ok(Json(anthropic_resp).into_response())
+    let mut response = Json(anthropic_resp).into_response();
+    insert_headers(&mut response, headers);
+    Ok(response)
 }
 
 /// Internal handler for Server-Sent Events (SSE) streaming requests.
 ///
 /// This asynchronous handler establishes a persistent connection to the client and
 /// pipes transformed events from Gemini in real-time:
-/// 1. Maps models and manages context caching (same as unary).
-/// 2. Opens a streaming connection to the Gemini API.
-/// 3. Transforms raw Gemini JSON chunks into Anthropic SSE events.
+/// 1. Manages context caching and model fallback (same as unary).
+/// 2. Opens a streaming connection to the Gemini API, reopening it if it stalls
+///    before the first chunk (see `StreamWatchdog`).
+/// 3. Transforms raw Gemini JSON chunks into Anthropic SSE events, ending with
+///    an `error` event if the upstream stalls later on.
 /// 4. Implements a watchdog loop to send keep-alive pings every 15 seconds.
-/// 5. Injects mock headers to maximize compatibility with the Claude SDK.
+/// 5. Injects mock headers to maximize compatibility with the Claude SDK, using
+///    the account's real quota for the rate-limit headers when it is known.
+///    The served-model and quota headers describe the first stream opened;
+///    a stream reissued after the headers were sent keeps them.
 async fn stream_messages_handler(
     state: AppState,
     req: crate::models::anthropic::MessagesRequest,
+    route: crate::models::ResolvedRoute,
 ) -> Result<Response, crate::error::ProxyError> {
     use crate::translation::streaming::StreamTranslator;
-    use crate::translation::translate_request;
     use futures::StreamExt;
     use tracing::{debug, warn};
 
@@ -304,36 +753,30 @@ async fn stream_messages_handler(
     debug!("Establishing SSE tunnel for model: {}", req.model);
     crate::metrics::record_sse_connection("opened");
 
-    let gemini_model = crate::models::mapping::map_model(&req.model)?;
-
-    let (cached_content, cached_translation) = if let Some(cache_mgr) = &state.cache_manager {
-        cache_mgr
-            .get_or_create_cache(&req, state.gemini_client.project_id(), &state.gemini_client)
-            .await?
-    } else {
-        (None, None)
+    let mut retry = StreamRetry::new(&state.config.stream);
+    let dispatched = match open_stream(&state, &req, &route, &[]).await {
+        Ok(dispatched) => dispatched,
+        Err(e) => retry.reopen(&state, &req, &route, None, e).await?,
     };
-
-    let mut gemini_req = if let Some(cached_req) = cached_translation {
-        cached_req
-    } else {
-        translate_request(req.clone(), state.gemini_client.project_id(), None, None).await?
-    };
-
-    if let Some(cache_name) = cached_content {
-        gemini_req.cached_content = Some(cache_name);
-    }
-
-    let gemini_stream = state
-        .gemini_client
-        .stream_generate_content(gemini_req, &gemini_model)
-        .await?;
+    // Headers go out before the body, so they describe this dispatch even if
+    // the stream is later reissued on another model or account
+    let headers = dispatched.headers();
+    let Dispatched {
+        value: mut gemini_stream,
+        _permit: permit,
+        mut served_model,
+        mut account,
+        circuit,
+    } = dispatched;
+    // Limiter slot of the current upstream stream
+    let mut permit = Some(permit);
+    // Circuit slot of the current upstream stream, until it answers or fails
+    let mut circuit = Some(circuit);
 
     let mut translator = StreamTranslator::new(req.model.clone());
 
     let sse_stream = async_stream::stream! {
         debug!("Upstream SSE stream acquired; beginning transformation cycle.");
-        futures::pin_mut!(gemini_stream);
 
         let mut chunk_count = 0;
         loop {
@@ -344,6 +787,9 @@ async fn stream_messages_handler(
                             chunk_count += 1;
                             match chunk_result {
                                 Ok(chunk) => {
+                                    if let Some(circuit) = circuit.take() {
+                                        circuit.record_success();
+                                    }
                                     match translator.translate_chunk(chunk) {
                                         Ok(events) => {
                                             for event in events.iter() {
@@ -365,27 +811,71 @@ async fn stream_messages_handler(
                                         }
                                     }
                                 }
+                                Err(e) if !translator.has_committed() => {
+                                    record_stream_error(&state, &mut circuit, &account, &served_model, &e);
+                                    // Nothing reached the client yet, so the
+                                    // request can be reissued invisibly. Free
+                                    // the failed stream's limiter slot and
+                                    // connection first, or the retry would
+                                    // queue behind its own request.
+                                    permit = None;
+                                    drop(std::mem::replace(&mut gemini_stream, Box::pin(futures::stream::empty())));
+                                    // Backoff and queueing can take minutes; keep
+                                    // the client's connection visibly alive
+                                    let reopen = retry.reopen(&state, &req, &route, Some(served_model.clone()), e);
+                                    futures::pin_mut!(reopen);
+                                    let reopened = loop {
+                                        tokio::select! {
+                                            result = &mut reopen => break result,
+                                            _ = tokio::time::sleep(std::time::Duration::from_secs(15)) => {
+                                                yield Ok(": keepalive\n\n".to_string());
+                                            }
+                                        }
+                                    };
+                                    match reopened {
+                                        Ok(dispatched) => {
+                                            gemini_stream = dispatched.value;
+                                            permit = Some(dispatched._permit);
+                                            circuit = Some(dispatched.circuit);
+                                            account = dispatched.account;
+                                            served_model = dispatched.served_model;
+                                            translator = StreamTranslator::new(req.model.clone());
+                                        }
+                                        Err(e) => {
+                                            warn!("Upstream failed before sending content: {}", e);
+                                            yield Ok(stream_error_event(&e).to_sse());
+                                            break;
+                                        }
+                                    }
+                                }
                                 Err(e) => {
+                                    record_stream_error(&state, &mut circuit, &account, &served_model, &e);
                                     warn!("Upstream connection reset or error: {}", e);
-                                    let error_event = crate::models::streaming::StreamEvent::Error {
-                                        error: crate::models::streaming::ErrorData {
-                                            error_type: "api_error".to_string(),
-                                            message: e.to_string(),
-                                        },
-                                    };
-                                    yield Ok(error_event.to_sse());
+                                    yield Ok(stream_error_event(&e).to_sse());
                                     break;
                                 }
                             }
                         }
-                        None => break,
+                        None => {
+                            for event in translator.flush() {
+                                yield Ok(event.to_sse());
+                            }
+                            break;
+                        }
                     }
                 }
                 _ = tokio::time::sleep(std::time::Duration::from_secs(15)) => {
-                    yield Ok("event: ping\ndata: {\"type\": \"ping\"}\n\n".to_string());
+                    if translator.has_committed() {
+                        yield Ok("event: ping\ndata: {\"type\": \"ping\"}\n\n".to_string());
+                    } else {
+                        // A ping before message_start would confuse clients
+                        yield Ok(": keepalive\n\n".to_string());
+                    }
                 }
             }
         }
+        // Hold the limiter slot until the stream ends
+        drop(permit);
 
         let duration = request_start.elapsed().as_secs_f64();
         debug!("SSE Stream finalized ({} chunks processed).", chunk_count);
@@ -409,7 +899,7 @@ async fn stream_messages_handler(
     use axum::body::Body;
     let body = Body::from_stream(sse_stream);
 
-    Ok(Response::builder()
+    let mut response = Response::builder()
         .status(200)
         .header("Content-Type", "text/event-stream; charset=utf-8")
         .header("Cache-Control", "no-cache")
@@ -421,7 +911,176 @@ async fn stream_messages_handler(
         .header("anthropic-ratelimit-requests-remaining", "49")
         .header("request-id", format!("req_{}", uuid::Uuid::new_v4()))
         .body(body)
-        .unwrap())
+        .unwrap();
+    // Real quota, when known, replaces the placeholder rate-limit headers
+    insert_headers(&mut response, headers);
+    Ok(response)
+}
+
+/// Opens an upstream stream for `req`, with stalls handled by the watchdog.
+///
+/// Returns once the upstream has accepted the request, without waiting for
+/// the first chunk.
+async fn open_stream(
+    state: &AppState,
+    req: &crate::models::anthropic::MessagesRequest,
+    route: &crate::models::ResolvedRoute,
+    skip_models: &[String],
+) -> Result<Dispatched<crate::gemini::streaming::GeminiStream>, crate::error::ProxyError> {
+    dispatch_with_fallback(state, req, route, skip_models, |account, model| {
+        let state = state.clone();
+        let req = req.clone();
+        let routed = model == route.gemini_model;
+        async move {
+            let backend = account.backend().as_ref();
+            let gemini_req = translate_for_model(&state, backend, &req, &model, routed).await?;
+            let started = tokio::time::Instant::now();
+            let stream = backend.stream_generate_content(gemini_req, &model).await?;
+            // Stalls, including before the first chunk, surface in the body
+            Ok(crate::gemini::StreamWatchdog::new(&state.config.stream)
+                .watch(stream, &model, started))
+        }
+    })
+    .await
+}
+
+/// Error event ending a stream, typed like the equivalent error response so
+/// that clients apply their usual retry handling (e.g. `rate_limit_error`).
+fn stream_error_event(error: &crate::error::ProxyError) -> crate::models::streaming::StreamEvent {
+    crate::models::streaming::StreamEvent::Error {
+        error: crate::models::streaming::ErrorData {
+            error_type: error.status_and_type().1.to_string(),
+            message: error.to_string(),
+        },
+    }
+}
+
+/// Records a stream error against the circuit of the model serving it. Stalls
+/// and read errors surface in the body, after the headers were sent, so they
+/// are only known here.
+fn record_stream_error(
+    state: &AppState,
+    circuit: &mut Option<crate::gemini::CircuitPermit>,
+    account: &crate::gemini::Account,
+    model: &str,
+    error: &crate::error::ProxyError,
+) {
+    match circuit.take() {
+        Some(circuit) => circuit.record_error(error),
+        // The stream already answered; a later stall still counts
+        None => state
+            .circuit_breaker
+            .record_late_error(account.backend().endpoint(), model, error),
+    }
+}
+
+/// Reissues streams that fail before any content reached the client, up to
+/// `stream.retries_before_content` times per request.
+///
+/// The client only sees the response headers (sent once, for the first
+/// stream) and keep-alive comments until the translator commits, so a
+/// retried stream is indistinguishable from a slow one.
+struct StreamRetry {
+    remaining: u32,
+    on_fallback: bool,
+    backoff: backoff::ExponentialBackoff,
+    /// Models that failed, left out of later attempts with `retry_on_fallback`.
+    skip_models: Vec<String>,
+}
+
+impl StreamRetry {
+    fn new(config: &crate::config::StreamConfig) -> Self {
+        Self {
+            remaining: config.retries_before_content,
+            on_fallback: config.retry_on_fallback,
+            backoff: crate::utils::retry::create_backoff(),
+            skip_models: Vec::new(),
+        }
+    }
+
+    /// Opens the stream again after `error`, for as long as the failures are
+    /// retryable and retries remain. `failed_model` is the model that failed,
+    /// when known; otherwise the first model still in the chain is assumed.
+    async fn reopen(
+        &mut self,
+        state: &AppState,
+        req: &crate::models::anthropic::MessagesRequest,
+        route: &crate::models::ResolvedRoute,
+        mut failed_model: Option<String>,
+        mut error: crate::error::ProxyError,
+    ) -> Result<Dispatched<crate::gemini::streaming::GeminiStream>, crate::error::ProxyError> {
+        use crate::error::ProxyError;
+        use backoff::backoff::Backoff;
+
+        loop {
+            let retryable = matches!(
+                error,
+                ProxyError::TooManyRequests(_)
+                    | ProxyError::ServiceUnavailable(_)
+                    | ProxyError::Http(_)
+            );
+            if !retryable || self.remaining == 0 {
+                return Err(error);
+            }
+            self.remaining -= 1;
+
+            let chain: Vec<String> = state
+                .model_router
+                .fallback_chain(&route.gemini_model)
+                .into_iter()
+                .filter(|model| !self.skip_models.contains(model))
+                .collect();
+            let model = failed_model
+                .take()
+                .or_else(|| chain.first().cloned())
+                .unwrap_or_else(|| route.gemini_model.clone());
+            // Skipping the last model left would leave nothing to retry on
+            if self.on_fallback && chain.iter().any(|other| other != &model) {
+                self.skip_models.push(model.clone());
+            }
+
+            // The upstream's RetryInfo delay, when a 429 carries one, beats backoff
+            let backoff = self.backoff.next_backoff();
+            let delay = upstream_retry_delay(&error)
+                .or(backoff)
+                .unwrap_or(std::time::Duration::from_secs(30));
+            tracing::warn!(
+                "Model {} failed before sending content ({}); reissuing the stream in {:?} ({} retries left)",
+                model,
+                error,
+                delay,
+                self.remaining
+            );
+            crate::metrics::record_retry_attempt(&model, "before_content");
+            tokio::time::sleep(delay).await;
+
+            match open_stream(state, req, route, &self.skip_models).await {
+                Ok(dispatched) => return Ok(dispatched),
+                Err(e) => error = e,
+            }
+        }
+    }
+}
+
+/// Returns the `RetryInfo` delay of a 429 whose message carries the upstream
+/// error body.
+fn upstream_retry_delay(error: &crate::error::ProxyError) -> Option<std::time::Duration> {
+    match error {
+        crate::error::ProxyError::TooManyRequests(message) => {
+            let body = &message[message.find('{')?..];
+            crate::utils::retry::parse_retry_delay(body)
+        }
+        _ => None,
+    }
+}
+
+/// Sets response headers, replacing any existing values.
+fn insert_headers(response: &mut Response, headers: Vec<(&'static str, String)>) {
+    for (name, value) in headers {
+        if let Ok(value) = value.parse() {
+            response.headers_mut().insert(name, value);
+        }
+    }
 }
 
 /// Sink handler for Claude Code telemetry and event logging.
@@ -443,3 +1102,557 @@ pub async fn event_logging_handler(body: String) -> impl IntoResponse {
 
     axum::http::StatusCode::OK
 }
+
+/// Query parameters for `GET /v1/files`.
+#[derive(Debug, Deserialize)]
+pub struct ListFilesQuery {
+    /// Page size (1-1000). Default: 20
+    pub limit: Option<usize>,
+    /// Return the page of files immediately before (newer than) this ID.
+    pub before_id: Option<String>,
+    /// Return the page of files immediately after (older than) this ID.
+    pub after_id: Option<String>,
+}
+
+/// Returns the file store, or a 404 when the Files API is disabled.
+fn file_store(state: &AppState) -> Result<&crate::files::FileStore, crate::error::ProxyError> {
+    state.file_store.as_deref().ok_or_else(|| {
+        crate::error::ProxyError::NotFound(
+            "Files API is disabled on this proxy (files.enabled)".to_string(),
+        )
+    })
+}
+
+/// Uploads a file (`POST /v1/files`, multipart form with a `file` field).
+pub async fn upload_file_handler(
+    State(state): State<AppState>,
+    mut multipart: axum::extract::Multipart,
+) -> Result<Json<crate::files::FileObject>, crate::error::ProxyError> {
+    use crate::error::ProxyError;
+
+    let store = file_store(&state)?;
+    let invalid = |e: axum::extract::multipart::MultipartError| {
+        ProxyError::InvalidRequest(format!("Invalid multipart upload: {}", e))
+    };
+
+    while let Some(field) = multipart.next_field().await.map_err(invalid)? {
+        if field.name() != Some("file") {
+            continue;
+        }
+
+        let filename = field.file_name().unwrap_or("upload").to_string();
+        let content_type = field.content_type().map(str::to_string);
+        let bytes = field.bytes().await.map_err(invalid)?;
+
+        let record = store.upload(filename, content_type, &bytes).await?;
+        return Ok(Json(record.to_object()));
+    }
+
+    Err(ProxyError::InvalidRequest(
+        "Multipart upload must include a 'file' field".to_string(),
+    ))
+}
+
+/// Lists uploaded files, newest first (`GET /v1/files`).
+pub async fn list_files_handler(
+    State(state): State<AppState>,
+    axum::extract::Query(query): axum::extract::Query<ListFilesQuery>,
+) -> Result<Json<crate::files::FileList>, crate::error::ProxyError> {
+    let store = file_store(&state)?;
+    let limit = query.limit.unwrap_or(20).clamp(1, 1000);
+
+    Ok(Json(store.list(
+        limit,
+        query.before_id.as_deref(),
+        query.after_id.as_deref(),
+    )))
+}
+
+/// Returns metadata for a single file (`GET /v1/files/{file_id}`).
+pub async fn get_file_handler(
+    State(state): State<AppState>,
+    axum::extract::Path(file_id): axum::extract::Path<String>,
+) -> Result<Json<crate::files::FileObject>, crate::error::ProxyError> {
+    file_store(&state)?
+        .get(&file_id)
+        .map(|record| Json(record.to_object()))
+        .ok_or_else(|| crate::error::ProxyError::NotFound(format!("File not found: {}", file_id)))
+}
+
+/// Deletes a file (`DELETE /v1/files/{file_id}`).
+pub async fn delete_file_handler(
+    State(state): State<AppState>,
+    axum::extract::Path(file_id): axum::extract::Path<String>,
+) -> Result<Json<crate::files::FileDeleted>, crate::error::ProxyError> {
+    Ok(Json(file_store(&state)?.delete(&file_id).await?))
+}
+
+/// Builds the model object for a Claude model name.
+fn model_info(
+    state: &AppState,
+    id: &str,
+    gemini_model: &str,
+) -> crate::models::anthropic::ModelInfo {
+    crate::models::anthropic::ModelInfo {
+        object_type: "model".to_string(),
+        id: id.to_string(),
+        display_name: format!("{} (via {})", id, gemini_model),
+        created_at: "1970-01-01T00:00:00Z".to_string(),
+        gemini_model: gemini_model.to_string(),
+        capabilities: state.model_registry.capabilities(gemini_model),
+    }
+}
+
+/// Lists supported models with their Gemini backend and capabilities (`GET /v1/models`).
+pub async fn list_models_handler(
+    State(state): State<AppState>,
+) -> Json<crate::models::anthropic::ModelList> {
+    let data: Vec<_> = state
+        .model_router
+        .known_models()
+        .into_iter()
+        .map(|(id, gemini_model)| model_info(&state, &id, &gemini_model))
+        .collect();
+
+    Json(crate::models::anthropic::ModelList {
+        first_id: data.first().map(|m| m.id.clone()),
+        last_id: data.last().map(|m| m.id.clone()),
+        has_more: false,
+        data,
+    })
+}
+
+/// Returns a single model (`GET /v1/models/{model_id}`).
+pub async fn get_model_handler(
+    State(state): State<AppState>,
+    axum::extract::Path(model_id): axum::extract::Path<String>,
+) -> Result<Json<crate::models::anthropic::ModelInfo>, crate::error::ProxyError> {
+    let route = state.model_router.resolve(&model_id).map_err(|_| {
+        crate::error::ProxyError::NotFound(format!("Model not found: {}", model_id))
+    })?;
+
+    Ok(Json(model_info(&state, &model_id, &route.gemini_model)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AppConfig, BackendKind, FallbackChain};
+    use crate::error::ProxyError;
+    use crate::gemini::streaming::GeminiStream;
+    use crate::gemini::{Account, AccountPool, ModelAvailabilityService, UpstreamBackend};
+    use crate::models::gemini::{GenerateContentRequest, GenerateContentResponse};
+    use futures::StreamExt;
+    use parking_lot::Mutex;
+    use std::collections::VecDeque;
+    use std::time::Duration;
+
+    /// Backend that serves scripted streams in order and records the models
+    /// they were requested for.
+    #[derive(Default)]
+    struct ScriptedBackend {
+        availability: ModelAvailabilityService,
+        streams: Mutex<VecDeque<crate::error::Result<GeminiStream>>>,
+        requested: Mutex<Vec<String>>,
+    }
+
+    impl ScriptedBackend {
+        fn with_streams(streams: Vec<crate::error::Result<GeminiStream>>) -> Arc<Self> {
+            Arc::new(Self {
+                streams: Mutex::new(streams.into()),
+                ..Self::default()
+            })
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl UpstreamBackend for ScriptedBackend {
+        fn kind(&self) -> BackendKind {
+            BackendKind::ApiKey
+        }
+
+        fn endpoint(&self) -> &str {
+            "scripted"
+        }
+
+        fn availability(&self) -> &ModelAvailabilityService {
+            &self.availability
+        }
+
+        async fn generate_content(
+            &self,
+            _request: GenerateContentRequest,
+            _model: &str,
+        ) -> crate::error::Result<GenerateContentResponse> {
+            Err(ProxyError::Internal("not scripted".to_string()))
+        }
+
+        async fn stream_generate_content(
+            &self,
+            _request: GenerateContentRequest,
+            model: &str,
+        ) -> crate::error::Result<GeminiStream> {
+            self.requested.lock().push(model.to_string());
+            self.streams
+                .lock()
+                .pop_front()
+                .unwrap_or_else(|| Err(ProxyError::Internal("not scripted".to_string())))
+        }
+
+        async fn count_tokens(
+            &self,
+            _request: &GenerateContentRequest,
+            _model: &str,
+        ) -> crate::error::Result<u32> {
+            Ok(0)
+        }
+
+        async fn check_connectivity(&self, _model: &str) -> crate::error::Result<Duration> {
+            Ok(Duration::ZERO)
+        }
+    }
+
+    /// State serving every request from `backend`, with a single limiter
+    /// slot per model and a pro → flash fallback chain.
+    fn state(backend: Arc<ScriptedBackend>, retry_on_fallback: bool) -> AppState {
+        let mut config = AppConfig::default();
+        config.limits.max_concurrent_requests = 1;
+        config.limits.queue_timeout_seconds = 1;
+        config.routing.fallbacks = vec![FallbackChain {
+            models: vec!["gemini-2.5-pro".to_string(), "gemini-2.5-flash".to_string()],
+        }];
+        config.stream.retry_on_fallback = retry_on_fallback;
+
+        let accounts = AccountPool::new(
+            vec![Account::with_backend("default", backend)],
+            &config.pool,
+        );
+        AppState {
+            accounts: Arc::new(accounts),
+            backends: Arc::new(crate::gemini::BackendRegistry::new(&config).unwrap()),
+            cache_manager: None,
+            media_fetcher: None,
+            file_store: None,
+            prompt_policy: Arc::new(crate::translation::PromptPolicy::new(&config.prompt).unwrap()),
+            model_registry: Arc::new(crate::models::ModelRegistry::new(&config.models).unwrap()),
+            model_router: Arc::new(
+                crate::models::ModelRouter::new(&config.routing, &config.gemini.default_model)
+                    .unwrap(),
+            ),
+            limiter: Arc::new(crate::gemini::RequestLimiter::new(&config.limits)),
+            circuit_breaker: Arc::new(crate::gemini::CircuitBreaker::new(&config.circuit_breaker)),
+            probe: Arc::new(super::super::probe::UpstreamProbe::new(&config.health)),
+            config,
+        }
+    }
+
+    fn request() -> crate::models::anthropic::MessagesRequest {
+        serde_json::from_value(serde_json::json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 64,
+            "stream": true,
+            "messages": [{"role": "user", "content": "hi"}],
+        }))
+        .unwrap()
+    }
+
+    fn route() -> crate::models::ResolvedRoute {
+        crate::models::ResolvedRoute {
+            gemini_model: "gemini-2.5-pro".to_string(),
+            source: crate::models::RouteSource::Config,
+            route: None,
+            rule: None,
+            backend: None,
+        }
+    }
+
+    fn stream(items: Vec<crate::error::Result<GenerateContentResponse>>) -> GeminiStream {
+        futures::stream::iter(items).boxed()
+    }
+
+    fn answer(text: &str) -> crate::error::Result<GenerateContentResponse> {
+        Ok(serde_json::from_value(serde_json::json!({
+            "response": {
+                "candidates": [{
+                    "content": {"role": "model", "parts": [{"text": text}]},
+                    "finishReason": "STOP",
+                }],
+                "usageMetadata": {"promptTokenCount": 1, "candidatesTokenCount": 1},
+            },
+        }))
+        .unwrap())
+    }
+
+    fn unavailable() -> ProxyError {
+        ProxyError::ServiceUnavailable("Upstream unavailable".to_string())
+    }
+
+    /// State whose only account is a Code Assist client holding an expired
+    /// access token.
+    async fn expired_token_state(
+        auto_refresh: bool,
+    ) -> (AppState, tempfile::NamedTempFile, tempfile::TempDir) {
+        // NamedTempFile is created 0600, as OAuthManager requires
+        let credentials = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(
+            credentials.path(),
+            serde_json::json!({
+                "access_token": "ya29.expired",
+                "refresh_token": "1//refresh",
+                "token_type": "Bearer",
+                "expiry_date": chrono::Utc::now().timestamp_millis() - 3_600_000,
+            })
+            .to_string(),
+        )
+        .unwrap();
+        let oauth_manager = crate::oauth::OAuthManager::new(&crate::config::OAuthConfig {
+            credentials_path: credentials.path().to_string_lossy().to_string(),
+            auto_refresh,
+            ..crate::config::OAuthConfig::default()
+        })
+        .await
+        .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let gemini = crate::config::GeminiConfig {
+            state_file: String::new(),
+            project_cache_file: dir
+                .path()
+                .join("projects.json")
+                .to_string_lossy()
+                .to_string(),
+            ..crate::config::GeminiConfig::default()
+        };
+        let client = crate::gemini::GeminiClient::new(&gemini, oauth_manager)
+            .await
+            .unwrap();
+
+        let mut state = state(Arc::new(ScriptedBackend::default()), false);
+        state.accounts = Arc::new(AccountPool::new(
+            vec![Account::with_backend("default", Arc::new(client))],
+            &state.config.pool,
+        ));
+        (state, credentials, dir)
+    }
+
+    #[tokio::test]
+    async fn test_health_treats_refreshable_expired_token_as_degraded() {
+        let (state, _credentials, _dir) = expired_token_state(true).await;
+        let Json(health) = health_handler(State(state)).await;
+        assert_eq!(health.status, HealthStatus::Degraded);
+        assert_eq!(health.checks["account:default"].status, "warning");
+
+        // Without auto-refresh the token cannot recover by itself
+        let (state, _credentials, _dir) = expired_token_state(false).await;
+        let Json(health) = health_handler(State(state)).await;
+        assert_eq!(health.status, HealthStatus::Unhealthy);
+        assert_eq!(health.checks["account:default"].status, "error");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stream_failing_before_content_is_retried_invisibly() {
+        let backend = ScriptedBackend::with_streams(vec![
+            Ok(stream(vec![Err(unavailable())])),
+            Ok(stream(vec![answer("Hello")])),
+        ]);
+        let state = state(backend.clone(), false);
+
+        let response = stream_messages_handler(state, request(), route())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        // The retry reused the single limiter slot instead of queueing
+        // behind the failed stream, and the client saw one clean message
+        assert_eq!(body.matches("event: message_start").count(), 1);
+        assert!(body.contains("Hello"));
+        assert!(!body.contains("event: error"), "{}", body);
+        assert_eq!(
+            *backend.requested.lock(),
+            vec!["gemini-2.5-pro", "gemini-2.5-pro"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stalled_streams_open_circuit() {
+        let backend = ScriptedBackend::with_streams(vec![
+            Ok(futures::stream::pending().boxed()),
+            Ok(futures::stream::pending().boxed()),
+        ]);
+        let mut state = state(backend.clone(), false);
+        state.config.stream.retries_before_content = 1;
+        state.circuit_breaker = Arc::new(crate::gemini::CircuitBreaker::new(
+            &crate::config::CircuitBreakerConfig {
+                enabled: true,
+                failure_threshold: 2,
+                open_seconds: 30,
+            },
+        ));
+
+        let response = stream_messages_handler(state.clone(), request(), route())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        // Both streams were accepted, then stalled in the body
+        assert!(body.contains("event: error"), "{}", body);
+        assert_eq!(backend.requested.lock().len(), 2);
+        let tripped = state.circuit_breaker.tripped();
+        assert_eq!(tripped.len(), 1);
+        assert_eq!(tripped[0].model, "gemini-2.5-pro");
+        assert_eq!(
+            tripped[0].state,
+            crate::gemini::circuit_breaker::CircuitState::Open
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reopen_waits_for_retry_info_with_keepalives() {
+        let rate_limited = |retry_delay: &str| {
+            crate::gemini::backend::upstream_error(
+                429,
+                serde_json::json!({"error": {"code": 429, "details": [{
+                    "@type": "type.googleapis.com/google.rpc.RetryInfo",
+                    "retryDelay": retry_delay,
+                }]}})
+                .to_string(),
+            )
+        };
+        let body_for = |retry_delay: &'static str| async move {
+            let backend = ScriptedBackend::with_streams(vec![
+                Ok(stream(vec![Err(rate_limited(retry_delay))])),
+                Ok(stream(vec![answer("Hello")])),
+            ]);
+            let started = tokio::time::Instant::now();
+            let response = stream_messages_handler(state(backend, false), request(), route())
+                .await
+                .unwrap();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            (String::from_utf8(body.to_vec()).unwrap(), started.elapsed())
+        };
+
+        let (quick, _) = body_for("0s").await;
+        let (slow, waited) = body_for("40s").await;
+
+        // The retry waited out RetryInfo, with keep-alives at 15s and 30s
+        assert!(waited >= std::time::Duration::from_secs(40), "{:?}", waited);
+        assert!(slow.contains("Hello"));
+        assert_eq!(
+            slow.matches(": keepalive").count(),
+            quick.matches(": keepalive").count() + 2
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failed_reopen_keeps_error_type() {
+        let backend = ScriptedBackend::with_streams(vec![Ok(stream(vec![Err(
+            ProxyError::TooManyRequests("quota".to_string()),
+        )]))]);
+        let mut state = state(backend, false);
+        state.config.stream.retries_before_content = 0;
+
+        let response = stream_messages_handler(state, request(), route())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        // Clients retry rate limits on their own only when typed as such
+        assert!(body.contains("\"rate_limit_error\""), "{}", body);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reopen() {
+        let backend = ScriptedBackend::with_streams(vec![
+            Ok(stream(vec![answer("Hello")])),
+            Err(unavailable()),
+        ]);
+        let state = state(backend.clone(), true);
+        let mut retry = StreamRetry::new(&state.config.stream);
+
+        // Errors the upstream would repeat are not retried
+        let result = retry
+            .reopen(
+                &state,
+                &request(),
+                &route(),
+                None,
+                ProxyError::InvalidRequest("bad".to_string()),
+            )
+            .await;
+        assert!(matches!(result, Err(ProxyError::InvalidRequest(_))));
+        assert!(backend.requested.lock().is_empty());
+
+        // With retry_on_fallback the failed model is skipped
+        let dispatched = retry
+            .reopen(
+                &state,
+                &request(),
+                &route(),
+                Some("gemini-2.5-pro".to_string()),
+                ProxyError::TooManyRequests("quota".to_string()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(dispatched.served_model, "gemini-2.5-flash");
+        drop(dispatched);
+
+        // The last model of the chain is retried, until the budget runs out
+        let result = retry
+            .reopen(
+                &state,
+                &request(),
+                &route(),
+                Some("gemini-2.5-flash".to_string()),
+                unavailable(),
+            )
+            .await;
+        assert!(matches!(result, Err(ProxyError::ServiceUnavailable(_))));
+        assert_eq!(
+            *backend.requested.lock(),
+            vec!["gemini-2.5-flash", "gemini-2.5-flash"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_exhausted_chain_fails_without_calling_upstream() {
+        let backend = ScriptedBackend::with_streams(vec![Ok(stream(vec![answer("Hello")]))]);
+        let app = state(backend.clone(), false);
+        let soon = chrono::Utc::now() + chrono::Duration::minutes(5);
+        backend
+            .availability
+            .mark_terminal("gemini-2.5-pro", "quota".to_string(), soon);
+        backend.availability.mark_terminal(
+            "gemini-2.5-flash",
+            "quota".to_string(),
+            soon + chrono::Duration::minutes(5),
+        );
+
+        let Err(ProxyError::TooManyRequests(message)) =
+            open_stream(&app, &request(), &route(), &[]).await
+        else {
+            panic!("expected a 429");
+        };
+        assert!(message.contains(&soon.to_rfc3339()), "{}", message);
+        assert!(backend.requested.lock().is_empty());
+
+        // Skipping every model of the chain leaves nothing to fall back to
+        let backend = ScriptedBackend::with_streams(vec![Ok(stream(vec![answer("Hello")]))]);
+        let app = state(backend.clone(), false);
+        let skip = vec!["gemini-2.5-pro".to_string(), "gemini-2.5-flash".to_string()];
+        assert!(matches!(
+            open_stream(&app, &request(), &route(), &skip).await,
+            Err(ProxyError::ServiceUnavailable(_))
+        ));
+        assert!(backend.requested.lock().is_empty());
+    }
+}
@@ -8,12 +8,14 @@
 //!
 //! - `handlers`: Implementation of individual API endpoints (e.g., messages, health, metrics).
 //! - `middleware`: Custom tower/axum middleware for request ID tracking, logging, and more.
+//! - `probe`: Periodic, cached upstream probe behind `/health` and `/readyz`.
 //! - `routes`: The main router configuration that ties everything together.
 //!
 //! Author: kelexine (<https://github.com/kelexine>)
 
 mod handlers;
 mod middleware;
+mod probe;
 mod routes;
 
 pub use routes::{create_router, AppState};
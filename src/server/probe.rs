@@ -0,0 +1,166 @@
+//! Cached upstream probe behind `/health` and `/readyz`.
+//!
+//! Probing the upstream on every health request would let orchestrators that
+//! poll `/health` every few seconds add load and latency. Instead the primary
+//! account is probed every `health.probe_interval_seconds` with a quota-free
+//! check (see [`UpstreamBackend::check_connectivity`]) and the last result is
+//! served from here.
+//!
+//! Author: kelexine (<https://github.com/kelexine>)
+
+use crate::config::HealthConfig;
+use crate::error::ProxyError;
+use crate::gemini::{AccountPool, UpstreamBackend};
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Outcome of one probe.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProbeOutcome {
+    /// The upstream answered within the given round-trip time.
+    Reachable(Duration),
+    /// The upstream could not be reached (e.g. offline or timed out).
+    Unreachable(String),
+    /// The upstream answered with an error (e.g. rejected credentials).
+    Failed(String),
+}
+
+/// Most recent probe result.
+#[derive(Debug, Clone)]
+pub struct ProbeResult {
+    pub checked_at: DateTime<Utc>,
+    pub outcome: ProbeOutcome,
+}
+
+/// Periodically refreshed upstream status.
+pub struct UpstreamProbe {
+    config: HealthConfig,
+    latest: RwLock<Option<ProbeResult>>,
+}
+
+impl UpstreamProbe {
+    /// Creates a probe with no result yet.
+    pub fn new(config: &HealthConfig) -> Self {
+        Self {
+            config: config.clone(),
+            latest: RwLock::new(None),
+        }
+    }
+
+    /// Whether periodic probing is enabled.
+    pub fn enabled(&self) -> bool {
+        self.config.probe_interval_seconds > 0
+    }
+
+    /// Returns the most recent result, if a probe has completed.
+    pub fn latest(&self) -> Option<ProbeResult> {
+        self.latest.read().clone()
+    }
+
+    /// Probes `backend` once and stores the result.
+    pub async fn run(&self, backend: &dyn UpstreamBackend) -> ProbeResult {
+        let outcome = match backend.check_connectivity(&self.config.probe_model).await {
+            Ok(latency) => ProbeOutcome::Reachable(latency),
+            Err(e @ ProxyError::ServiceUnavailable(_)) => ProbeOutcome::Unreachable(e.to_string()),
+            Err(e) => ProbeOutcome::Failed(e.to_string()),
+        };
+        match &outcome {
+            ProbeOutcome::Reachable(latency) => debug!("Upstream probe passed in {:?}", latency),
+            ProbeOutcome::Unreachable(e) | ProbeOutcome::Failed(e) => {
+                warn!("Upstream probe failed: {}", e)
+            }
+        }
+
+        let result = ProbeResult {
+            checked_at: Utc::now(),
+            outcome,
+        };
+        *self.latest.write() = Some(result.clone());
+        result
+    }
+
+    /// Probes the primary account every `health.probe_interval_seconds`,
+    /// starting now.
+    pub fn spawn(self: Arc<Self>, accounts: Arc<AccountPool>) {
+        let interval = Duration::from_secs(self.config.probe_interval_seconds);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                self.run(accounts.primary().backend().as_ref()).await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BackendConfig, BackendKind, GeminiConfig};
+    use crate::gemini::backend::ApiKeyBackend;
+
+    fn backend(base_url: String) -> ApiKeyBackend {
+        ApiKeyBackend::new(
+            &BackendConfig {
+                name: "studio".to_string(),
+                kind: BackendKind::ApiKey,
+                api_key: Some("test-key".to_string()),
+                base_url: Some(base_url),
+                ..BackendConfig::default()
+            },
+            &GeminiConfig {
+                state_file: String::new(),
+                ..GeminiConfig::default()
+            },
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_run_caches_outcome() {
+        let mut server = mockito::Server::new_async().await;
+        let probe = UpstreamProbe::new(&HealthConfig::default());
+        assert!(probe.latest().is_none());
+
+        let ok = server
+            .mock("GET", "/models")
+            .match_query(mockito::Matcher::Any)
+            .with_body(r#"{"models": []}"#)
+            .create_async()
+            .await;
+        let result = probe.run(&backend(server.url())).await;
+        assert!(matches!(result.outcome, ProbeOutcome::Reachable(_)));
+        ok.remove_async().await;
+
+        server
+            .mock("GET", "/models")
+            .match_query(mockito::Matcher::Any)
+            .with_status(403)
+            .with_body(r#"{"error": {"message": "API key not valid"}}"#)
+            .create_async()
+            .await;
+        probe.run(&backend(server.url())).await;
+        assert!(matches!(
+            probe.latest().unwrap().outcome,
+            ProbeOutcome::Failed(_)
+        ));
+
+        // Nothing listens on a port that was just released
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        probe
+            .run(&backend(format!("http://127.0.0.1:{}", port)))
+            .await;
+        assert!(matches!(
+            probe.latest().unwrap().outcome,
+            ProbeOutcome::Unreachable(_)
+        ));
+    }
+}
@@ -5,13 +5,17 @@
 //!
 //! Author: kelexine (<https://github.com/kelexine>)
 
-use super::handlers::{event_logging_handler, health_handler, messages_handler, metrics_handler};
+use super::handlers::{
+    delete_file_handler, event_logging_handler, get_file_handler, get_model_handler,
+    health_handler, list_files_handler, list_models_handler, livez_handler, messages_handler,
+    metrics_handler, readyz_handler, upload_file_handler,
+};
 use super::middleware::request_id_layers;
 use crate::config::AppConfig;
-use crate::error::Result;
-use crate::gemini::GeminiClient;
-use crate::oauth::OAuthManager;
+use crate::error::{ProxyError, Result};
+use crate::gemini::{AccountPool, BackendRegistry};
 use axum::{
+    extract::DefaultBodyLimit,
     routing::{get, post},
     Router,
 };
@@ -26,12 +30,28 @@ use tower_http::trace::TraceLayer;
 pub struct AppState {
     /// Read-only global application configuration.
     pub config: AppConfig,
-    /// Shared instance of the Gemini API client.
-    pub gemini_client: Arc<GeminiClient>,
-    /// Manager for OAuth2 authentication with Google Cloud.
-    pub oauth_manager: OAuthManager,
+    /// Pooled Google accounts, each with its own Gemini client and OAuth manager.
+    pub accounts: Arc<AccountPool>,
+    /// Named `[[backends]]` (API key, Vertex AI) that routes can select.
+    pub backends: Arc<BackendRegistry>,
     /// Optional manager for Gemini 1.5 context caching feature.
     pub cache_manager: Option<Arc<crate::cache::CacheManager>>,
+    /// Fetcher for URL image and document sources (`None` when disabled).
+    pub media_fetcher: Option<Arc<crate::vision::MediaFetcher>>,
+    /// Storage for the local Files API (`None` when disabled).
+    pub file_store: Option<Arc<crate::files::FileStore>>,
+    /// System prompt injection policy (injects nothing by default).
+    pub prompt_policy: Arc<crate::translation::PromptPolicy>,
+    /// Per-model capability table (configured overrides plus built-ins).
+    pub model_registry: Arc<crate::models::ModelRegistry>,
+    /// Claude → Gemini model routing table.
+    pub model_router: Arc<crate::models::ModelRouter>,
+    /// Per-account, per-model concurrency and request rate limits.
+    pub limiter: Arc<crate::gemini::RequestLimiter>,
+    /// Per-endpoint, per-model circuit breakers.
+    pub circuit_breaker: Arc<crate::gemini::CircuitBreaker>,
+    /// Cached result of the periodic upstream probe.
+    pub probe: Arc<super::probe::UpstreamProbe>,
 }
 
 /// Creates the main application router with all core routes and middleware.
@@ -39,24 +59,28 @@ pub struct AppState {
 /// # Arguments
 ///
 /// * `config` - Application configuration loaded from environment or file.
-/// * `gemini_client` - Initialized client for the Gemini API.
-/// * `oauth_manager` - Manager for handling Google OAuth2 tokens.
+/// * `accounts` - Connected account pool (at least one account).
 ///
 /// # Returns
 ///
 /// A configured `axum::Router` ready to be served.
 ///
+/// Must be called from within a Tokio runtime: it starts background project
+/// resolution, the quota poller (unless `quota.refresh_seconds` is `0`) and the
+/// upstream probe (unless `health.probe_interval_seconds` is `0`).
+///
 /// # Routes
 ///
 /// - `GET /health`: Health checks for service and dependencies.
+/// - `GET /livez`: Liveness (the process is serving requests).
+/// - `GET /readyz`: Readiness (the upstream was reachable at the last probe).
 /// - `GET /metrics`: Prometheus-formatted metrics.
 /// - `POST /v1/messages`: Anthropic-compatible messages endpoint.
+/// - `GET /v1/models`, `GET /v1/models/{model_id}`: Supported models and their capabilities.
+/// - `POST /v1/files`, `GET /v1/files`: Upload and list files (when `files.enabled`).
+/// - `GET /v1/files/{file_id}`, `DELETE /v1/files/{file_id}`: File metadata and deletion.
 /// - `POST /api/event_logging/batch`: Sink for Claude Code telemetry/logs.
-pub fn create_router(
-    config: AppConfig,
-    gemini_client: GeminiClient,
-    oauth_manager: OAuthManager,
-) -> Result<Router> {
+pub fn create_router(config: AppConfig, accounts: AccountPool) -> Result<Router> {
     // Initialize cache manager if ENABLE_CONTEXT_CACHING is set
     let cache_manager = if std::env::var("ENABLE_CONTEXT_CACHING")
         .unwrap_or_else(|_| "false".to_string())
@@ -69,23 +93,93 @@ pub fn create_router(
         None
     };
 
+    let media_fetcher = if config.media.url_sources_enabled {
+        Some(Arc::new(crate::vision::MediaFetcher::new(&config.media)))
+    } else {
+        None
+    };
+
+    let file_store = if config.files.enabled {
+        Some(Arc::new(crate::files::FileStore::open(&config.files)?))
+    } else {
+        None
+    };
+    // Leave headroom over the file cap for multipart boundaries and headers
+    let upload_body_limit = config.files.max_file_bytes + 64 * 1024;
+
+    let prompt_policy = Arc::new(crate::translation::PromptPolicy::new(&config.prompt)?);
+    let model_registry = Arc::new(crate::models::ModelRegistry::new(&config.models)?);
+    let model_router = Arc::new(crate::models::ModelRouter::new(
+        &config.routing,
+        &config.gemini.default_model,
+    )?);
+    let backends = Arc::new(BackendRegistry::new(&config)?);
+    let accounts = Arc::new(accounts);
+    accounts.clone().spawn_project_resolution();
+    if config.quota.refresh_seconds > 0 {
+        accounts
+            .clone()
+            .spawn_quota_refresh(std::time::Duration::from_secs(config.quota.refresh_seconds));
+    }
+    let limiter = Arc::new(crate::gemini::RequestLimiter::new(&config.limits));
+    let circuit_breaker = Arc::new(crate::gemini::CircuitBreaker::new(&config.circuit_breaker));
+    let probe = Arc::new(super::probe::UpstreamProbe::new(&config.health));
+    if probe.enabled() {
+        probe.clone().spawn(accounts.clone());
+    }
+    if let Some(name) = model_router
+        .backend_names()
+        .find(|name| backends.get(name).is_none())
+    {
+        return Err(ProxyError::Config(format!(
+            "Routing refers to unknown backend: {}",
+            name
+        )));
+    }
+
     let state = AppState {
         config,
-        gemini_client: Arc::new(gemini_client),
-        oauth_manager,
+        accounts,
+        backends,
         cache_manager,
+        media_fetcher,
+        file_store,
+        prompt_policy,
+        model_registry,
+        model_router,
+        limiter,
+        circuit_breaker,
+        probe,
     };
 
     let (set_request_id, propagate_request_id) = request_id_layers();
 
+    // Uploads are capped by `files.max_file_bytes` instead of the 50MB limit
+    let files = Router::new()
+        .route(
+            "/v1/files",
+            post(upload_file_handler)
+                .get(list_files_handler)
+                .layer(DefaultBodyLimit::max(upload_body_limit)),
+        )
+        .route(
+            "/v1/files/:file_id",
+            get(get_file_handler).delete(delete_file_handler),
+        );
+
     let app = Router::new()
         .route("/health", get(health_handler))
+        .route("/livez", get(livez_handler))
+        .route("/readyz", get(readyz_handler))
         .route("/metrics", get(metrics_handler))
         .route("/v1/messages", post(messages_handler))
+        .route("/v1/models", get(list_models_handler))
+        .route("/v1/models/:model_id", get(get_model_handler))
         .route("/api/event_logging/batch", post(event_logging_handler))
         .layer(tower_http::limit::RequestBodyLimitLayer::new(
             50 * 1024 * 1024,
         )) // 50MB limit
+        .merge(files)
         .layer(TraceLayer::new_for_http())
         .layer(propagate_request_id)
         .layer(set_request_id)
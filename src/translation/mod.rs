@@ -1,12 +1,18 @@
 // Translation module - Anthropic ↔ Gemini API translation
 // Author: kelexine (https://github.com/kelexine)
 
+pub mod normalize;
+pub mod prompt;
 pub mod request;
 pub mod response;
 pub mod signature_store;
 pub mod streaming;
 pub mod tools;
 
+pub use normalize::normalize_messages;
+pub use prompt::{client_name, PromptPolicy, PromptVars};
 pub use request::translate_request;
 pub use response::translate_response;
-pub use signature_store::{get_signature, store_signature};
+pub use signature_store::{
+    decode_thinking_signature, encode_thinking_signature, get_signature, store_signature,
+};
@@ -0,0 +1,315 @@
+// Conversation normalization (repairs history before Anthropic → Gemini translation)
+// Author: kelexine (https://github.com/kelexine)
+//
+// Gemini is much stricter about conversation shape than Anthropic clients are:
+// roles must alternate, and a function call turn must be answered by the next
+// turn with matching function responses in call order. This pass repairs
+// histories that break those rules instead of letting them fail upstream.
+
+use crate::models::anthropic::{ContentBlock, Message, MessageContent, ToolResultContent};
+use tracing::info;
+
+/// Normalize a conversation so it translates into a valid Gemini history.
+///
+/// Repairs applied (each one is logged):
+/// 1. Adjacent messages with the same role are merged into one.
+/// 2. Tool results that don't answer a tool use from the preceding assistant
+///    message become plain text (followed by their images and documents)
+///    instead of function responses.
+/// 3. Tool results are reordered to match the order of the preceding tool uses.
+pub fn normalize_messages(messages: Vec<Message>) -> Vec<Message> {
+    let mut merged = merge_same_role(messages);
+
+    for i in 1..merged.len() {
+        if merged[i].role != "user" || merged[i - 1].role != "assistant" {
+            continue;
+        }
+
+        let call_ids = tool_use_ids(&merged[i - 1]);
+        if let MessageContent::Blocks(blocks) = &mut merged[i].content {
+            convert_orphaned_results(blocks, &call_ids, i);
+            reorder_tool_results(blocks, &call_ids, i);
+        }
+    }
+
+    // A user message that doesn't follow an assistant turn can't answer any call
+    if let Some(first) = merged.first_mut() {
+        if let MessageContent::Blocks(blocks) = &mut first.content {
+            convert_orphaned_results(blocks, &[], 0);
+        }
+    }
+
+    merged
+}
+
+/// Merge adjacent messages that share a role.
+fn merge_same_role(messages: Vec<Message>) -> Vec<Message> {
+    let mut merged: Vec<Message> = Vec::with_capacity(messages.len());
+
+    for (index, message) in messages.into_iter().enumerate() {
+        match merged.last_mut() {
+            Some(previous) if previous.role == message.role => {
+                info!(
+                    "Normalization: merged {} message {} into the preceding {} message",
+                    message.role, index, previous.role
+                );
+                let mut blocks = into_blocks(std::mem::replace(
+                    &mut previous.content,
+                    MessageContent::Blocks(Vec::new()),
+                ));
+                blocks.extend(into_blocks(message.content));
+                previous.content = MessageContent::Blocks(blocks);
+            }
+            _ => merged.push(message),
+        }
+    }
+
+    merged
+}
+
+/// Convert message content into a list of blocks.
+fn into_blocks(content: MessageContent) -> Vec<ContentBlock> {
+    match content {
+        MessageContent::Text(text) => vec![ContentBlock::Text {
+            text,
+            cache_control: None,
+        }],
+        MessageContent::Blocks(blocks) => blocks,
+    }
+}
+
+/// IDs of the tool uses in a message, in order.
+fn tool_use_ids(message: &Message) -> Vec<String> {
+    match &message.content {
+        MessageContent::Blocks(blocks) => blocks
+            .iter()
+            .filter_map(|block| match block {
+                ContentBlock::ToolUse { id, .. } => Some(id.clone()),
+                _ => None,
+            })
+            .collect(),
+        MessageContent::Text(_) => Vec::new(),
+    }
+}
+
+/// Replace tool results whose `tool_use_id` isn't in `call_ids` with text blocks.
+///
+/// The text carries the result's text; its images and documents follow it as
+/// sibling blocks.
+fn convert_orphaned_results(
+    blocks: &mut Vec<ContentBlock>,
+    call_ids: &[String],
+    message_index: usize,
+) {
+    let mut converted = Vec::with_capacity(blocks.len());
+    for block in std::mem::take(blocks) {
+        match block {
+            ContentBlock::ToolResult {
+                tool_use_id,
+                content,
+                is_error,
+            } if !call_ids.contains(&tool_use_id) => {
+                info!(
+                    "Normalization: converted orphaned tool result {} in message {} to text",
+                    tool_use_id, message_index
+                );
+                let label = if is_error.unwrap_or(false) {
+                    "Tool error"
+                } else {
+                    "Tool result"
+                };
+                converted.push(ContentBlock::Text {
+                    text: format!("[{} for {}]\n{}", label, tool_use_id, content),
+                    cache_control: None,
+                });
+                if let ToolResultContent::Blocks(parts) = content {
+                    converted.extend(
+                        parts
+                            .into_iter()
+                            .filter(|part| !matches!(part, ContentBlock::Text { .. })),
+                    );
+                }
+            }
+            block => converted.push(block),
+        }
+    }
+    *blocks = converted;
+}
+
+/// Reorder tool results to follow `call_ids`, keeping other blocks in place.
+fn reorder_tool_results(blocks: &mut [ContentBlock], call_ids: &[String], message_index: usize) {
+    let position = |block: &ContentBlock| match block {
+        ContentBlock::ToolResult { tool_use_id, .. } => {
+            call_ids.iter().position(|id| id == tool_use_id)
+        }
+        _ => None,
+    };
+
+    let slots: Vec<usize> = (0..blocks.len())
+        .filter(|&i| position(&blocks[i]).is_some())
+        .collect();
+    let mut results: Vec<ContentBlock> = slots.iter().map(|&i| blocks[i].clone()).collect();
+    if results.is_sorted_by_key(position) {
+        return;
+    }
+
+    info!(
+        "Normalization: reordered tool results in message {} to match call order",
+        message_index
+    );
+    results.sort_by_key(position);
+    for (slot, result) in slots.into_iter().zip(results) {
+        blocks[slot] = result;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(role: &str, text: &str) -> Message {
+        Message {
+            role: role.to_string(),
+            content: MessageContent::Text(text.to_string()),
+        }
+    }
+
+    fn tool_use(id: &str) -> ContentBlock {
+        ContentBlock::ToolUse {
+            id: id.to_string(),
+            name: "read_file".to_string(),
+            input: serde_json::json!({}),
+            cache_control: None,
+        }
+    }
+
+    fn tool_result(id: &str) -> ContentBlock {
+        ContentBlock::ToolResult {
+            tool_use_id: id.to_string(),
+            content: ToolResultContent::Text(format!("output of {}", id)),
+            is_error: None,
+        }
+    }
+
+    fn result_ids(message: &Message) -> Vec<String> {
+        let MessageContent::Blocks(blocks) = &message.content else {
+            return Vec::new();
+        };
+        blocks
+            .iter()
+            .filter_map(|block| match block {
+                ContentBlock::ToolResult { tool_use_id, .. } => Some(tool_use_id.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_merges_adjacent_same_role_messages() {
+        let normalized = normalize_messages(vec![
+            text("user", "first"),
+            text("user", "second"),
+            text("assistant", "reply"),
+        ]);
+
+        assert_eq!(normalized.len(), 2);
+        match &normalized[0].content {
+            MessageContent::Blocks(blocks) => assert_eq!(blocks.len(), 2),
+            other => panic!("Expected merged blocks, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_reorders_results_to_call_order() {
+        let normalized = normalize_messages(vec![
+            text("user", "read both"),
+            Message {
+                role: "assistant".to_string(),
+                content: MessageContent::Blocks(vec![tool_use("toolu_a"), tool_use("toolu_b")]),
+            },
+            Message {
+                role: "user".to_string(),
+                content: MessageContent::Blocks(vec![
+                    ContentBlock::Text {
+                        text: "note".to_string(),
+                        cache_control: None,
+                    },
+                    tool_result("toolu_b"),
+                    tool_result("toolu_a"),
+                ]),
+            },
+        ]);
+
+        assert_eq!(result_ids(&normalized[2]), vec!["toolu_a", "toolu_b"]);
+        let MessageContent::Blocks(blocks) = &normalized[2].content else {
+            panic!("Expected blocks");
+        };
+        assert!(matches!(blocks[0], ContentBlock::Text { .. }));
+    }
+
+    #[test]
+    fn test_orphaned_results_become_text() {
+        let normalized = normalize_messages(vec![
+            text("user", "hello"),
+            text("assistant", "no tools here"),
+            Message {
+                role: "user".to_string(),
+                content: MessageContent::Blocks(vec![tool_result("toolu_gone")]),
+            },
+        ]);
+
+        assert!(result_ids(&normalized[2]).is_empty());
+        let MessageContent::Blocks(blocks) = &normalized[2].content else {
+            panic!("Expected blocks");
+        };
+        match &blocks[0] {
+            ContentBlock::Text { text, .. } => {
+                assert!(text.contains("toolu_gone"));
+                assert!(text.contains("output of toolu_gone"));
+            }
+            other => panic!("Expected text block, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_orphaned_result_keeps_images() {
+        let normalized = normalize_messages(vec![Message {
+            role: "user".to_string(),
+            content: MessageContent::Blocks(vec![
+                ContentBlock::ToolResult {
+                    tool_use_id: "toolu_gone".to_string(),
+                    content: ToolResultContent::Blocks(vec![
+                        ContentBlock::Text {
+                            text: "screenshot taken".to_string(),
+                            cache_control: None,
+                        },
+                        ContentBlock::Image {
+                            source: crate::models::anthropic::ImageSource::Base64 {
+                                media_type: Some("image/png".to_string()),
+                                data: "iVBORw0KGgo=".to_string(),
+                            },
+                            cache_control: None,
+                        },
+                    ]),
+                    is_error: None,
+                },
+                ContentBlock::Text {
+                    text: "what do you see?".to_string(),
+                    cache_control: None,
+                },
+            ]),
+        }]);
+
+        let MessageContent::Blocks(blocks) = &normalized[0].content else {
+            panic!("Expected blocks");
+        };
+        assert_eq!(blocks.len(), 3);
+        assert!(
+            matches!(&blocks[0], ContentBlock::Text { text, .. } if text.contains("screenshot taken"))
+        );
+        assert!(matches!(&blocks[1], ContentBlock::Image { .. }));
+        assert!(
+            matches!(&blocks[2], ContentBlock::Text { text, .. } if text == "what do you see?")
+        );
+    }
+}
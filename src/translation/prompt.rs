@@ -0,0 +1,239 @@
+// System prompt injection policy
+// Author: kelexine (https://github.com/kelexine)
+//
+// Applies the configured prefix/suffix snippets and model/client templates to
+// the system prompt before translation. With the default configuration the
+// client's system prompt reaches Gemini unchanged.
+
+use crate::config::{PromptConfig, PromptTemplate};
+use crate::error::{ProxyError, Result};
+use crate::models::anthropic::{ContentBlock, MessagesRequest, SystemPrompt};
+use std::collections::HashMap;
+use tracing::debug;
+
+/// Values substituted into injected prompt text.
+pub struct PromptVars<'a> {
+    /// Model name requested by the client.
+    pub model: &'a str,
+    /// Gemini model the request is routed to.
+    pub gemini_model: &'a str,
+    /// Client name (see [`client_name`]).
+    pub client: &'a str,
+}
+
+/// Resolved system prompt injection policy.
+#[derive(Debug, Clone, Default)]
+pub struct PromptPolicy {
+    prefix: Vec<String>,
+    suffix: Vec<String>,
+    templates: Vec<PromptTemplate>,
+}
+
+impl PromptPolicy {
+    /// Build the policy from configuration, resolving snippet names.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProxyError::Config` if `prefix` or `suffix` names an undefined snippet.
+    pub fn new(config: &PromptConfig) -> Result<Self> {
+        let snippets: HashMap<String, &String> = config
+            .snippets
+            .iter()
+            .map(|(name, text)| (name.to_lowercase(), text))
+            .collect();
+
+        let resolve = |names: &[String]| {
+            names
+                .iter()
+                .map(|name| {
+                    snippets
+                        .get(&name.to_lowercase())
+                        .map(|text| text.to_string())
+                        .ok_or_else(|| {
+                            ProxyError::Config(format!("Unknown prompt snippet: {}", name))
+                        })
+                })
+                .collect::<Result<Vec<_>>>()
+        };
+
+        Ok(Self {
+            prefix: resolve(&config.prefix)?,
+            suffix: resolve(&config.suffix)?,
+            templates: config.templates.clone(),
+        })
+    }
+
+    /// Whether the policy never changes a request.
+    pub fn is_empty(&self) -> bool {
+        self.prefix.is_empty() && self.suffix.is_empty() && self.templates.is_empty()
+    }
+
+    /// Inject the configured text around the request's system prompt.
+    pub fn apply(&self, req: &mut MessagesRequest, vars: &PromptVars<'_>) {
+        let matching: Vec<&PromptTemplate> = self
+            .templates
+            .iter()
+            .filter(|t| matches_name(t.model.as_deref(), vars.model))
+            .filter(|t| matches_name(t.client.as_deref(), vars.client))
+            .collect();
+
+        let prefix: Vec<&str> = self
+            .prefix
+            .iter()
+            .map(String::as_str)
+            .chain(matching.iter().filter_map(|t| t.prefix.as_deref()))
+            .collect();
+        let suffix: Vec<&str> = matching
+            .iter()
+            .filter_map(|t| t.suffix.as_deref())
+            .chain(self.suffix.iter().map(String::as_str))
+            .collect();
+
+        if prefix.is_empty() && suffix.is_empty() {
+            return;
+        }
+
+        debug!(
+            "Injecting {} prefix and {} suffix prompt snippets (model: {}, client: {})",
+            prefix.len(),
+            suffix.len(),
+            vars.model,
+            vars.client
+        );
+
+        let text_block = |text: &str| ContentBlock::Text {
+            text: render(text, vars),
+            cache_control: None,
+        };
+        let original = match req.system.take() {
+            Some(SystemPrompt::Text(text)) => vec![ContentBlock::Text {
+                text,
+                cache_control: None,
+            }],
+            Some(SystemPrompt::Blocks(blocks)) => blocks,
+            None => Vec::new(),
+        };
+
+        let blocks = prefix
+            .into_iter()
+            .map(text_block)
+            .chain(original)
+            .chain(suffix.into_iter().map(text_block))
+            .collect();
+        req.system = Some(SystemPrompt::Blocks(blocks));
+    }
+}
+
+/// Identify the client from its `User-Agent` product token (e.g. `claude-cli`).
+pub fn client_name(headers: &axum::http::HeaderMap) -> String {
+    headers
+        .get(axum::http::header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|ua| ua.split(['/', ' ']).next())
+        .filter(|name| !name.is_empty())
+        .map(str::to_lowercase)
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Case-insensitive match where an unset pattern matches everything.
+fn matches_name(pattern: Option<&str>, value: &str) -> bool {
+    pattern.is_none_or(|p| p.eq_ignore_ascii_case(value))
+}
+
+/// Substitute `{{date}}`, `{{model}}`, `{{gemini_model}}` and `{{client}}`.
+fn render(text: &str, vars: &PromptVars<'_>) -> String {
+    text.replace(
+        "{{date}}",
+        &chrono::Local::now().format("%Y-%m-%d").to_string(),
+    )
+    .replace("{{gemini_model}}", vars.gemini_model)
+    .replace("{{model}}", vars.model)
+    .replace("{{client}}", vars.client)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(system: &str) -> MessagesRequest {
+        serde_json::from_value(serde_json::json!({
+            "model": "claude-opus-4-5",
+            "max_tokens": 1024,
+            "system": system,
+            "messages": [{"role": "user", "content": "hi"}],
+        }))
+        .unwrap()
+    }
+
+    const VARS: PromptVars<'static> = PromptVars {
+        model: "claude-opus-4-5",
+        gemini_model: "gemini-3-pro-preview",
+        client: "claude-cli",
+    };
+
+    #[test]
+    fn test_default_policy_injects_nothing() {
+        let policy = PromptPolicy::new(&PromptConfig::default()).unwrap();
+        let mut req = request("Be terse.");
+        policy.apply(&mut req, &VARS);
+
+        assert!(policy.is_empty());
+        assert_eq!(req.system.unwrap().to_text(), "Be terse.");
+    }
+
+    #[test]
+    fn test_snippets_and_templates() {
+        let config = PromptConfig {
+            snippets: HashMap::from([
+                ("Style".to_string(), "Use British spelling.".to_string()),
+                ("footer".to_string(), "Client: {{client}}".to_string()),
+            ]),
+            prefix: vec!["style".to_string()],
+            suffix: vec!["footer".to_string()],
+            templates: vec![
+                PromptTemplate {
+                    model: Some("claude-opus-4-5".to_string()),
+                    prefix: Some("Running as {{model}} on {{gemini_model}}.".to_string()),
+                    ..PromptTemplate::default()
+                },
+                PromptTemplate {
+                    client: Some("other-client".to_string()),
+                    suffix: Some("Not for this client.".to_string()),
+                    ..PromptTemplate::default()
+                },
+            ],
+        };
+        let policy = PromptPolicy::new(&config).unwrap();
+        let mut req = request("Be terse.");
+        policy.apply(&mut req, &VARS);
+
+        assert_eq!(
+            req.system.unwrap().to_text(),
+            "Use British spelling.\n\
+             Running as claude-opus-4-5 on gemini-3-pro-preview.\n\
+             Be terse.\n\
+             Client: claude-cli"
+        );
+    }
+
+    #[test]
+    fn test_unknown_snippet_is_rejected() {
+        let config = PromptConfig {
+            prefix: vec!["missing".to_string()],
+            ..PromptConfig::default()
+        };
+        assert!(PromptPolicy::new(&config).is_err());
+    }
+
+    #[test]
+    fn test_client_name_from_user_agent() {
+        let mut headers = axum::http::HeaderMap::new();
+        assert_eq!(client_name(&headers), "unknown");
+
+        headers.insert(
+            axum::http::header::USER_AGENT,
+            "claude-cli/2.0.14 (external, cli)".parse().unwrap(),
+        );
+        assert_eq!(client_name(&headers), "claude-cli");
+    }
+}
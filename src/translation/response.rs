@@ -4,6 +4,7 @@
 use crate::error::{ProxyError, Result};
 use crate::models::anthropic::{ContentBlock, MessagesResponse, Usage};
 use crate::models::gemini::{GenerateContentResponse, Part as GeminiPart};
+use crate::translation::signature_store::encode_thinking_signature;
 use regex::Regex;
 use std::sync::OnceLock;
 use tracing::{debug, warn};
@@ -87,18 +88,24 @@ fn strip_thinking_artifacts(parts: Vec<GeminiPart>) -> Result<Vec<GeminiPart>> {
     parts
         .into_iter()
         .filter_map(|part| match part {
-            GeminiPart::Text { text, .. } => {
+            GeminiPart::Text {
+                text,
+                thought,
+                thought_signature,
+            } => {
                 // Remove <think>...</think> tags
                 let cleaned = get_thinking_regex().replace_all(&text, "").to_string();
 
-                // Only include if there's remaining content after stripping
-                if cleaned.trim().is_empty() {
+                // Only include if there's remaining content after stripping,
+                // keeping signed thought parts so their signature survives
+                let is_signed_thought = thought == Some(true) && thought_signature.is_some();
+                if cleaned.trim().is_empty() && !is_signed_thought {
                     None
                 } else {
                     Some(Ok(GeminiPart::Text {
                         text: cleaned,
-                        thought: None,
-                        thought_signature: None,
+                        thought,
+                        thought_signature,
                     }))
                 }
             }
@@ -122,15 +129,34 @@ pub fn translate_parts(parts: Vec<GeminiPart>) -> Result<Vec<ContentBlock>> {
 /// - FunctionResponse -> Error (should not be in output)
 fn translate_part(part: GeminiPart) -> Result<ContentBlock> {
     match part {
+        // Gemini 2.0+ flags native thinking content on text parts
+        GeminiPart::Text {
+            text,
+            thought: Some(true),
+            thought_signature,
+        } => {
+            debug!("Translating Gemini thought to Claude thinking block");
+            Ok(ContentBlock::Thinking {
+                thinking: text,
+                signature: thought_signature.as_deref().map(encode_thinking_signature),
+            })
+        }
+
         GeminiPart::Text { text, .. } => Ok(ContentBlock::Text {
             text,
             cache_control: None,
         }),
 
         // Extended thinking - translate Gemini thought to Claude thinking block
-        GeminiPart::Thought { thought, .. } => {
+        GeminiPart::Thought {
+            thought,
+            thought_signature,
+        } => {
             debug!("Translating Gemini thought to Claude thinking block");
-            Ok(ContentBlock::Thinking { thinking: thought })
+            Ok(ContentBlock::Thinking {
+                thinking: thought,
+                signature: thought_signature.as_deref().map(encode_thinking_signature),
+            })
         }
 
         GeminiPart::InlineData { inline_data } => {
@@ -282,4 +308,24 @@ mod tests {
             panic!("Expected ToolUse content block");
         }
     }
+
+    #[test]
+    fn test_thought_part_translation() {
+        let thought_part = GeminiPart::Text {
+            text: "Considering options".to_string(),
+            thought: Some(true),
+            thought_signature: Some("CiQBjz1rX".to_string()),
+        };
+
+        match translate_part(thought_part).unwrap() {
+            ContentBlock::Thinking {
+                thinking,
+                signature,
+            } => {
+                assert_eq!(thinking, "Considering options");
+                assert_eq!(signature.as_deref(), Some("gem2claude:CiQBjz1rX"));
+            }
+            other => panic!("Expected Thinking content block, got {:?}", other),
+        }
+    }
 }
@@ -50,6 +50,23 @@ pub fn get_signature(tool_use_id: &str) -> Option<String> {
     }
 }
 
+/// Prefix identifying Anthropic thinking signatures that wrap a Gemini thoughtSignature
+const THINKING_SIGNATURE_PREFIX: &str = "gem2claude:";
+
+/// Wrap a Gemini thoughtSignature for use as an Anthropic thinking block `signature`
+/// The prefix lets us recognize blocks this proxy emitted when clients replay them
+pub fn encode_thinking_signature(thought_signature: &str) -> String {
+    format!("{}{}", THINKING_SIGNATURE_PREFIX, thought_signature)
+}
+
+/// Recover the Gemini thoughtSignature from an Anthropic thinking block `signature`
+/// Returns None for signatures this proxy did not issue (e.g. from real Claude models)
+pub fn decode_thinking_signature(signature: &str) -> Option<&str> {
+    signature
+        .strip_prefix(THINKING_SIGNATURE_PREFIX)
+        .filter(|sig| !sig.is_empty())
+}
+
 /// Clean up old signatures to prevent memory growth
 /// Call periodically or when conversation ends
 pub fn cleanup_signatures(tool_use_ids: &[String]) {
@@ -92,4 +109,14 @@ mod tests {
         let retrieved = get_signature("nonexistent_id");
         assert_eq!(retrieved, None);
     }
+
+    #[test]
+    fn test_thinking_signature_round_trip() {
+        let encoded = encode_thinking_signature("CiQBjz1rX");
+        assert_eq!(decode_thinking_signature(&encoded), Some("CiQBjz1rX"));
+
+        // Signatures from other providers are not ours to replay
+        assert_eq!(decode_thinking_signature("EqQBCkgIARABGAIiQL"), None);
+        assert_eq!(decode_thinking_signature("gem2claude:"), None);
+    }
 }
@@ -55,6 +55,11 @@ pub struct StreamTranslator {
     thinking_buffer: String,
     /// Flag indicating if the cursor is currently inside a `<think>` block.
     in_thinking: bool,
+
+    /// Events held back until the first content delta (see `translate_chunk`).
+    pending: Vec<StreamEvent>,
+    /// Whether any event has been released to the client.
+    committed: bool,
 }
 
 impl StreamTranslator {
@@ -75,9 +80,25 @@ impl StreamTranslator {
 
             thinking_buffer: String::new(),
             in_thinking: false,
+
+            pending: Vec::new(),
+            committed: false,
         }
     }
 
+    /// Whether any event has been released to the client. Until then the
+    /// upstream request can be reissued without the client noticing.
+    pub fn has_committed(&self) -> bool {
+        self.committed
+    }
+
+    /// Releases events held back by `translate_chunk`, e.g. when the upstream
+    /// stream ends before producing content.
+    pub fn flush(&mut self) -> Vec<StreamEvent> {
+        self.committed = true;
+        std::mem::take(&mut self.pending)
+    }
+
     /// Segments a text chunk into logical parts by detecting `<think>` and `</think>` tags.
     ///
     /// This method is designed to be robust against "fragmented tags" where an opening
@@ -178,11 +199,42 @@ impl StreamTranslator {
 
     /// Primary entry point for translating a Gemini API chunk into Anthropic events.
     ///
+    /// `message_start` and block starts are held back until the first content
+    /// delta (or the end of the message), so that a stream failing before any
+    /// content can be retried without the client seeing a second message.
+    /// Returns the events ready to be sent.
+    pub fn translate_chunk(
+        &mut self,
+        gemini_chunk: GenerateContentResponse,
+    ) -> Result<Vec<StreamEvent>> {
+        let events = self.translate_events(gemini_chunk)?;
+        if self.committed {
+            return Ok(events);
+        }
+
+        let has_content = events.iter().any(|event| {
+            !matches!(
+                event,
+                StreamEvent::MessageStart { .. }
+                    | StreamEvent::ContentBlockStart { .. }
+                    | StreamEvent::Ping
+            )
+        });
+        self.pending.extend(events);
+        if has_content {
+            Ok(self.flush())
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    /// Translates one chunk without holding anything back.
+    ///
     /// This method manages the lifecycle of the entire stream:
     /// - Emits `message_start` on the first encounter.
     /// - Dispatches content to `emit_thinking_content`, `emit_text_segments`, or `emit_tool_use`.
     /// - Finalizes the stream with `emit_completion` when the `finish_reason` is detected.
-    pub fn translate_chunk(
+    fn translate_events(
         &mut self,
         gemini_chunk: GenerateContentResponse,
     ) -> Result<Vec<StreamEvent>> {
@@ -312,7 +364,9 @@ impl StreamTranslator {
         if let Some(sig) = signature {
             events.push(StreamEvent::ContentBlockDelta {
                 index: self.current_block_index,
-                delta: Delta::SignatureDelta { signature: sig },
+                delta: Delta::SignatureDelta {
+                    signature: crate::translation::encode_thinking_signature(&sig),
+                },
             });
         }
     }
@@ -464,6 +518,53 @@ mod tests {
         assert_eq!(segments[1], (BlockType::Thinking, "internal".to_string()));
     }
 
+    fn chunk(value: serde_json::Value) -> GenerateContentResponse {
+        serde_json::from_value(serde_json::json!({ "response": value })).unwrap()
+    }
+
+    #[test]
+    fn test_message_start_held_until_content() {
+        let mut translator = StreamTranslator::new("test".to_string());
+
+        // Usage alone is not content
+        let events = translator
+            .translate_chunk(chunk(serde_json::json!({
+                "candidates": [],
+                "usageMetadata": {"promptTokenCount": 12},
+            })))
+            .unwrap();
+        assert!(events.is_empty());
+        assert!(!translator.has_committed());
+
+        let events = translator
+            .translate_chunk(chunk(serde_json::json!({
+                "candidates": [{"content": {"role": "model", "parts": [{"text": "Hi"}]}}],
+            })))
+            .unwrap();
+        assert!(translator.has_committed());
+        assert!(matches!(
+            &events[0],
+            StreamEvent::MessageStart { message } if message.usage.input_tokens == 12
+        ));
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, StreamEvent::ContentBlockDelta { .. })));
+    }
+
+    #[test]
+    fn test_flush_releases_held_events() {
+        let mut translator = StreamTranslator::new("test".to_string());
+        translator
+            .translate_chunk(chunk(serde_json::json!({"candidates": []})))
+            .unwrap();
+
+        let events = translator.flush();
+        assert_eq!(events.len(), 1);
+        assert!(matches!(events[0], StreamEvent::MessageStart { .. }));
+        assert!(translator.has_committed());
+        assert!(translator.flush().is_empty());
+    }
+
     #[test]
     fn test_partial_tag_detection() {
         assert_eq!(
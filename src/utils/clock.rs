@@ -0,0 +1,52 @@
+//! Injectable wall clock.
+//!
+//! Time-dependent state (model availability expiry, quota resets) reads the
+//! current time through the `Clock` trait so that tests can drive it with
+//! `MockClock` instead of sleeping.
+//!
+//! Author: kelexine (<https://github.com/kelexine>)
+
+use chrono::{DateTime, Utc};
+use std::sync::Mutex;
+
+/// Source of the current time.
+pub trait Clock: Send + Sync + std::fmt::Debug {
+    /// Returns the current instant.
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The real system clock.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A manually advanced clock for tests.
+#[derive(Debug)]
+pub struct MockClock {
+    now: Mutex<DateTime<Utc>>,
+}
+
+impl MockClock {
+    /// Creates a clock frozen at `now`.
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            now: Mutex::new(now),
+        }
+    }
+
+    /// Moves the clock forward by `by`.
+    pub fn advance(&self, by: chrono::Duration) {
+        *self.now.lock().unwrap() += by;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
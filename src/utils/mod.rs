@@ -5,10 +5,14 @@
 //!
 //! # Submodules
 //!
+//! - `clock`: Injectable wall clock for time-dependent state.
 //! - `logging`: Tracing and logging initialization with security filters.
+//! - `paths`: Filesystem path helpers.
 //! - `retry`: Robust retry mechanisms that respect upstream API hints.
 //!
 //! Author: kelexine (<https://github.com/kelexine>)
 
+pub mod clock;
 pub mod logging;
+pub mod paths;
 pub mod retry;
@@ -0,0 +1,13 @@
+//! Filesystem path helpers.
+//!
+//! Author: kelexine (<https://github.com/kelexine>)
+
+use std::path::PathBuf;
+
+/// Expands a leading `~/` to the user's home directory.
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
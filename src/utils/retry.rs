@@ -84,10 +84,19 @@ pub fn create_backoff() -> ExponentialBackoff {
 /// * **502**: Bad Gateway
 /// * **503**: Service Unavailable
 /// * **504**: Gateway Timeout
+/// * [`CONNECT_ERROR`]: the request never reached the server
 pub fn is_retryable(status: u16) -> bool {
-    matches!(status, 429 | 500 | 502 | 503 | 504)
+    matches!(status, CONNECT_ERROR | 429 | 500 | 502 | 503 | 504)
 }
 
+/// Pseudo-status for a connection that could not be established (refused,
+/// DNS failure, connect timeout). Unlike other transport failures (status 0),
+/// the request never left the machine, so it is always safe to retry.
+pub const CONNECT_ERROR: u16 = 1;
+
+/// Attempts made by [`with_retry`].
+const MAX_ATTEMPTS: u32 = 5;
+
 /// Executes an asynchronous operation with intelligent retry logic.
 ///
 /// This is the primary entry point for making resilient API calls.
@@ -102,17 +111,32 @@ pub fn is_retryable(status: u16) -> bool {
 ///
 /// * `operation_name` - Descriptive name used for logging retry attempts.
 /// * `operation` - A closure that returns a `Future` yielding `Result<T, (u16, String)>`.
-pub async fn with_retry<F, Fut, T>(
+pub async fn with_retry<F, Fut, T>(operation_name: &str, operation: F) -> Result<T, (u16, String)>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, (u16, String)>>,
+{
+    with_retry_limit(operation_name, MAX_ATTEMPTS - 1, |_, _| true, operation).await
+}
+
+/// Executes an operation like [`with_retry`], retrying at most `max_retries` times.
+///
+/// `should_retry` is consulted with the status and body of each retryable
+/// failure before waiting; returning `false` gives up immediately (e.g. when
+/// the failure shows the model is out of quota).
+pub async fn with_retry_limit<F, Fut, T, R>(
     operation_name: &str,
+    max_retries: u32,
+    mut should_retry: R,
     mut operation: F,
 ) -> Result<T, (u16, String)>
 where
     F: FnMut() -> Fut,
     Fut: std::future::Future<Output = Result<T, (u16, String)>>,
+    R: FnMut(u16, &str) -> bool,
 {
     let mut backoff = create_backoff();
     let mut attempt = 0;
-    const MAX_ATTEMPTS: u32 = 5;
 
     loop {
         attempt += 1;
@@ -126,7 +150,10 @@ where
             }
             Err((status, error_body)) => {
                 // Determine if we should attempt another retry
-                if !is_retryable(status) || attempt >= MAX_ATTEMPTS {
+                if !is_retryable(status)
+                    || attempt > max_retries
+                    || !should_retry(status, &error_body)
+                {
                     return Err((status, error_body));
                 }
 
@@ -191,12 +218,48 @@ mod tests {
         assert_eq!(parse_duration_string("120s").unwrap().as_secs(), 60);
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn test_with_retry_limit() {
+        let attempts = std::cell::Cell::new(0);
+        let operation = || {
+            attempts.set(attempts.get() + 1);
+            async { Err::<(), _>((503, "unavailable".to_string())) }
+        };
+
+        // Two retries means three attempts in total
+        let result = with_retry_limit("test", 2, |_, _| true, operation).await;
+        assert_eq!(result.unwrap_err().0, 503);
+        assert_eq!(attempts.get(), 3);
+
+        // Vetoed retries stop after the first attempt
+        attempts.set(0);
+        let _ = with_retry_limit("test", 2, |_, _| false, operation).await;
+        assert_eq!(attempts.get(), 1);
+
+        // Non-retryable statuses are returned immediately
+        attempts.set(0);
+        let result = with_retry_limit(
+            "test",
+            2,
+            |_, _| true,
+            || {
+                attempts.set(attempts.get() + 1);
+                async { Err::<(), _>((400, "bad request".to_string())) }
+            },
+        )
+        .await;
+        assert_eq!(result.unwrap_err().0, 400);
+        assert_eq!(attempts.get(), 1);
+    }
+
     #[test]
     fn test_is_retryable() {
         assert!(is_retryable(429));
         assert!(is_retryable(500));
         assert!(is_retryable(502));
         assert!(is_retryable(503));
+        assert!(is_retryable(CONNECT_ERROR));
+        assert!(!is_retryable(0));
         assert!(!is_retryable(400));
         assert!(!is_retryable(404));
     }
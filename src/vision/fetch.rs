@@ -0,0 +1,632 @@
+//! Remote media fetching for URL-based image and document sources.
+//!
+//! Anthropic clients may reference images and documents with
+//! `{"type": "url", "url": "..."}` sources. Gemini cannot fetch arbitrary URLs
+//! through the internal API, so the proxy downloads them itself and forwards
+//! the bytes as inline data.
+//!
+//! Because the proxy fetches on behalf of the client, every request is guarded
+//! against SSRF:
+//! - Only `http` and `https` URLs are accepted.
+//! - Hosts are checked against the configured deny and allow lists.
+//! - Resolved addresses in loopback, private, link-local and other reserved
+//!   ranges are rejected, and the connection is pinned to the vetted addresses.
+//! - Redirects are followed manually so every hop passes the same checks.
+//!
+//! Author: kelexine (<https://github.com/kelexine>)
+
+use super::translation::detect_mime_type;
+use crate::config::MediaConfig;
+use crate::error::{ProxyError, Result};
+use crate::models::anthropic::{
+    media_sources_mut, DocumentSource, ImageSource, MediaSourceMut, Message,
+};
+use base64::Engine;
+use futures::StreamExt;
+use lru::LruCache;
+use parking_lot::Mutex;
+use reqwest::Url;
+use std::net::{IpAddr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// Maximum number of redirects followed for a single fetch.
+const MAX_REDIRECTS: usize = 3;
+
+/// A fetched resource ready to be forwarded as inline data.
+#[derive(Debug, Clone)]
+pub struct FetchedMedia {
+    /// Detected (or server-declared) MIME type of the resource.
+    pub mime_type: String,
+    /// Base64-encoded resource bytes.
+    pub data: String,
+    /// When the resource was downloaded, used for cache expiry.
+    fetched_at: Instant,
+}
+
+/// Downloads URL-referenced media with size, time and network restrictions.
+///
+/// Successful fetches are kept in a small LRU cache keyed by URL, so agent
+/// loops that resend the same screenshot on every turn only download it once.
+pub struct MediaFetcher {
+    /// Limits and host policy for outbound fetches.
+    config: MediaConfig,
+    /// Recently fetched resources (`None` when caching is disabled).
+    cache: Option<Mutex<LruCache<String, FetchedMedia>>>,
+}
+
+impl MediaFetcher {
+    /// Creates a fetcher with the given media configuration.
+    pub fn new(config: &MediaConfig) -> Self {
+        let cache = NonZeroUsize::new(config.cache_entries).map(|n| Mutex::new(LruCache::new(n)));
+
+        Self {
+            config: config.clone(),
+            cache,
+        }
+    }
+
+    /// Replaces every URL image and document source in `messages` with inline base64 data,
+    /// including those nested in tool results.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProxyError::InvalidRequest` if any referenced resource cannot be fetched.
+    pub async fn resolve_messages(&self, messages: &mut [Message]) -> Result<()> {
+        for source in media_sources_mut(messages) {
+            match source {
+                MediaSourceMut::Image(source) => {
+                    if let ImageSource::Url { url } = source {
+                        let media = self.fetch(url).await?;
+                        *source = ImageSource::Base64 {
+                            media_type: Some(media.mime_type),
+                            data: media.data,
+                        };
+                    }
+                }
+                MediaSourceMut::Document(source) => {
+                    if let DocumentSource::Url { url } = source {
+                        let media = self.fetch(url).await?;
+                        *source = DocumentSource::Base64 {
+                            media_type: Some(media.mime_type),
+                            data: media.data,
+                        };
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fetches a single URL, serving it from the cache when possible.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProxyError::InvalidRequest` if the URL is rejected by policy, the
+    /// download fails or exceeds the size cap, or the content type is unknown.
+    pub async fn fetch(&self, url: &str) -> Result<FetchedMedia> {
+        if let Some(media) = self.cached(url) {
+            debug!("Serving {} from media cache", url);
+            return Ok(media);
+        }
+
+        let mut current = parse_url(url)?;
+
+        for _ in 0..=MAX_REDIRECTS {
+            let client = self.client_for(&current).await?;
+
+            let response = client
+                .get(current.clone())
+                .send()
+                .await
+                .map_err(|e| fetch_error(url, e))?;
+
+            let status = response.status();
+            if status.is_redirection() {
+                let location = response
+                    .headers()
+                    .get(reqwest::header::LOCATION)
+                    .and_then(|v| v.to_str().ok())
+                    .ok_or_else(|| {
+                        ProxyError::InvalidRequest(format!(
+                            "Failed to fetch {}: redirect without a Location header",
+                            url
+                        ))
+                    })?;
+                current = parse_url(
+                    current
+                        .join(location)
+                        .map_err(|e| fetch_error(url, e))?
+                        .as_str(),
+                )?;
+                debug!("Following redirect to {}", current);
+                continue;
+            }
+
+            if !status.is_success() {
+                return Err(ProxyError::InvalidRequest(format!(
+                    "Failed to fetch {}: HTTP {}",
+                    url, status
+                )));
+            }
+
+            let declared_type = response
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.split(';').next())
+                .map(|v| v.trim().to_lowercase())
+                .filter(|v| !v.is_empty() && v != "application/octet-stream");
+
+            let bytes = self.read_body(url, response).await?;
+
+            // Prefer the sniffed type: servers frequently mislabel images.
+            let mime_type = detect_mime_type(&bytes).or(declared_type).ok_or_else(|| {
+                ProxyError::InvalidRequest(format!(
+                    "Failed to fetch {}: could not determine content type",
+                    url
+                ))
+            })?;
+
+            debug!("Fetched {} ({}, {} bytes)", url, mime_type, bytes.len());
+
+            let media = FetchedMedia {
+                mime_type,
+                data: base64::engine::general_purpose::STANDARD.encode(&bytes),
+                fetched_at: Instant::now(),
+            };
+
+            if let Some(cache) = &self.cache {
+                cache.lock().put(url.to_string(), media.clone());
+            }
+
+            return Ok(media);
+        }
+
+        Err(ProxyError::InvalidRequest(format!(
+            "Failed to fetch {}: too many redirects",
+            url
+        )))
+    }
+
+    /// Returns a cached entry for `url` if it is still within its TTL.
+    fn cached(&self, url: &str) -> Option<FetchedMedia> {
+        let cache = self.cache.as_ref()?;
+        let mut cache = cache.lock();
+        let ttl = Duration::from_secs(self.config.cache_ttl_seconds);
+
+        match cache.get(url) {
+            Some(media) if media.fetched_at.elapsed() < ttl => Some(media.clone()),
+            Some(_) => {
+                cache.pop(url);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Vets the target host and builds a client pinned to the vetted addresses.
+    ///
+    /// Pinning the resolved addresses closes the DNS rebinding window between
+    /// the policy check and the actual connection. Proxies from the environment
+    /// are ignored for the same reason.
+    async fn client_for(&self, url: &Url) -> Result<reqwest::Client> {
+        let host = url
+            .host_str()
+            .ok_or_else(|| ProxyError::InvalidRequest(format!("URL has no host: {}", url)))?;
+        self.check_host_policy(host)?;
+
+        let port = url.port_or_known_default().unwrap_or(443);
+        let literal_ip = host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .ok();
+        let (domain, addrs) = match literal_ip {
+            Some(ip) => (None, vec![SocketAddr::new(ip, port)]),
+            None => {
+                let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+                    .await
+                    .map_err(|e| {
+                        ProxyError::InvalidRequest(format!("Failed to resolve {}: {}", host, e))
+                    })?
+                    .collect();
+                (Some(host), addrs)
+            }
+        };
+
+        if addrs.is_empty() {
+            return Err(ProxyError::InvalidRequest(format!(
+                "Failed to resolve {}: no addresses",
+                host
+            )));
+        }
+
+        if !self.config.allow_private_networks {
+            if let Some(addr) = addrs.iter().find(|a| is_restricted_address(a.ip())) {
+                warn!(
+                    "Blocked media fetch to restricted address {} ({})",
+                    addr, host
+                );
+                return Err(ProxyError::InvalidRequest(format!(
+                    "Fetching from {} is not allowed: private or reserved address",
+                    host
+                )));
+            }
+        }
+
+        let mut builder = reqwest::Client::builder()
+            .timeout(Duration::from_secs(self.config.fetch_timeout_seconds))
+            .connect_timeout(Duration::from_secs(10))
+            .redirect(reqwest::redirect::Policy::none())
+            // A proxy would resolve the host itself, bypassing the vetted addresses
+            .no_proxy()
+            .use_rustls_tls();
+        if let Some(domain) = domain {
+            builder = builder.resolve_to_addrs(domain, &addrs);
+        }
+
+        builder
+            .build()
+            .map_err(|e| ProxyError::Internal(format!("Failed to create HTTP client: {}", e)))
+    }
+
+    /// Applies the configured deny and allow lists to a host name.
+    fn check_host_policy(&self, host: &str) -> Result<()> {
+        if self
+            .config
+            .denied_hosts
+            .iter()
+            .any(|pattern| host_matches(host, pattern))
+        {
+            return Err(ProxyError::InvalidRequest(format!(
+                "Fetching from {} is not allowed: host is denied",
+                host
+            )));
+        }
+
+        if !self.config.allowed_hosts.is_empty()
+            && !self
+                .config
+                .allowed_hosts
+                .iter()
+                .any(|pattern| host_matches(host, pattern))
+        {
+            return Err(ProxyError::InvalidRequest(format!(
+                "Fetching from {} is not allowed: host is not in the allowlist",
+                host
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Reads the response body, aborting as soon as it exceeds the size cap.
+    async fn read_body(&self, url: &str, response: reqwest::Response) -> Result<Vec<u8>> {
+        let max_bytes = self.config.max_fetch_bytes;
+        let too_large = || {
+            ProxyError::InvalidRequest(format!(
+                "Failed to fetch {}: resource exceeds the {} byte limit",
+                url, max_bytes
+            ))
+        };
+
+        if response.content_length().unwrap_or(0) > max_bytes as u64 {
+            return Err(too_large());
+        }
+
+        let mut body = Vec::new();
+        let mut stream = response.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| fetch_error(url, e))?;
+            if body.len() + chunk.len() > max_bytes {
+                return Err(too_large());
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        Ok(body)
+    }
+}
+
+/// Parses a URL and rejects any scheme other than `http` or `https`.
+fn parse_url(url: &str) -> Result<Url> {
+    let parsed = Url::parse(url)
+        .map_err(|e| ProxyError::InvalidRequest(format!("Invalid URL {}: {}", url, e)))?;
+
+    match parsed.scheme() {
+        "http" | "https" => Ok(parsed),
+        scheme => Err(ProxyError::InvalidRequest(format!(
+            "Unsupported URL scheme '{}': only http and https are allowed",
+            scheme
+        ))),
+    }
+}
+
+/// Maps a transport-level failure to a client-facing error.
+fn fetch_error(url: &str, e: impl std::fmt::Display) -> ProxyError {
+    ProxyError::InvalidRequest(format!("Failed to fetch {}: {}", url, e))
+}
+
+/// Matches a host against an exact (`example.com`) or wildcard (`*.example.com`) pattern.
+fn host_matches(host: &str, pattern: &str) -> bool {
+    let host = host.trim_end_matches('.').to_lowercase();
+    let pattern = pattern.trim_end_matches('.').to_lowercase();
+
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => host.ends_with(&format!(".{}", suffix)),
+        None => host == pattern,
+    }
+}
+
+/// Returns `true` for addresses that must never be reached from a client-supplied URL.
+///
+/// Covers loopback, private (RFC 1918 / unique local), link-local, CGNAT,
+/// unspecified, broadcast, multicast, documentation and other reserved ranges.
+/// IPv6 addresses that embed an IPv4 address are judged by that address.
+fn is_restricted_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            v4.is_loopback()
+                || v4.is_private()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_multicast()
+                || v4.is_documentation()
+                || a == 0
+                || a >= 240
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 198 && (b == 18 || b == 19))
+        }
+        IpAddr::V6(v6) => {
+            if let Some(v4) = embedded_ipv4(&v6) {
+                return is_restricted_address(IpAddr::V4(v4));
+            }
+            let first = v6.segments()[0];
+            v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+/// Returns the IPv4 address an IPv6 address routes to: IPv4-mapped
+/// (`::ffff:a.b.c.d`), IPv4-compatible (`::a.b.c.d`), NAT64 (`64:ff9b::/96`,
+/// `64:ff9b:1::/48`) or 6to4 (`2002::/16`).
+fn embedded_ipv4(v6: &std::net::Ipv6Addr) -> Option<std::net::Ipv4Addr> {
+    let segments = v6.segments();
+    let octets = v6.octets();
+    let tail = || std::net::Ipv4Addr::new(octets[12], octets[13], octets[14], octets[15]);
+    match segments {
+        // ::ffff:a.b.c.d and ::a.b.c.d (`::` and `::1` included)
+        [0, 0, 0, 0, 0, 0xffff | 0, ..] => Some(tail()),
+        [0x64, 0xff9b, 0, 0, 0, 0, ..] | [0x64, 0xff9b, 1, ..] => Some(tail()),
+        [0x2002, ..] => Some(std::net::Ipv4Addr::new(
+            octets[2], octets[3], octets[4], octets[5],
+        )),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::anthropic::{ContentBlock, MessageContent};
+
+    fn local_fetcher() -> MediaFetcher {
+        MediaFetcher::new(&MediaConfig {
+            allow_private_networks: true,
+            ..MediaConfig::default()
+        })
+    }
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    #[test]
+    fn test_host_matches() {
+        assert!(host_matches("example.com", "example.com"));
+        assert!(host_matches("Cdn.Example.com", "*.example.com"));
+        assert!(!host_matches("example.com", "*.example.com"));
+        assert!(!host_matches("evilexample.com", "*.example.com"));
+    }
+
+    #[test]
+    fn test_restricted_addresses() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::127.0.0.1",
+            "64:ff9b::7f00:1",
+            "64:ff9b:1::a00:1",
+            "2002:7f00:1::",
+            "2002:a9fe:a9fe::1",
+        ] {
+            assert!(is_restricted_address(ip.parse().unwrap()), "{}", ip);
+        }
+        assert!(!is_restricted_address("8.8.8.8".parse().unwrap()));
+        assert!(!is_restricted_address("2606:4700::1111".parse().unwrap()));
+        assert!(!is_restricted_address("64:ff9b::808:808".parse().unwrap()));
+        assert!(!is_restricted_address("2002:808:808::".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_ignores_environment_proxy() {
+        let mut proxy = mockito::Server::new_async().await;
+        let proxied = proxy
+            .mock("GET", mockito::Matcher::Any)
+            .expect(0)
+            .create_async()
+            .await;
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/cat")
+            .with_body(PNG)
+            .create_async()
+            .await;
+        let url = parse_url(&format!("{}/cat", server.url())).unwrap();
+
+        // Proxies are read when the client is built; keep the variable set
+        // only that long so other tests are not routed through it
+        std::env::set_var("HTTP_PROXY", proxy.url());
+        let client = local_fetcher().client_for(&url).await;
+        std::env::remove_var("HTTP_PROXY");
+
+        let body = client.unwrap().get(url).send().await.unwrap().bytes().await;
+        assert_eq!(&body.unwrap()[..], PNG);
+        proxied.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_rejects_private_and_non_http_urls() {
+        let fetcher = MediaFetcher::new(&MediaConfig::default());
+
+        assert!(fetcher.fetch("file:///etc/passwd").await.is_err());
+        assert!(fetcher.fetch("http://127.0.0.1/image.png").await.is_err());
+        assert!(fetcher.fetch("http://[::1]/image.png").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_sniffs_type_and_caches() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/cat")
+            .with_header("content-type", "application/octet-stream")
+            .with_body(PNG)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let fetcher = local_fetcher();
+        let url = format!("{}/cat", server.url());
+
+        let first = fetcher.fetch(&url).await.unwrap();
+        let second = fetcher.fetch(&url).await.unwrap();
+
+        assert_eq!(first.mime_type, "image/png");
+        assert_eq!(second.data, first.data);
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_fetch_enforces_size_cap() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/big")
+            .with_body(vec![0u8; 4096])
+            .create_async()
+            .await;
+
+        let fetcher = MediaFetcher::new(&MediaConfig {
+            allow_private_networks: true,
+            max_fetch_bytes: 1024,
+            ..MediaConfig::default()
+        });
+
+        let result = fetcher.fetch(&format!("{}/big", server.url())).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_messages_inlines_url_images() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/cat.png")
+            .with_body(PNG)
+            .create_async()
+            .await;
+
+        let mut messages = vec![Message {
+            role: "user".to_string(),
+            content: MessageContent::Blocks(vec![ContentBlock::Image {
+                source: ImageSource::Url {
+                    url: format!("{}/cat.png", server.url()),
+                },
+                cache_control: None,
+            }]),
+        }];
+
+        local_fetcher()
+            .resolve_messages(&mut messages)
+            .await
+            .unwrap();
+
+        let MessageContent::Blocks(blocks) = &messages[0].content else {
+            panic!("Expected block content");
+        };
+        match &blocks[0] {
+            ContentBlock::Image {
+                source: ImageSource::Base64 { media_type, .. },
+                ..
+            } => assert_eq!(media_type.as_deref(), Some("image/png")),
+            other => panic!("Expected resolved base64 image, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tool_result_images_reach_gemini() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/screenshot.png")
+            .with_body(PNG)
+            .create_async()
+            .await;
+        let req: crate::models::anthropic::MessagesRequest =
+            serde_json::from_value(serde_json::json!({
+                "model": "claude-sonnet-4",
+                "max_tokens": 64,
+                "messages": [
+                    {"role": "assistant", "content": [
+                        {"type": "tool_use", "id": "toolu_1", "name": "screenshot", "input": {}}
+                    ]},
+                    {"role": "user", "content": [
+                        {"type": "tool_result", "tool_use_id": "toolu_1", "content": [
+                            {"type": "text", "text": "Captured"},
+                            {"type": "image", "source": {
+                                "type": "url",
+                                "url": format!("{}/screenshot.png", server.url()),
+                            }},
+                        ]}
+                    ]},
+                ],
+            }))
+            .unwrap();
+        let registry = crate::models::ModelRegistry::new(&[]).unwrap();
+
+        let translated = crate::translation::translate_request(
+            req,
+            "gemini-2.5-pro",
+            Some(&local_fetcher()),
+            None,
+            &registry,
+        )
+        .await
+        .unwrap();
+
+        // The image follows the function response it belongs to
+        let parts = &translated.contents[1].parts;
+        assert_eq!(parts.len(), 2);
+        assert!(matches!(
+            &parts[0],
+            crate::models::gemini::Part::FunctionResponse { .. }
+        ));
+        let crate::models::gemini::Part::InlineData { inline_data } = &parts[1] else {
+            panic!("Expected inline data, got {:?}", parts[1]);
+        };
+        assert_eq!(inline_data.mime_type, "image/png");
+    }
+}
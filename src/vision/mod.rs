@@ -1,17 +1,21 @@
 //! Vision and image processing module for image-to-text bridge.
 //!
-//! This module handles the translation of image content blocks from Anthropic's
-//! format to Gemini's `InlineData` format. It includes MIME type detection,
-//! image validation, and format conversion logic.
+//! This module handles the translation of image and document content blocks
+//! from Anthropic's format to Gemini's `InlineData` format. It includes MIME type
+//! detection, image validation, format conversion logic and fetching of
+//! URL-referenced media.
 //!
 //! # Submodules
 //!
+//! - `fetch`: Size-capped, SSRF-guarded downloading of URL image and document sources.
 //! - `models`: Data structures and validation constraints for image data.
-//! - `translation`: Logic for converting image blocks between API formats.
+//! - `translation`: Logic for converting image and document blocks between API formats.
 //!
 //! Author: kelexine (<https://github.com/kelexine>)
 
+pub mod fetch;
 pub mod models;
 pub mod translation;
 
-pub use translation::translate_image_block;
+pub use fetch::MediaFetcher;
+pub use translation::{translate_document_block, translate_image_block};
@@ -82,3 +82,27 @@ pub fn validate_image_size(data_len: usize) -> Result<(), String> {
     }
     Ok(())
 }
+
+/// Returns `true` if a document MIME type can be forwarded to Gemini as inline data.
+///
+/// PDFs and plain-text variants (`text/plain`, `text/markdown`, `text/csv`, ...)
+/// are accepted.
+pub fn is_supported_document_type(mime: &str) -> bool {
+    let mime = mime.to_lowercase();
+    mime == "application/pdf" || mime.starts_with("text/")
+}
+
+/// Validates that the provided document data length does not exceed 20MB.
+///
+/// # Errors
+///
+/// Returns an `Err` containing a descriptive message if the size is exceeded.
+pub fn validate_document_size(data_len: usize) -> Result<(), String> {
+    if data_len > MAX_IMAGE_SIZE_BYTES {
+        return Err(format!(
+            "Document size ({} bytes) exceeds the Google Gemini inline data maximum of 20MB ({} bytes).",
+            data_len, MAX_IMAGE_SIZE_BYTES
+        ));
+    }
+    Ok(())
+}
@@ -5,10 +5,12 @@
 //!
 //! Author: kelexine (<https://github.com/kelexine>)
 
-use super::models::{validate_image_size, ImageFormat};
+use super::models::{
+    is_supported_document_type, validate_document_size, validate_image_size, ImageFormat,
+};
 use crate::error::{ProxyError, Result};
-use crate::models::anthropic::{ContentBlock, ImageSource};
-use crate::models::gemini::InlineData;
+use crate::models::anthropic::{ContentBlock, DocumentSource, ImageSource};
+use crate::models::gemini::{InlineData, Part as GeminiPart};
 use base64::Engine;
 
 /// Translates a Claude-formatted image content block into Gemini's `InlineData` format.
@@ -34,11 +36,27 @@ use base64::Engine;
 /// * The base64 data is malformed.
 /// * The image format is unsupported or cannot be detected.
 /// * The image exceeds size limits.
+/// * The image still references a URL (URL sources are resolved by
+///   [`MediaFetcher`](super::fetch::MediaFetcher) before translation).
+/// * The image still references an uploaded file (resolved by
+///   [`FileStore`](crate::files::FileStore) before translation).
 pub fn translate_image_block(block: &ContentBlock) -> Result<InlineData> {
     // Extract Image variant details
     let (media_type_opt, data) = match block {
         ContentBlock::Image { source, .. } => match source {
             ImageSource::Base64 { media_type, data } => (media_type.clone(), data.clone()),
+            ImageSource::Url { url } => {
+                return Err(ProxyError::InvalidRequest(format!(
+                    "URL image sources are disabled on this proxy (media.url_sources_enabled): {}",
+                    url
+                )));
+            }
+            ImageSource::File { file_id } => {
+                return Err(ProxyError::InvalidRequest(format!(
+                    "File sources are disabled on this proxy (files.enabled): {}",
+                    file_id
+                )));
+            }
         },
         _ => {
             return Err(ProxyError::InvalidRequest(
@@ -77,13 +95,83 @@ pub fn translate_image_block(block: &ContentBlock) -> Result<InlineData> {
     })
 }
 
-/// Detects the MIME type of an image by analyzing its initial "magic bytes".
+/// Translates a Claude-formatted document content block into a Gemini part.
+///
+/// Base64 documents (PDFs and text files) become `InlineData` parts, while
+/// plain-text sources are forwarded as a regular text part. URL sources must
+/// already have been resolved by [`MediaFetcher`](super::fetch::MediaFetcher).
+///
+/// # Errors
+///
+/// Returns a `ProxyError::InvalidRequest` if the block is not a document block,
+/// the data is malformed, the format is unsupported or the size limit is exceeded.
+pub fn translate_document_block(block: &ContentBlock) -> Result<GeminiPart> {
+    let source = match block {
+        ContentBlock::Document { source, .. } => source,
+        _ => {
+            return Err(ProxyError::InvalidRequest(
+                "Expected Document content block for document processing".to_string(),
+            ));
+        }
+    };
+
+    match source {
+        DocumentSource::Text { data, .. } => Ok(GeminiPart::Text {
+            text: data.clone(),
+            thought: None,
+            thought_signature: None,
+        }),
+        DocumentSource::Base64 { media_type, data } => {
+            let decoded = base64::engine::general_purpose::STANDARD
+                .decode(data)
+                .map_err(|e| {
+                    ProxyError::InvalidRequest(format!("Invalid base64 document data: {}", e))
+                })?;
+
+            let media_type = match media_type {
+                Some(mt) => mt.clone(),
+                None => detect_mime_type(&decoded).ok_or_else(|| {
+                    ProxyError::InvalidRequest(
+                        "Could not detect document format from data. Please provide a media_type."
+                            .to_string(),
+                    )
+                })?,
+            };
+
+            if !is_supported_document_type(&media_type) {
+                return Err(ProxyError::InvalidRequest(format!(
+                    "Unsupported document format: {}",
+                    media_type
+                )));
+            }
+
+            validate_document_size(decoded.len()).map_err(ProxyError::InvalidRequest)?;
+
+            Ok(GeminiPart::InlineData {
+                inline_data: InlineData {
+                    mime_type: media_type,
+                    data: data.clone(),
+                },
+            })
+        }
+        DocumentSource::Url { url } => Err(ProxyError::InvalidRequest(format!(
+            "URL document sources are disabled on this proxy (media.url_sources_enabled): {}",
+            url
+        ))),
+        DocumentSource::File { file_id } => Err(ProxyError::InvalidRequest(format!(
+            "File sources are disabled on this proxy (files.enabled): {}",
+            file_id
+        ))),
+    }
+}
+
+/// Detects the MIME type of an image or document by analyzing its initial "magic bytes".
 ///
 /// This is a lightweight implementation that covers the most common web image formats
-/// supported by LLMs today.
+/// supported by LLMs today, plus PDF documents.
 ///
-/// Supported formats: JPEG, PNG, GIF, WebP, HEIC.
-fn detect_mime_type(data: &[u8]) -> Option<String> {
+/// Supported formats: JPEG, PNG, GIF, WebP, HEIC, PDF.
+pub fn detect_mime_type(data: &[u8]) -> Option<String> {
     if data.len() < 12 {
         return None;
     }
@@ -104,6 +192,9 @@ fn detect_mime_type(data: &[u8]) -> Option<String> {
     } else if data[4..12] == *b"ftypheic" || data[4..12] == *b"ftypheix" {
         // HEIC brand in ISO Base Media File Format (BMFF)
         Some("image/heic".to_string())
+    } else if data.starts_with(b"%PDF-") {
+        // PDF documents start with a %PDF-<version> header
+        Some("application/pdf".to_string())
     } else {
         None
     }
@@ -181,4 +272,58 @@ mod tests {
         let result = translate_image_block(&image);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_unresolved_url_image() {
+        let image = ContentBlock::Image {
+            source: ImageSource::Url {
+                url: "https://example.com/cat.png".to_string(),
+            },
+            cache_control: None,
+        };
+
+        let result = translate_image_block(&image);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_translate_pdf_document() {
+        let pdf_data = base64::engine::general_purpose::STANDARD.encode(b"%PDF-1.4\n%fake pdf");
+
+        let document = ContentBlock::Document {
+            source: DocumentSource::Base64 {
+                media_type: None,
+                data: pdf_data.clone(),
+            },
+            title: None,
+            context: None,
+            cache_control: None,
+        };
+
+        match translate_document_block(&document).unwrap() {
+            GeminiPart::InlineData { inline_data } => {
+                assert_eq!(inline_data.mime_type, "application/pdf");
+                assert_eq!(inline_data.data, pdf_data);
+            }
+            other => panic!("Expected InlineData part, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_translate_text_document() {
+        let document = ContentBlock::Document {
+            source: DocumentSource::Text {
+                media_type: Some("text/plain".to_string()),
+                data: "The grass is green.".to_string(),
+            },
+            title: Some("Facts".to_string()),
+            context: None,
+            cache_control: None,
+        };
+
+        match translate_document_block(&document).unwrap() {
+            GeminiPart::Text { text, .. } => assert_eq!(text, "The grass is green."),
+            other => panic!("Expected Text part, got {:?}", other),
+        }
+    }
 }